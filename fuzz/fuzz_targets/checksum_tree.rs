@@ -0,0 +1,38 @@
+//! Check that adding arbitrary files to a `ChecksumTree` never panics and
+//! that, for the files it accepts, it agrees with `SortedChecksumBuilder`.
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use zarr_checksum_gallery::checksum::{
+    Checksum, ChecksumTree, FileChecksum, Md5Digest, SortedChecksumBuilder,
+};
+use zarr_checksum_gallery::zarr::EntryPath;
+
+#[derive(Arbitrary, Debug)]
+struct File<'a> {
+    path: &'a str,
+    digest: [u8; 16],
+    // Sizes are limited to 32 bits so that the total size of a tree cannot
+    // overflow a u64, which no real directory will do
+    size: u32,
+}
+
+fuzz_target!(|files: Vec<File<'_>>| {
+    let mut tree = ChecksumTree::new();
+    let mut added = Vec::new();
+    for f in files {
+        let Ok(relpath) = EntryPath::try_from(f.path) else {
+            continue;
+        };
+        let node = FileChecksum::new(relpath, Md5Digest::from(f.digest), u64::from(f.size));
+        if tree.add_file(node.clone()).is_ok() {
+            added.push(node);
+        }
+    }
+    let checksum = tree.checksum();
+    added.sort_by(|a, b| a.relpath().cmp(b.relpath()));
+    let sorted = SortedChecksumBuilder::from_files(added)
+        .expect("files accepted by ChecksumTree should be accepted in sorted order");
+    assert_eq!(sorted.into_checksum(), checksum);
+    assert_eq!(tree.into_checksum(), checksum);
+});
@@ -0,0 +1,27 @@
+//! Check that any string accepted by `EntryPath::try_from()` is normalized
+//! such that parsing its `Display` output yields the same path again.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zarr_checksum_gallery::zarr::EntryPath;
+
+fuzz_target!(|s: &str| {
+    let Ok(path) = EntryPath::try_from(s) else {
+        return;
+    };
+    let shown = path.to_string();
+    let reparsed = EntryPath::try_from(shown.as_str())
+        .unwrap_or_else(|e| panic!("Display output {shown:?} of {s:?} failed to parse: {e}"));
+    assert_eq!(reparsed, path);
+    assert_eq!(reparsed.to_string(), shown);
+    // Every component must also be usable on its own
+    for component in shown.split('/') {
+        assert_eq!(
+            EntryPath::try_from(component).map(|p| p.to_string()).ok(),
+            Some(component.to_owned())
+        );
+    }
+    assert_eq!(
+        path.file_name(),
+        shown.rsplit('/').next().expect("split should be nonempty")
+    );
+});
@@ -0,0 +1,13 @@
+//! Check that the JSON serialization of entry names is pure ASCII and parses
+//! back to the original string with a real JSON parser.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use zarr_checksum_gallery::fuzzing::json_str;
+
+fuzz_target!(|s: &str| {
+    let json = json_str(s);
+    assert!(json.is_ascii(), "{json:?} is not ASCII");
+    let parsed = serde_json::from_str::<String>(&json)
+        .unwrap_or_else(|e| panic!("{json:?} is not a valid JSON string: {e}"));
+    assert_eq!(parsed, s);
+});
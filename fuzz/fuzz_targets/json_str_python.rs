@@ -0,0 +1,53 @@
+//! Differential test of the JSON serialization of entry names against a
+//! transcription of `py_encode_basestring_ascii()` from CPython's
+//! `Lib/json/encoder.py`, which is what `json.dumps()` (and thus the reference
+//! Python implementation of the Zarr checksum) uses for strings with the
+//! default `ensure_ascii=True`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::fmt::Write;
+use zarr_checksum_gallery::fuzzing::json_str;
+
+/// `ESCAPE_DCT` from `json.encoder`, restricted to the characters that are
+/// not escaped as `\uXXXX`
+fn escape_dct(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some("\\\\"),
+        '"' => Some("\\\""),
+        '\x08' => Some("\\b"),
+        '\x0c' => Some("\\f"),
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        '\t' => Some("\\t"),
+        _ => None,
+    }
+}
+
+fn py_encode_basestring_ascii(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        // ESCAPE_ASCII = re.compile(r'([\\"]|[^\ -~])')
+        if c != '\\' && c != '"' && (' '..='~').contains(&c) {
+            out.push(c);
+        } else if let Some(esc) = escape_dct(c) {
+            out.push_str(esc);
+        } else {
+            let n = u32::from(c);
+            if n < 0x10000 {
+                write!(out, "\\u{n:04x}").expect("formatting a String should not fail");
+            } else {
+                // surrogate pair
+                let n = n - 0x10000;
+                let s1 = 0xd800 | ((n >> 10) & 0x3ff);
+                let s2 = 0xdc00 | (n & 0x3ff);
+                write!(out, "\\u{s1:04x}\\u{s2:04x}").expect("formatting a String should not fail");
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+fuzz_target!(|s: &str| {
+    assert_eq!(json_str(s), py_encode_basestring_ascii(s));
+});
@@ -0,0 +1,452 @@
+//! Benchmarking of checksumming implementations
+//!
+//! A [`Benchmark`] runs external commands (normally the
+//! `zarr-checksum-gallery` binary itself, with one implementation per
+//! [`Target`]) repeatedly over a directory tree, measuring the wall-clock time
+//! and peak resident set size of each run, and summarizes the results in a
+//! [`Report`] that can be serialized as JSON for comparison across commits.
+//!
+//! Runs can be made with a warm page cache or a cold one.  For the latter,
+//! the contents of every file in the tree are dropped from the page cache
+//! with `posix_fadvise(POSIX_FADV_DONTNEED)` before each run, which requires
+//! no special privileges but only works on Linux and cannot drop cached
+//! directory entries, inodes, or dirty pages.
+use crate::errors::{BenchError, FSError};
+use crate::progress::{scan, NoProgress};
+use crate::zarr::{Zarr, ZarrEntry};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// The state of the page cache at the start of each measured run
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheState {
+    /// File contents are left in the page cache by the warmup runs and
+    /// previous runs
+    Warm,
+    /// File contents are dropped from the page cache before every run
+    Cold,
+}
+
+impl CacheState {
+    /// Returns `true` if cold runs are supported on this platform
+    pub fn cold_supported() -> bool {
+        cfg!(target_os = "linux")
+    }
+}
+
+/// A command to benchmark
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Target {
+    /// The name under which to report the command's results
+    pub name: String,
+    /// The program to run
+    pub program: PathBuf,
+    /// The arguments to pass to the program.  The program must print the
+    /// checksum for the tree being benchmarked on standard output.
+    pub args: Vec<OsString>,
+}
+
+/// Configuration for a benchmarking session
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    /// The tree that the targets operate on.  This is scanned to determine
+    /// the number of files & bytes for computing throughput, and its files
+    /// are evicted from the page cache before cold runs.
+    pub zarr: Zarr,
+    /// The number of unmeasured runs of each target to perform before the
+    /// measured runs
+    pub warmups: usize,
+    /// The number of measured runs of each target
+    pub runs: usize,
+}
+
+impl Benchmark {
+    /// Start a report for this benchmark by scanning the tree
+    pub fn start_report(&self) -> Result<Report, BenchError> {
+        let totals = scan(&self.zarr)?;
+        Ok(Report {
+            version: env!("CARGO_PKG_VERSION"),
+            dirpath: self.zarr.root_dir().path().to_string_lossy().into_owned(),
+            files: totals.files,
+            bytes: totals.bytes,
+            warmups: self.warmups,
+            runs: self.runs,
+            results: Vec::new(),
+        })
+    }
+
+    /// Run `target` under the given cache state, returning a summary of the
+    /// measured runs.  `files` & `bytes` are the totals for the tree, as
+    /// found in the [`Report`].
+    ///
+    /// Fails if the target cannot be run, if it exits unsuccessfully, or if it
+    /// prints different checksums on different runs.
+    pub fn run_target(
+        &self,
+        target: &Target,
+        cache: CacheState,
+        files: u64,
+        bytes: u64,
+    ) -> Result<BenchResult, BenchError> {
+        let mut checksum: Option<String> = None;
+        let mut times = Vec::with_capacity(self.runs);
+        let mut peak_rss = None;
+        for i in 0..(self.warmups + self.runs) {
+            if cache == CacheState::Cold {
+                evict(&self.zarr).map_err(BenchError::Evict)?;
+            }
+            log::debug!("Running {} ({i})", target.name);
+            let m = run_measured(Command::new(&target.program).args(&target.args)).map_err(
+                |source| BenchError::Spawn {
+                    name: target.name.clone(),
+                    source,
+                },
+            )?;
+            if !m.status.success() {
+                return Err(BenchError::Failed {
+                    name: target.name.clone(),
+                    status: m.status,
+                });
+            }
+            let output = String::from_utf8_lossy(&m.stdout).trim().to_owned();
+            match checksum {
+                Some(ref expected) if *expected != output => {
+                    return Err(BenchError::Inconsistent {
+                        name: target.name.clone(),
+                        expected: expected.clone(),
+                        got: output,
+                    });
+                }
+                Some(_) => (),
+                None => checksum = Some(output),
+            }
+            if i >= self.warmups {
+                times.push(m.wall_time);
+                peak_rss = peak_rss.max(m.peak_rss);
+            }
+        }
+        let stats = Stats::from_times(&times);
+        Ok(BenchResult {
+            name: target.name.clone(),
+            args: target
+                .args
+                .iter()
+                .map(|s| s.to_string_lossy().into_owned())
+                .collect(),
+            cache,
+            checksum: checksum.unwrap_or_default(),
+            times: times.iter().map(Duration::as_secs_f64).collect(),
+            files_per_sec: as_f64(files) / stats.mean,
+            mb_per_sec: as_f64(bytes) / 1_000_000.0 / stats.mean,
+            stats,
+            peak_rss,
+        })
+    }
+}
+
+/// The results of a benchmarking session
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    /// The version of `zarr-checksum-gallery` that produced the report
+    pub version: &'static str,
+    /// The path to the tree that was benchmarked.  Non-UTF-8 paths are
+    /// converted lossily.
+    pub dirpath: String,
+    /// The number of files in the tree
+    pub files: u64,
+    /// The total size in bytes of the files in the tree
+    pub bytes: u64,
+    /// The number of warmup runs performed for each target
+    pub warmups: usize,
+    /// The number of measured runs performed for each target
+    pub runs: usize,
+    /// The results for each target & cache state
+    pub results: Vec<BenchResult>,
+}
+
+/// The results of benchmarking a single [`Target`] under a single
+/// [`CacheState`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BenchResult {
+    /// The name of the target
+    pub name: String,
+    /// The arguments passed to the target
+    pub args: Vec<String>,
+    /// The state of the page cache at the start of each run
+    pub cache: CacheState,
+    /// The checksum printed by the target
+    pub checksum: String,
+    /// The wall-clock time of each measured run, in seconds
+    pub times: Vec<f64>,
+    /// Summary statistics of `times`
+    #[serde(flatten)]
+    pub stats: Stats,
+    /// The mean number of files checksummed per second
+    pub files_per_sec: f64,
+    /// The mean number of megabytes (10⁶ bytes) checksummed per second
+    pub mb_per_sec: f64,
+    /// The largest peak resident set size of any measured run, in bytes, or
+    /// `None` if this could not be determined on this platform
+    pub peak_rss: Option<u64>,
+}
+
+/// Summary statistics for a series of times, in seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub mean: f64,
+    /// The sample standard deviation, or zero if there is only one sample
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    /// Compute statistics for the given times.  If `times` is empty, all
+    /// fields are zero.
+    pub fn from_times(times: &[Duration]) -> Stats {
+        let secs = times.iter().map(Duration::as_secs_f64).collect::<Vec<_>>();
+        let Some(&first) = secs.first() else {
+            return Stats::default();
+        };
+        let n = as_f64(secs.len() as u64);
+        let mean = secs.iter().sum::<f64>() / n;
+        let stddev = if secs.len() > 1 {
+            (secs.iter().map(|&t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        let (min, max) = secs
+            .iter()
+            .fold((first, first), |(lo, hi), &t| (lo.min(t), hi.max(t)));
+        Stats {
+            mean,
+            stddev,
+            min,
+            max,
+        }
+    }
+}
+
+/// The result of running a command with [`run_measured()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Measurement {
+    /// The command's exit status
+    pub status: ExitStatus,
+    /// The command's standard output
+    pub stdout: Vec<u8>,
+    /// The time from spawning the command to its exit
+    pub wall_time: Duration,
+    /// The command's peak resident set size in bytes, or `None` if this could
+    /// not be determined on this platform
+    pub peak_rss: Option<u64>,
+}
+
+/// Run `cmd` to completion, capturing its standard output and measuring its
+/// wall-clock time and (on Linux) peak resident set size
+pub fn run_measured(cmd: &mut Command) -> io::Result<Measurement> {
+    cmd.stdout(Stdio::piped());
+    let start = Instant::now();
+    let mut child = cmd.spawn()?;
+    let mut stdout = Vec::new();
+    if let Some(mut out) = child.stdout.take() {
+        if let Err(e) = out.read_to_end(&mut stdout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    }
+    let (status, peak_rss) = wait_with_rusage(child)?;
+    Ok(Measurement {
+        status,
+        stdout,
+        wall_time: start.elapsed(),
+        peak_rss,
+    })
+}
+
+/// Wait for `child` to exit and return its exit status and peak resident set
+/// size in bytes
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+fn wait_with_rusage(child: Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+    let pid = libc::pid_t::try_from(child.id()).map_err(io::Error::other)?;
+    let mut status = 0;
+    // SAFETY: `rusage` is a plain C struct for which all-zero bytes is a
+    // valid value.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: `pid` is a child of this process that has not yet been
+        // reaped (`Child` only reaps in `wait()` & `try_wait()`, which are
+        // never called on it), and `status` & `usage` are valid for writes.
+        let r = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if r == pid {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    // `ru_maxrss` is in KiB on Linux.
+    let peak_rss = u64::try_from(usage.ru_maxrss)
+        .ok()
+        .map(|kib| kib.saturating_mul(1024));
+    Ok((ExitStatus::from_raw(status), peak_rss))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_with_rusage(mut child: Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    Ok((child.wait()?, None))
+}
+
+/// Drop the contents of every file in `zarr` from the page cache.
+///
+/// On platforms other than Linux, this fails with an
+/// [`Unsupported`][io::ErrorKind::Unsupported] I/O error.
+pub fn evict(zarr: &Zarr) -> Result<(), FSError> {
+    let zarr = zarr.clone().progress(NoProgress);
+    let mut stack = vec![zarr.root_dir()];
+    while let Some(zd) = stack.pop() {
+        for entry in zd.iter_entries()? {
+            match entry? {
+                ZarrEntry::File(zf) => evict_file(zf.path())?,
+                ZarrEntry::Directory(sub) => stack.push(sub),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+fn evict_file(path: &std::path::Path) -> Result<(), FSError> {
+    use std::os::fd::AsRawFd;
+    let fp = fs_err::File::open(path)?;
+    // SAFETY: `fp` is a valid open file descriptor for the duration of the
+    // call, and `posix_fadvise()` does not touch any of our memory.
+    let r = unsafe { libc::posix_fadvise(fp.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if r == 0 {
+        Ok(())
+    } else {
+        Err(FSError::Io(io::Error::new(
+            io::Error::from_raw_os_error(r).kind(),
+            format!(
+                "posix_fadvise() failed for {}: {}",
+                path.display(),
+                io::Error::from_raw_os_error(r)
+            ),
+        )))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn evict_file(path: &std::path::Path) -> Result<(), FSError> {
+    Err(FSError::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "cannot drop {} from the page cache on this platform",
+            path.display()
+        ),
+    )))
+}
+
+/// Convert a count to a float.  Counts large enough to lose precision are
+/// not a concern here.
+#[allow(clippy::cast_precision_loss)]
+fn as_f64(n: u64) -> f64 {
+    n as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_stats() {
+        let times = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].map(Duration::from_secs_f64);
+        let stats = Stats::from_times(&times);
+        assert_close(stats.mean, 5.0);
+        assert_close(stats.stddev, (32.0f64 / 7.0).sqrt());
+        assert_close(stats.min, 2.0);
+        assert_close(stats.max, 9.0);
+    }
+
+    #[test]
+    fn test_stats_one() {
+        let stats = Stats::from_times(&[Duration::from_millis(1500)]);
+        assert_close(stats.mean, 1.5);
+        assert_close(stats.stddev, 0.0);
+        assert_close(stats.min, 1.5);
+        assert_close(stats.max, 1.5);
+    }
+
+    #[test]
+    fn test_stats_empty() {
+        assert_eq!(Stats::from_times(&[]), Stats::default());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_measured() {
+        let m = run_measured(Command::new("sh").args(["-c", "echo hello; exit 3"])).unwrap();
+        assert_eq!(m.stdout, b"hello\n");
+        assert_eq!(m.status.code(), Some(3));
+        if cfg!(target_os = "linux") {
+            assert!(m.peak_rss.is_some_and(|rss| rss > 0));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_evict() {
+        let zarr = Zarr::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample.zarr"
+        ));
+        evict(&zarr).unwrap();
+    }
+
+    #[test]
+    fn test_serialize_result() {
+        let result = BenchResult {
+            name: String::from("fastio"),
+            args: vec![String::from("fastio"), String::from("dir")],
+            cache: CacheState::Cold,
+            checksum: String::from("4313ab36412db2981c3ed391b38604d6-5--1516"),
+            times: vec![0.5, 1.5],
+            stats: Stats::from_times(&[Duration::from_millis(500), Duration::from_millis(1500)]),
+            files_per_sec: 5.0,
+            mb_per_sec: 0.001516,
+            peak_rss: None,
+        };
+        let stddev = result.stats.stddev;
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "name": "fastio",
+                "args": ["fastio", "dir"],
+                "cache": "cold",
+                "checksum": "4313ab36412db2981c3ed391b38604d6-5--1516",
+                "times": [0.5, 1.5],
+                "mean": 1.0,
+                "stddev": stddev,
+                "min": 0.5,
+                "max": 1.5,
+                "files_per_sec": 5.0,
+                "mb_per_sec": 0.001516,
+                "peak_rss": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Cooperative cancellation of Zarr traversals
+//!
+//! A [`CancelToken`] and/or a deadline can be attached to a
+//! [`Zarr`][crate::zarr::Zarr] with
+//! [`Zarr::cancel_token()`][crate::zarr::Zarr::cancel_token] and
+//! [`Zarr::deadline()`][crate::zarr::Zarr::deadline].  Once the token is
+//! cancelled or the deadline passes, all traversals of the Zarr stop listing
+//! directories & reading files, shut down their workers, and return
+//! [`ChecksumError::Cancelled`][crate::errors::ChecksumError::Cancelled].
+use crate::errors::FSError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often workers that are waiting for jobs check whether a traversal with
+/// a [`CancelToken`] has been cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A handle for cancelling traversals from another thread or task.  Clones of
+/// a token share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a new, uncancelled token
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel all traversals using this token
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if [`cancel()`][CancelToken::cancel] has been called
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// The cancellation conditions for a traversal
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Cancellation {
+    pub(crate) token: Option<CancelToken>,
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.as_ref().is_some_and(CancelToken::is_cancelled)
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Return how long a worker waiting for jobs may sleep before it must
+    /// check for cancellation again, or `None` if the traversal cannot be
+    /// cancelled
+    pub(crate) fn wait_interval(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|d| d.saturating_duration_since(Instant::now()));
+        match (&self.token, remaining) {
+            (Some(_), Some(r)) => Some(r.min(POLL_INTERVAL)),
+            (Some(_), None) => Some(POLL_INTERVAL),
+            (None, r) => r,
+        }
+    }
+
+    /// Return `Err(FSError::Cancelled)` if the traversal has been cancelled
+    pub(crate) fn check(&self) -> Result<(), FSError> {
+        if self.is_cancelled() {
+            Err(FSError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
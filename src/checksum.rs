@@ -8,13 +8,18 @@
 //! computed with
 //! [`ZarrDirectory::get_checksum()`][crate::zarr::ZarrDirectory::get_checksum].
 //! The checksum for an entire Zarr can then be computed by building up these
-//! types, by building up a [`ChecksumTree`] from [`FileChecksum`]s, or by
-//! using just [`compile_checksum()`] or [`try_compile_checksum()`].
-mod json;
+//! types, by building up a [`ChecksumTree`] from [`FileChecksum`]s, by
+//! feeding [`FileChecksum`]s in sorted order to a [`SortedChecksumBuilder`],
+//! or by using just [`compile_checksum()`] or [`try_compile_checksum()`].
+mod digest;
+pub(crate) mod json;
 pub(crate) mod nodes;
+mod sorted;
 mod tree;
 use crate::errors::{ChecksumError, ChecksumTreeError, FSError};
+pub use digest::*;
 pub use nodes::*;
+pub use sorted::*;
 pub use tree::*;
 
 /// Compute a checksum for a Zarr from an iterator of [`FileChecksum`]s for
@@ -0,0 +1,77 @@
+use crate::errors::DigestParseError;
+use std::fmt;
+use std::str::FromStr;
+
+/// A raw MD5 digest.  It is displayed & parsed as 32 lowercase hexadecimal
+/// digits.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Md5Digest([u8; 16]);
+
+impl Md5Digest {
+    /// Return the raw bytes of the digest
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl From<[u8; 16]> for Md5Digest {
+    fn from(bytes: [u8; 16]) -> Md5Digest {
+        Md5Digest(bytes)
+    }
+}
+
+impl fmt::Display for Md5Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0u8; 32];
+        hex::encode_to_slice(self.0, &mut buf).map_err(|_| fmt::Error)?;
+        f.write_str(std::str::from_utf8(&buf).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl fmt::Debug for Md5Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+impl FromStr for Md5Digest {
+    type Err = DigestParseError;
+
+    fn from_str(s: &str) -> Result<Md5Digest, DigestParseError> {
+        let mut bytes = [0u8; 16];
+        // `hex` accepts uppercase digits, but the Zarr checksum format only
+        // uses lowercase.
+        if s.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err(DigestParseError(s.into()));
+        }
+        hex::decode_to_slice(s, &mut bytes).map_err(|_| DigestParseError(s.into()))?;
+        Ok(Md5Digest(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_display_roundtrip() {
+        let s = "0123456789abcdef0123456789abcdef";
+        let digest = s.parse::<Md5Digest>().unwrap();
+        assert_eq!(digest.to_string(), s);
+        assert_eq!(format!("{digest:?}"), format!("\"{s}\""));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("0123456789abcdef")]
+    #[case("0123456789abcdef0123456789abcdef0")]
+    #[case("0123456789ABCDEF0123456789ABCDEF")]
+    #[case("0123456789abcdef0123456789abcdeg")]
+    fn test_parse_err(#[case] s: &str) {
+        assert_eq!(
+            s.parse::<Md5Digest>(),
+            Err(DigestParseError(String::from(s)))
+        );
+    }
+}
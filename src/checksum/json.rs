@@ -1,10 +1,44 @@
+use super::digest::Md5Digest;
 use super::nodes::*;
+use crate::util::Md5Writer;
 use std::fmt::{Error, Write};
 
+/// Compute the MD5 digest of the canonical JSON listing of a directory's
+/// entries.  The JSON is streamed directly into the hasher rather than being
+/// built up in memory first.
+pub(super) fn get_checksum_digest<'a, FI, DI>(files: FI, directories: DI) -> Md5Digest
+where
+    FI: IntoIterator<Item = &'a FileChecksum>,
+    DI: IntoIterator<Item = &'a DirChecksum>,
+{
+    let mut hasher = Md5Writer::new();
+    write_checksum_json(files, directories, &mut hasher)
+        .expect("writing to an MD5 hasher should not fail");
+    hasher.finish()
+}
+
+/// Return the canonical JSON listing of a directory's entries as a string.
+/// This is only used for debugging; use [`get_checksum_digest()`] to compute
+/// the digest of the JSON.
 pub(super) fn get_checksum_json<'a, FI, DI>(files: FI, directories: DI) -> String
 where
     FI: IntoIterator<Item = &'a FileChecksum>,
     DI: IntoIterator<Item = &'a DirChecksum>,
+{
+    let mut buf = String::new();
+    write_checksum_json(files, directories, &mut buf).expect("formatting a String should not fail");
+    buf
+}
+
+fn write_checksum_json<'a, FI, DI, W>(
+    files: FI,
+    directories: DI,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    FI: IntoIterator<Item = &'a FileChecksum>,
+    DI: IntoIterator<Item = &'a DirChecksum>,
+    W: Write,
 {
     let mut filevec = files.into_iter().map(JSONEntry::from).collect::<Vec<_>>();
     filevec.sort_unstable();
@@ -18,25 +52,28 @@ where
         directories: dirvec,
         files: filevec,
     };
-    let mut buf = String::new();
-    collection
-        .write_json(&mut buf)
-        .expect("formatting a String should not fail");
-    buf
+    collection.write_json(writer)
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 struct JSONEntry<'a> {
     name: &'a str,
-    digest: &'a str,
+    digest: &'a Md5Digest,
+    /// For directories, the number of files within, which forms part of the
+    /// directory's checksum string
+    file_count: Option<u64>,
     size: u64,
 }
 
 impl JSONEntry<'_> {
     fn write_json<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
-        writer.write_str(r#"{"digest":"#)?;
-        write_json_str(self.digest, writer)?;
-        writer.write_str(r#","name":"#)?;
+        // Checksum strings consist only of hex digits, digits, and hyphens,
+        // none of which need escaping.
+        write!(writer, r#"{{"digest":"{}"#, self.digest)?;
+        if let Some(file_count) = self.file_count {
+            write!(writer, "-{file_count}--{}", self.size)?;
+        }
+        writer.write_str(r#"","name":"#)?;
         write_json_str(self.name, writer)?;
         write!(writer, r#","size":{}}}"#, self.size)?;
         Ok(())
@@ -47,7 +84,8 @@ impl<'a> From<&'a FileChecksum> for JSONEntry<'a> {
     fn from(node: &'a FileChecksum) -> JSONEntry<'a> {
         JSONEntry {
             name: node.name(),
-            digest: node.checksum(),
+            digest: &node.digest,
+            file_count: None,
             size: node.size,
         }
     }
@@ -57,7 +95,8 @@ impl<'a> From<&'a DirChecksum> for JSONEntry<'a> {
     fn from(node: &'a DirChecksum) -> JSONEntry<'a> {
         JSONEntry {
             name: node.name(),
-            digest: node.checksum(),
+            digest: &node.digest,
+            file_count: Some(node.file_count),
             size: node.size,
         }
     }
@@ -90,7 +129,7 @@ impl JSONEntryCollection<'_> {
     }
 }
 
-fn write_json_str<W: Write>(s: &str, writer: &mut W) -> Result<(), Error> {
+pub(crate) fn write_json_str<W: Write>(s: &str, writer: &mut W) -> Result<(), Error> {
     writer.write_char('"')?;
     for c in s.chars() {
         match c {
@@ -117,8 +156,13 @@ fn write_json_str<W: Write>(s: &str, writer: &mut W) -> Result<(), Error> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use md5::{Digest, Md5};
     use rstest::rstest;
 
+    fn md5_of(s: &str) -> Md5Digest {
+        Md5Digest::from(<[u8; 16]>::from(Md5::digest(s)))
+    }
+
     #[rstest]
     #[case("foobar", r#""foobar""#)]
     #[case("foo / bar", r#""foo / bar""#)]
@@ -139,22 +183,26 @@ mod test {
         let files = [
             FileChecksum {
                 relpath: "foo".try_into().unwrap(),
-                checksum: "0123456789abcdef0123456789abcdef".into(),
+                digest: "0123456789abcdef0123456789abcdef".parse().unwrap(),
                 size: 69105,
             },
             FileChecksum {
                 relpath: "bar".try_into().unwrap(),
-                checksum: "abcdef0123456789abcdef0123456789".into(),
+                digest: "abcdef0123456789abcdef0123456789".parse().unwrap(),
                 size: 42,
             },
         ];
         let directories = Vec::from([DirChecksum {
             relpath: "quux".try_into().unwrap(),
-            checksum: "0987654321fedcba0987654321fedcba-23--65537".into(),
+            digest: "0987654321fedcba0987654321fedcba".parse().unwrap(),
             size: 65537,
             file_count: 23,
         }]);
         let json = get_checksum_json(files.iter(), directories.iter());
+        assert_eq!(
+            get_checksum_digest(files.iter(), directories.iter()),
+            md5_of(&json)
+        );
         assert_eq!(
             json,
             r#"{"directories":[{"digest":"0987654321fedcba0987654321fedcba-23--65537","name":"quux","size":65537}],"files":[{"digest":"abcdef0123456789abcdef0123456789","name":"bar","size":42},{"digest":"0123456789abcdef0123456789abcdef","name":"foo","size":69105}]}"#
@@ -166,11 +214,41 @@ mod test {
         let files = Vec::new();
         let directories = [DirChecksum {
             relpath: "quux".try_into().unwrap(),
-            checksum: "481a2f77ab786a0f45aafd5db0971caa-0--0".into(),
+            digest: "481a2f77ab786a0f45aafd5db0971caa".parse().unwrap(),
             size: 0,
             file_count: 0,
         }];
         let json = get_checksum_json(files.iter(), directories.iter());
         assert_eq!(json, r#"{"directories":[],"files":[]}"#);
+        assert_eq!(
+            get_checksum_digest(files.iter(), directories.iter()),
+            md5_of(&json)
+        );
+    }
+
+    #[test]
+    fn test_get_checksum_digest_escaped_names() {
+        let files = [
+            FileChecksum {
+                relpath: "foo\"bar".try_into().unwrap(),
+                digest: "0123456789abcdef0123456789abcdef".parse().unwrap(),
+                size: 1,
+            },
+            FileChecksum {
+                relpath: "🐐\n—".try_into().unwrap(),
+                digest: "abcdef0123456789abcdef0123456789".parse().unwrap(),
+                size: 2,
+            },
+        ];
+        let directories: [DirChecksum; 0] = [];
+        let json = get_checksum_json(files.iter(), directories.iter());
+        assert_eq!(
+            json,
+            r#"{"directories":[],"files":[{"digest":"0123456789abcdef0123456789abcdef","name":"foo\"bar","size":1},{"digest":"abcdef0123456789abcdef0123456789","name":"\ud83d\udc10\n\u2014","size":2}]}"#
+        );
+        assert_eq!(
+            get_checksum_digest(files.iter(), directories.iter()),
+            md5_of(&json)
+        );
     }
 }
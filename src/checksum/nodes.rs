@@ -1,5 +1,6 @@
-use super::json::get_checksum_json;
-use crate::util::md5_string;
+use super::digest::Md5Digest;
+use super::json::{get_checksum_digest, get_checksum_json};
+use crate::errors::DirChecksumParseError;
 use crate::zarr::EntryPath;
 use enum_dispatch::enum_dispatch;
 
@@ -12,8 +13,12 @@ pub trait Checksum {
     /// Return the final component of the path
     fn name(&self) -> &str;
 
-    /// Return the checksum for the file or directory
-    fn checksum(&self) -> &str;
+    /// Return the MD5 digest for the file or directory.  For a directory,
+    /// this is only the first component of its checksum.
+    fn digest(&self) -> &Md5Digest;
+
+    /// Return the checksum for the file or directory as a string
+    fn checksum(&self) -> String;
 
     /// Consume the node and return the checksum for the file or directory
     fn into_checksum(self) -> String;
@@ -31,16 +36,18 @@ pub trait Checksum {
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct FileChecksum {
     pub(super) relpath: EntryPath,
-    /// The MD5 digest (in lowercase hexadecimal) of the file's contents
-    pub(super) checksum: String,
+    /// The MD5 digest of the file's contents
+    pub(super) digest: Md5Digest,
     pub(super) size: u64,
 }
 
 impl FileChecksum {
-    pub(crate) fn new(relpath: EntryPath, checksum: String, size: u64) -> Self {
+    /// Construct a `FileChecksum` for the file at `relpath` with the given
+    /// digest & size
+    pub fn new(relpath: EntryPath, digest: Md5Digest, size: u64) -> Self {
         FileChecksum {
             relpath,
-            checksum,
+            digest,
             size,
         }
     }
@@ -55,12 +62,16 @@ impl Checksum for FileChecksum {
         self.relpath.file_name()
     }
 
-    fn checksum(&self) -> &str {
-        &self.checksum
+    fn digest(&self) -> &Md5Digest {
+        &self.digest
+    }
+
+    fn checksum(&self) -> String {
+        self.digest.to_string()
     }
 
     fn into_checksum(self) -> String {
-        self.checksum
+        self.checksum()
     }
 
     fn size(&self) -> u64 {
@@ -76,12 +87,37 @@ impl Checksum for FileChecksum {
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct DirChecksum {
     pub(super) relpath: EntryPath,
-    /// A Zarr directory checksum of the form `{md5_hash}-{file_count}-{size}`
-    pub(super) checksum: String,
+    /// The MD5 digest of the directory's JSON listing.  The directory's full
+    /// checksum is of the form `{digest}-{file_count}--{size}`.
+    pub(super) digest: Md5Digest,
     pub(super) size: u64,
     pub(super) file_count: u64,
 }
 
+impl DirChecksum {
+    /// Construct a `DirChecksum` for the directory at `relpath` from a
+    /// checksum string of the form `{digest}-{file_count}--{size}`, as
+    /// returned by [`Checksum::checksum()`]
+    pub fn from_checksum(
+        relpath: EntryPath,
+        checksum: &str,
+    ) -> Result<DirChecksum, DirChecksumParseError> {
+        let err = || DirChecksumParseError(checksum.to_string());
+        let (digest, rest) = checksum.split_once('-').ok_or_else(err)?;
+        let (file_count, size) = rest.split_once("--").ok_or_else(err)?;
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_number(file_count) || !is_number(size) {
+            return Err(err());
+        }
+        Ok(DirChecksum {
+            relpath,
+            digest: digest.parse().map_err(|_| err())?,
+            size: size.parse().map_err(|_| err())?,
+            file_count: file_count.parse().map_err(|_| err())?,
+        })
+    }
+}
+
 impl Checksum for DirChecksum {
     fn relpath(&self) -> &EntryPath {
         &self.relpath
@@ -91,12 +127,16 @@ impl Checksum for DirChecksum {
         self.relpath.file_name()
     }
 
-    fn checksum(&self) -> &str {
-        &self.checksum
+    fn digest(&self) -> &Md5Digest {
+        &self.digest
+    }
+
+    fn checksum(&self) -> String {
+        format!("{}-{}--{}", self.digest, self.file_count, self.size)
     }
 
     fn into_checksum(self) -> String {
-        self.checksum
+        self.checksum()
     }
 
     fn size(&self) -> u64 {
@@ -156,6 +196,12 @@ impl Dirsummer {
         &self.relpath
     }
 
+    /// Return the checksums for the files pushed so far, in the order in
+    /// which they were pushed
+    pub(crate) fn files(&self) -> &[FileChecksum] {
+        &self.files
+    }
+
     /// Add a checksum for an entry of the directory.
     ///
     /// It is the caller's responsibility to ensure that `chksum` actually
@@ -176,22 +222,26 @@ impl Dirsummer {
     /// Compute the checksum for the directory based on the entry checksums
     /// added so far
     pub fn checksum(&self) -> DirChecksum {
-        let md5 = md5_string(&get_checksum_json(
-            self.files.iter(),
-            self.directories.iter(),
-        ));
-        let checksum = format!("{}-{}--{}", md5, self.file_count, self.size);
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!(
+                "JSON listing for directory {}: {}",
+                self.relpath,
+                get_checksum_json(self.files.iter(), self.directories.iter())
+            );
+        }
+        let digest = get_checksum_digest(self.files.iter(), self.directories.iter());
+        let node = DirChecksum {
+            relpath: self.relpath.clone(),
+            digest,
+            size: self.size,
+            file_count: self.file_count,
+        };
         log::debug!(
             "Computed checksum for directory {}: {}",
             self.relpath,
-            checksum
+            node.checksum()
         );
-        DirChecksum {
-            relpath: self.relpath.clone(),
-            checksum,
-            size: self.size,
-            file_count: self.file_count,
-        }
+        node
     }
 }
 
@@ -210,11 +260,49 @@ impl<N: Into<EntryChecksum>> Extend<N> for Dirsummer {
 mod test {
     use super::*;
 
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_node_sizes() {
+        // One pointer for the path, 16 bytes for the digest, and 8 bytes for
+        // each count
+        assert_eq!(size_of::<FileChecksum>(), 32);
+        assert_eq!(size_of::<DirChecksum>(), 40);
+    }
+
+    #[test]
+    fn test_dir_checksum_from_checksum() {
+        let node = DirChecksum::from_checksum(
+            "foo/bar".try_into().unwrap(),
+            "51c74ec257069ce3a555bdddeb50230a-2--746",
+        )
+        .unwrap();
+        assert_eq!(node.relpath().to_string(), "foo/bar");
+        assert_eq!(node.file_count(), 2);
+        assert_eq!(node.size(), 746);
+        assert_eq!(node.checksum(), "51c74ec257069ce3a555bdddeb50230a-2--746");
+    }
+
+    #[rstest::rstest]
+    #[case("51c74ec257069ce3a555bdddeb50230a")]
+    #[case("51c74ec257069ce3a555bdddeb50230a-2-746")]
+    #[case("51c74ec257069ce3a555bdddeb50230a-2--")]
+    #[case("51c74ec257069ce3a555bdddeb50230a--2--746")]
+    #[case("51c74ec257069ce3a555bdddeb50230a-+2--746")]
+    #[case("51c74ec257069ce3a555bdddeb5023-2--746")]
+    #[case("51C74EC257069CE3A555BDDDEB50230A-2--746")]
+    #[case("51c74ec257069ce3a555bdddeb50230a-2--746--1")]
+    fn test_dir_checksum_from_bad_checksum(#[case] s: &str) {
+        assert_eq!(
+            DirChecksum::from_checksum("foo".try_into().unwrap(), s),
+            Err(DirChecksumParseError(s.to_string()))
+        );
+    }
+
     #[test]
     fn test_dirsummer_nothing() {
         let ds = Dirsummer::new("foo".try_into().unwrap());
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "481a2f77ab786a0f45aafd5db0971caa-0--0"
         );
     }
@@ -224,11 +312,11 @@ mod test {
         let mut ds = Dirsummer::new("foo".try_into().unwrap());
         ds.push(FileChecksum {
             relpath: "bar".try_into().unwrap(),
-            checksum: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
+            digest: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".parse().unwrap(),
             size: 1,
         });
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "f21b9b4bf53d7ce1167bcfae76371e59-1--1"
         );
     }
@@ -238,12 +326,12 @@ mod test {
         let mut ds = Dirsummer::new("foo".try_into().unwrap());
         ds.push(DirChecksum {
             relpath: "bar".try_into().unwrap(),
-            checksum: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-1--1".into(),
+            digest: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".parse().unwrap(),
             size: 1,
             file_count: 1,
         });
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "ea8b8290b69b96422a3ed1cca0390f21-1--1"
         );
     }
@@ -253,16 +341,16 @@ mod test {
         let mut ds = Dirsummer::new("foo".try_into().unwrap());
         ds.push(FileChecksum {
             relpath: "bar".try_into().unwrap(),
-            checksum: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
+            digest: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".parse().unwrap(),
             size: 1,
         });
         ds.push(FileChecksum {
             relpath: "baz".try_into().unwrap(),
-            checksum: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".into(),
+            digest: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".parse().unwrap(),
             size: 1,
         });
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "8e50add2b46d3a6389e2d9d0924227fb-2--2"
         );
     }
@@ -272,18 +360,18 @@ mod test {
         let mut ds = Dirsummer::new("foo".try_into().unwrap());
         ds.push(DirChecksum {
             relpath: "bar".try_into().unwrap(),
-            checksum: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-1--1".into(),
+            digest: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".parse().unwrap(),
             size: 1,
             file_count: 1,
         });
         ds.push(DirChecksum {
             relpath: "baz".try_into().unwrap(),
-            checksum: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-1--1".into(),
+            digest: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".parse().unwrap(),
             size: 1,
             file_count: 1,
         });
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "4c21a113688f925240549b14136d61ff-2--2"
         );
     }
@@ -293,17 +381,17 @@ mod test {
         let mut ds = Dirsummer::new("foo".try_into().unwrap());
         ds.push(EntryChecksum::File(FileChecksum {
             relpath: "baz".try_into().unwrap(),
-            checksum: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".into(),
+            digest: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".parse().unwrap(),
             size: 1,
         }));
         ds.push(EntryChecksum::Directory(DirChecksum {
             relpath: "bar".try_into().unwrap(),
-            checksum: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-1--1".into(),
+            digest: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".parse().unwrap(),
             size: 1,
             file_count: 1,
         }));
         assert_eq!(
-            ds.checksum().checksum,
+            ds.checksum().checksum(),
             "d5e4eb5dc8efdb54ff089db1eef34119-2--2"
         );
     }
@@ -0,0 +1,362 @@
+use super::nodes::*;
+use crate::errors::ChecksumTreeError;
+use crate::zarr::EntryPath;
+use std::cmp::Ordering;
+
+/// A builder for computing the checksum for an entire Zarr from
+/// [`FileChecksum`]s supplied in sorted order
+///
+/// Files must be added with [`add_file()`][SortedChecksumBuilder::add_file] in
+/// increasing order of their paths, sorted either component by component (as
+/// determined by [`EntryPath`]'s `Ord` implementation) or as plain strings
+/// (i.e., byte order, as used by S3 listings).  The two orders differ only
+/// when a file name contains a character that sorts before `/`, e.g.,
+/// `foo.txt` sorts after `foo/bar` component-wise but before it bytewise;
+/// the builder determines which order is in use from the first such pair of
+/// paths and requires the remaining files to follow the same order.  Either
+/// way, the contents of each directory are added contiguously.
+///
+/// Only the directories containing the most recently added file are held in memory;
+/// each directory is collapsed into its checksum as soon as a file outside of
+/// it is added, and so memory usage is proportional to the depth of the
+/// hierarchy plus the number of entries in the directories currently open
+/// rather than to the total number of files.
+///
+/// Once all files have been added, the final checksum can be retrieved with
+/// [`checksum()`][SortedChecksumBuilder::checksum] or
+/// [`into_checksum()`][SortedChecksumBuilder::into_checksum].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SortedChecksumBuilder {
+    /// The currently open directories, starting with the root
+    stack: Vec<Dirsummer>,
+    /// The path of the most recently added file
+    last: Option<EntryPath>,
+    /// `last` as a string, for comparing in byte order
+    last_str: String,
+    /// The order that the files are being added in, if it has been determined
+    order: Option<PathOrder>,
+}
+
+/// The orders in which [`SortedChecksumBuilder`] accepts paths
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PathOrder {
+    /// Sorted component by component
+    Components,
+    /// Sorted as strings
+    Bytes,
+}
+
+impl SortedChecksumBuilder {
+    /// Create a new `SortedChecksumBuilder`
+    pub fn new() -> Self {
+        let Ok(ep) = EntryPath::try_from("<root>") else {
+            unreachable!("<root> should be a valid EntryPath");
+        };
+        SortedChecksumBuilder {
+            stack: vec![Dirsummer::new(ep)],
+            last: None,
+            last_str: String::new(),
+            order: None,
+        }
+    }
+
+    /// Add the checksum for a file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ChecksumTreeError::OutOfOrder`] if the file's path sorts
+    /// before that of the previously added file in the order in use,
+    /// [`ChecksumTreeError::DoubleAdd`] if it equals that of the previously
+    /// added file, or [`ChecksumTreeError::PathTypeConflict`] if a previously
+    /// added file is a parent path of the file.  The builder is left unchanged
+    /// on error.
+    pub fn add_file(&mut self, node: FileChecksum) -> Result<(), ChecksumTreeError> {
+        let ancestors = node.relpath().parents().collect::<Vec<_>>();
+        let path_str = node.relpath().to_string();
+        let mut order = self.order;
+        if let Some(last) = self.last.as_ref() {
+            let by_components = node.relpath().cmp(last);
+            let by_bytes = path_str.as_str().cmp(&self.last_str);
+            let cmp = match (order, by_components, by_bytes) {
+                (_, Ordering::Equal, _) => Ordering::Equal,
+                (Some(PathOrder::Components), c, _) | (Some(PathOrder::Bytes), _, c) => c,
+                (None, Ordering::Greater, Ordering::Greater) => Ordering::Greater,
+                (None, Ordering::Greater, _) => {
+                    order = Some(PathOrder::Components);
+                    Ordering::Greater
+                }
+                (None, _, Ordering::Greater) => {
+                    order = Some(PathOrder::Bytes);
+                    Ordering::Greater
+                }
+                (None, _, _) => Ordering::Less,
+            };
+            match cmp {
+                Ordering::Less => {
+                    return Err(ChecksumTreeError::OutOfOrder {
+                        path: node.relpath,
+                        previous: last.clone(),
+                    })
+                }
+                Ordering::Equal => return Err(ChecksumTreeError::DoubleAdd { path: node.relpath }),
+                Ordering::Greater => (),
+            }
+        }
+        // Count how many of the open directories (other than the root) are
+        // parents of the new file.  As the open directories form a chain, it
+        // suffices to compare their names.
+        let mut shared = 0;
+        while shared < ancestors.len()
+            && shared + 1 < self.stack.len()
+            && self.stack[shared + 1].relpath().file_name() == ancestors[shared].file_name()
+        {
+            shared += 1;
+        }
+        // Only the first of the directories about to be opened can clash with
+        // a file added earlier, namely one in the innermost shared directory.
+        // In either order, the files in a directory are added in order of
+        // their names, so such a file can be found by binary search.
+        if let Some(dir) = ancestors.get(shared) {
+            if self.stack[shared]
+                .files()
+                .binary_search_by(|f| f.name().cmp(dir.file_name()))
+                .is_ok()
+            {
+                return Err(ChecksumTreeError::PathTypeConflict { path: dir.clone() });
+            }
+        }
+        self.collapse_to(shared + 1);
+        self.stack
+            .extend(ancestors.into_iter().skip(shared).map(Dirsummer::new));
+        self.last = Some(node.relpath().clone());
+        self.last_str = path_str;
+        self.order = order;
+        self.stack
+            .last_mut()
+            .expect("root directory should always be on the stack")
+            .push(node);
+        Ok(())
+    }
+
+    /// Construct a new `SortedChecksumBuilder` from an iterator of
+    /// [`FileChecksum`]s in sorted order
+    pub fn from_files<I: IntoIterator<Item = FileChecksum>>(
+        iter: I,
+    ) -> Result<SortedChecksumBuilder, ChecksumTreeError> {
+        let mut builder = SortedChecksumBuilder::new();
+        for node in iter {
+            builder.add_file(node)?;
+        }
+        Ok(builder)
+    }
+
+    /// Compute the Zarr checksum for all files added so far
+    pub fn checksum(&self) -> String {
+        self.clone().into_checksum()
+    }
+
+    /// Consume the builder and return the Zarr checksum for all files added
+    pub fn into_checksum(mut self) -> String {
+        self.collapse_to(1);
+        self.stack
+            .pop()
+            .expect("root directory should always be on the stack")
+            .checksum()
+            .into_checksum()
+    }
+
+    /// Collapse open directories until only `len` remain
+    fn collapse_to(&mut self, len: usize) {
+        while self.stack.len() > len {
+            let Some(ds) = self.stack.pop() else {
+                unreachable!("stack should be nonempty");
+            };
+            let node = ds.checksum();
+            self.stack
+                .last_mut()
+                .expect("root directory should always be on the stack")
+                .push(node);
+        }
+    }
+}
+
+impl Default for SortedChecksumBuilder {
+    fn default() -> Self {
+        SortedChecksumBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::ChecksumTree;
+    use assert_matches::assert_matches;
+
+    fn file(path: &str, digest: &str, size: u64) -> FileChecksum {
+        FileChecksum {
+            relpath: path.try_into().unwrap(),
+            digest: digest.parse().unwrap(),
+            size,
+        }
+    }
+
+    fn sample_files() -> Vec<FileChecksum> {
+        vec![
+            file(".zgroup", "e20297935e73dd0154104d4ea53040ab", 24),
+            file("arr_0/.zarray", "9e30a0a1a465e24220d4132fdd544634", 315),
+            file("arr_0/0", "ed4e934a474f1d2096846c6248f18c00", 431),
+            file("arr_1/.zarray", "9e30a0a1a465e24220d4132fdd544634", 315),
+            file("arr_1/0", "fba4dee03a51bde314e9713b00284a93", 431),
+        ]
+    }
+
+    #[test]
+    fn test_sample() {
+        let builder = SortedChecksumBuilder::from_files(sample_files()).unwrap();
+        assert_eq!(
+            builder.checksum(),
+            "4313ab36412db2981c3ed391b38604d6-5--1516"
+        );
+        assert_eq!(
+            builder.into_checksum(),
+            "4313ab36412db2981c3ed391b38604d6-5--1516"
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(
+            SortedChecksumBuilder::new().into_checksum(),
+            "481a2f77ab786a0f45aafd5db0971caa-0--0"
+        );
+    }
+
+    #[test]
+    fn test_nested() {
+        let files = vec![
+            file("a/b/c/d", "0123456789abcdef0123456789abcdef", 1),
+            file("a/b/c/e", "0123456789abcdef0123456789abcdef", 2),
+            file("a/b/f", "0123456789abcdef0123456789abcdef", 3),
+            file("a/g/h", "0123456789abcdef0123456789abcdef", 4),
+            file("a/g/i/j", "0123456789abcdef0123456789abcdef", 5),
+            file("a/k", "0123456789abcdef0123456789abcdef", 6),
+            file("a.txt", "0123456789abcdef0123456789abcdef", 7),
+            file("l/m", "0123456789abcdef0123456789abcdef", 8),
+        ];
+        let expected = ChecksumTree::from_files(files.clone())
+            .unwrap()
+            .into_checksum();
+        assert_eq!(
+            SortedChecksumBuilder::from_files(files)
+                .unwrap()
+                .into_checksum(),
+            expected
+        );
+    }
+
+    /// Byte-sorted input, as from an S3 listing, in which `foo.txt` comes
+    /// before `foo/bar`
+    #[test]
+    fn test_byte_order() {
+        let files = vec![
+            file("foo.txt", "0123456789abcdef0123456789abcdef", 1),
+            file("foo/bar", "0123456789abcdef0123456789abcdef", 2),
+            file("foo/baz-1/a", "0123456789abcdef0123456789abcdef", 3),
+            file("foo/baz/b", "0123456789abcdef0123456789abcdef", 4),
+            file("foo0", "0123456789abcdef0123456789abcdef", 5),
+        ];
+        let mut sorted = files.clone();
+        sorted.sort_unstable_by_key(|a| a.relpath().to_string());
+        assert_eq!(sorted, files);
+        let expected = ChecksumTree::from_files(files.clone())
+            .unwrap()
+            .into_checksum();
+        assert_eq!(
+            SortedChecksumBuilder::from_files(files)
+                .unwrap()
+                .into_checksum(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_mixed_orders() {
+        // Component order is established by "foo/bar" before "foo.txt" ...
+        let mut builder = SortedChecksumBuilder::from_files([
+            file("foo/bar", "0123456789abcdef0123456789abcdef", 1),
+            file("foo.txt", "0123456789abcdef0123456789abcdef", 2),
+        ])
+        .unwrap();
+        // ... so returning to "foo/" would reopen a collapsed directory.
+        let r = builder.add_file(file("foo/baz", "0123456789abcdef0123456789abcdef", 3));
+        assert_matches!(r, Err(ChecksumTreeError::OutOfOrder { path, previous }) => {
+            assert_eq!(path.to_string(), "foo/baz");
+            assert_eq!(previous.to_string(), "foo.txt");
+        });
+        // Likewise for byte order
+        let mut builder = SortedChecksumBuilder::from_files([
+            file("foo.txt", "0123456789abcdef0123456789abcdef", 1),
+            file("foo/bar", "0123456789abcdef0123456789abcdef", 2),
+        ])
+        .unwrap();
+        let r = builder.add_file(file("foo.zzz", "0123456789abcdef0123456789abcdef", 3));
+        assert_matches!(r, Err(ChecksumTreeError::OutOfOrder { path, previous }) => {
+            assert_eq!(path.to_string(), "foo.zzz");
+            assert_eq!(previous.to_string(), "foo/bar");
+        });
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut builder = SortedChecksumBuilder::new();
+        builder
+            .add_file(file("arr_1/0", "fba4dee03a51bde314e9713b00284a93", 431))
+            .unwrap();
+        let r = builder.add_file(file("arr_0/0", "ed4e934a474f1d2096846c6248f18c00", 431));
+        assert_matches!(r, Err(ChecksumTreeError::OutOfOrder { path, previous }) => {
+            assert_eq!(path.to_string(), "arr_0/0");
+            assert_eq!(previous.to_string(), "arr_1/0");
+        });
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let mut builder = SortedChecksumBuilder::new();
+        builder
+            .add_file(file("arr_0/0", "ed4e934a474f1d2096846c6248f18c00", 431))
+            .unwrap();
+        let r = builder.add_file(file("arr_0/0", "ed4e934a474f1d2096846c6248f18c00", 431));
+        assert_matches!(r, Err(ChecksumTreeError::DoubleAdd { path }) => {
+            assert_eq!(path.to_string(), "arr_0/0");
+        });
+    }
+
+    #[test]
+    fn test_path_type_conflict() {
+        let mut builder = SortedChecksumBuilder::new();
+        builder
+            .add_file(file("arr_0", "ed4e934a474f1d2096846c6248f18c00", 431))
+            .unwrap();
+        let r = builder.add_file(file("arr_0/0/1", "ed4e934a474f1d2096846c6248f18c00", 431));
+        assert_matches!(r, Err(ChecksumTreeError::PathTypeConflict { path }) => {
+            assert_eq!(path.to_string(), "arr_0");
+        });
+    }
+
+    /// In byte order, other files can come between a file and a directory of
+    /// the same name
+    #[test]
+    fn test_path_type_conflict_byte_order() {
+        let mut builder = SortedChecksumBuilder::from_files([
+            file("a", "0123456789abcdef0123456789abcdef", 1),
+            file("a.txt", "0123456789abcdef0123456789abcdef", 2),
+        ])
+        .unwrap();
+        let before = builder.clone();
+        let r = builder.add_file(file("a/b", "0123456789abcdef0123456789abcdef", 3));
+        assert_matches!(r, Err(ChecksumTreeError::PathTypeConflict { path }) => {
+            assert_eq!(path.to_string(), "a");
+        });
+        assert_eq!(builder, before);
+    }
+}
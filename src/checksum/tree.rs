@@ -13,6 +13,12 @@ use std::fmt;
 /// be retrieved with [`checksum()`][ChecksumTree::checksum] or
 /// [`into_checksum()`][ChecksumTree::into_checksum].  Alternatively, these
 /// steps can be done all at once by calling [`ChecksumTree::from_files`].
+///
+/// Checksums for whole directories that were computed elsewhere (e.g., by
+/// another process that checksummed a single subdirectory) can be grafted
+/// onto a tree with [`add_directory()`][ChecksumTree::add_directory], and
+/// trees built from disjoint parts of a Zarr can be combined with
+/// [`merge()`][ChecksumTree::merge].
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ChecksumTree(DirTree);
 
@@ -26,6 +32,8 @@ struct DirTree {
 enum TreeNode {
     File(FileChecksum),
     Directory(DirTree),
+    /// A directory for which only the final checksum is known
+    Precomputed(DirChecksum),
 }
 
 impl ChecksumTree {
@@ -49,18 +57,7 @@ impl ChecksumTree {
 
     /// Add the checksum for a file to the tree
     pub fn add_file(&mut self, node: FileChecksum) -> Result<(), ChecksumTreeError> {
-        let mut d = &mut self.0.children;
-        for parent in node.relpath().parents() {
-            match d
-                .entry(parent.file_name().to_string())
-                .or_insert_with(|| TreeNode::directory(parent.clone()))
-            {
-                TreeNode::File(_) => {
-                    return Err(ChecksumTreeError::PathTypeConflict { path: parent })
-                }
-                TreeNode::Directory(DirTree { children, .. }) => d = children,
-            }
-        }
+        let d = self.0.parent_children(node.relpath())?;
         match d.entry(node.relpath().file_name().to_string()) {
             Entry::Occupied(_) => return Err(ChecksumTreeError::DoubleAdd { path: node.relpath }),
             Entry::Vacant(v) => {
@@ -70,6 +67,41 @@ impl ChecksumTree {
         Ok(())
     }
 
+    /// Graft the precomputed checksum for a directory onto the tree.  No
+    /// files or directories may be added beneath the directory afterwards.
+    pub fn add_directory(&mut self, node: DirChecksum) -> Result<(), ChecksumTreeError> {
+        let d = self.0.parent_children(node.relpath())?;
+        match d.entry(node.relpath().file_name().to_string()) {
+            Entry::Occupied(o) => match o.get() {
+                TreeNode::File(_) => {
+                    Err(ChecksumTreeError::PathTypeConflict { path: node.relpath })
+                }
+                TreeNode::Directory(_) => {
+                    Err(ChecksumTreeError::GraftConflict { path: node.relpath })
+                }
+                TreeNode::Precomputed(_) => {
+                    Err(ChecksumTreeError::DoubleAdd { path: node.relpath })
+                }
+            },
+            Entry::Vacant(v) => {
+                v.insert(TreeNode::Precomputed(node));
+                Ok(())
+            }
+        }
+    }
+
+    /// Add all of the files & directories in `other` to the tree.
+    ///
+    /// Directories present in both trees are merged recursively.  If any
+    /// path is present in both trees as something other than a directory
+    /// with file-level detail, an error is returned, and `self` is left
+    /// unmodified.
+    pub fn merge(&mut self, other: ChecksumTree) -> Result<(), ChecksumTreeError> {
+        self.0.check_merge(&other.0)?;
+        self.0.merge(other.0);
+        Ok(())
+    }
+
     /// Construct a new `ChecksumTree` from an iterator of
     /// [`FileChecksum`]s
     pub fn from_files<I: IntoIterator<Item = FileChecksum>>(
@@ -82,6 +114,23 @@ impl ChecksumTree {
         Ok(zarr)
     }
 
+    /// Return the checksums for the entries of the directory at `dir` (or of
+    /// the root of the tree if `dir` is `None`) in no particular order.
+    /// Returns `None` if `dir` is not a directory in the tree or is a
+    /// directory added with [`add_directory()`][ChecksumTree::add_directory].
+    pub fn entries(&self, dir: Option<&EntryPath>) -> Option<Vec<EntryChecksum>> {
+        let mut d = &self.0;
+        if let Some(dir) = dir {
+            for p in dir.parents().chain(std::iter::once(dir.clone())) {
+                match d.children.get(p.file_name()) {
+                    Some(TreeNode::Directory(sub)) => d = sub,
+                    _ => return None,
+                }
+            }
+        }
+        Some(d.children.values().map(TreeNode::to_checksum).collect())
+    }
+
     pub fn into_termtree(self) -> termtree::Tree<TermTreeNode> {
         let (_, tree) = self.0.into_termtree();
         let termtree::Tree {
@@ -110,6 +159,71 @@ impl DirTree {
         }
     }
 
+    /// Return the children of the directory that will contain `path`,
+    /// creating intermediate directories as needed
+    fn parent_children(
+        &mut self,
+        path: &EntryPath,
+    ) -> Result<&mut HashMap<String, TreeNode>, ChecksumTreeError> {
+        let mut d = &mut self.children;
+        for parent in path.parents() {
+            match d
+                .entry(parent.file_name().to_string())
+                .or_insert_with(|| TreeNode::directory(parent.clone()))
+            {
+                TreeNode::File(_) => {
+                    return Err(ChecksumTreeError::PathTypeConflict { path: parent })
+                }
+                TreeNode::Directory(DirTree { children, .. }) => d = children,
+                TreeNode::Precomputed(_) => {
+                    return Err(ChecksumTreeError::GraftConflict { path: parent })
+                }
+            }
+        }
+        Ok(d)
+    }
+
+    /// Check whether `other` can be merged into `self` without conflicts
+    fn check_merge(&self, other: &DirTree) -> Result<(), ChecksumTreeError> {
+        for (name, theirs) in &other.children {
+            let Some(ours) = self.children.get(name) else {
+                continue;
+            };
+            let path = theirs.relpath().clone();
+            match (ours, theirs) {
+                (TreeNode::Directory(a), TreeNode::Directory(b)) => a.check_merge(b)?,
+                (TreeNode::File(_), TreeNode::File(_))
+                | (TreeNode::Precomputed(_), TreeNode::Precomputed(_)) => {
+                    return Err(ChecksumTreeError::DoubleAdd { path })
+                }
+                (TreeNode::File(_), _) | (_, TreeNode::File(_)) => {
+                    return Err(ChecksumTreeError::PathTypeConflict { path })
+                }
+                (TreeNode::Precomputed(_), TreeNode::Directory(_))
+                | (TreeNode::Directory(_), TreeNode::Precomputed(_)) => {
+                    return Err(ChecksumTreeError::GraftConflict { path })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge `other` into `self`.  [`DirTree::check_merge()`] must have
+    /// succeeded first.
+    fn merge(&mut self, other: DirTree) {
+        for (name, theirs) in other.children {
+            match self.children.entry(name) {
+                Entry::Occupied(mut o) => match (o.get_mut(), theirs) {
+                    (TreeNode::Directory(a), TreeNode::Directory(b)) => a.merge(b),
+                    _ => unreachable!("merge conflicts should have been checked for"),
+                },
+                Entry::Vacant(v) => {
+                    v.insert(theirs);
+                }
+            }
+        }
+    }
+
     fn to_checksum(&self) -> DirChecksum {
         let mut ds = Dirsummer::new(self.relpath.clone());
         ds.extend(self.children.values().map(TreeNode::to_checksum));
@@ -127,7 +241,7 @@ impl DirTree {
                 TreeNode::File(fc) => {
                     leaves.push(termtree::Tree::new(TermTreeNode::File {
                         name: fc.name().to_string(),
-                        checksum: fc.checksum().to_string(),
+                        checksum: fc.checksum(),
                     }));
                     ds.push(fc);
                 }
@@ -136,10 +250,17 @@ impl DirTree {
                     leaves.push(subtree);
                     ds.push(dircheck);
                 }
+                TreeNode::Precomputed(dc) => {
+                    leaves.push(termtree::Tree::new(TermTreeNode::Directory {
+                        name: dc.name().to_string(),
+                        checksum: dc.checksum(),
+                    }));
+                    ds.push(dc);
+                }
             }
         }
         let dircheck = ds.checksum();
-        let checksum = dircheck.checksum().to_string();
+        let checksum = dircheck.checksum();
         (
             dircheck,
             termtree::Tree::new(TermTreeNode::Directory { name, checksum }).with_leaves(leaves),
@@ -160,10 +281,19 @@ impl TreeNode {
         TreeNode::Directory(DirTree::new(relpath))
     }
 
+    fn relpath(&self) -> &EntryPath {
+        match self {
+            TreeNode::File(node) => node.relpath(),
+            TreeNode::Directory(dirtree) => &dirtree.relpath,
+            TreeNode::Precomputed(node) => node.relpath(),
+        }
+    }
+
     fn to_checksum(&self) -> EntryChecksum {
         match self {
             TreeNode::File(node) => node.clone().into(),
             TreeNode::Directory(dirtree) => dirtree.to_checksum().into(),
+            TreeNode::Precomputed(node) => node.clone().into(),
         }
     }
 }
@@ -173,6 +303,7 @@ impl From<TreeNode> for EntryChecksum {
         match node {
             TreeNode::File(node) => node.into(),
             TreeNode::Directory(dirtree) => DirChecksum::from(dirtree).into(),
+            TreeNode::Precomputed(node) => node.into(),
         }
     }
 }
@@ -198,6 +329,7 @@ impl fmt::Display for TermTreeNode {
 #[cfg(test)]
 mod test {
     use super::*;
+    use assert_matches::assert_matches;
 
     #[test]
     fn test_checksum_tree() {
@@ -205,35 +337,35 @@ mod test {
         sample
             .add_file(FileChecksum {
                 relpath: "arr_0/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             })
             .unwrap();
         sample
             .add_file(FileChecksum {
                 relpath: "arr_0/0".try_into().unwrap(),
-                checksum: "ed4e934a474f1d2096846c6248f18c00".into(),
+                digest: "ed4e934a474f1d2096846c6248f18c00".parse().unwrap(),
                 size: 431,
             })
             .unwrap();
         sample
             .add_file(FileChecksum {
                 relpath: "arr_1/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             })
             .unwrap();
         sample
             .add_file(FileChecksum {
                 relpath: "arr_1/0".try_into().unwrap(),
-                checksum: "fba4dee03a51bde314e9713b00284a93".into(),
+                digest: "fba4dee03a51bde314e9713b00284a93".parse().unwrap(),
                 size: 431,
             })
             .unwrap();
         sample
             .add_file(FileChecksum {
                 relpath: ".zgroup".try_into().unwrap(),
-                checksum: "e20297935e73dd0154104d4ea53040ab".into(),
+                digest: "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
                 size: 24,
             })
             .unwrap();
@@ -248,27 +380,27 @@ mod test {
         let files = vec![
             FileChecksum {
                 relpath: "arr_0/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             },
             FileChecksum {
                 relpath: "arr_0/0".try_into().unwrap(),
-                checksum: "ed4e934a474f1d2096846c6248f18c00".into(),
+                digest: "ed4e934a474f1d2096846c6248f18c00".parse().unwrap(),
                 size: 431,
             },
             FileChecksum {
                 relpath: "arr_1/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             },
             FileChecksum {
                 relpath: "arr_1/0".try_into().unwrap(),
-                checksum: "fba4dee03a51bde314e9713b00284a93".into(),
+                digest: "fba4dee03a51bde314e9713b00284a93".parse().unwrap(),
                 size: 431,
             },
             FileChecksum {
                 relpath: ".zgroup".try_into().unwrap(),
-                checksum: "e20297935e73dd0154104d4ea53040ab".into(),
+                digest: "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
                 size: 24,
             },
         ];
@@ -280,7 +412,7 @@ mod test {
         sample
             .add_file(FileChecksum {
                 relpath: "arr_0/1".try_into().unwrap(),
-                checksum: "d41d8cd98f00b204e9800998ecf8427e".into(),
+                digest: "d41d8cd98f00b204e9800998ecf8427e".parse().unwrap(),
                 size: 0,
             })
             .unwrap();
@@ -295,27 +427,27 @@ mod test {
         let files = vec![
             FileChecksum {
                 relpath: "arr_0/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             },
             FileChecksum {
                 relpath: "arr_0/0".try_into().unwrap(),
-                checksum: "ed4e934a474f1d2096846c6248f18c00".into(),
+                digest: "ed4e934a474f1d2096846c6248f18c00".parse().unwrap(),
                 size: 431,
             },
             FileChecksum {
                 relpath: "arr_1/.zarray".try_into().unwrap(),
-                checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
                 size: 315,
             },
             FileChecksum {
                 relpath: "arr_1/0".try_into().unwrap(),
-                checksum: "fba4dee03a51bde314e9713b00284a93".into(),
+                digest: "fba4dee03a51bde314e9713b00284a93".parse().unwrap(),
                 size: 431,
             },
             FileChecksum {
                 relpath: ".zgroup".try_into().unwrap(),
-                checksum: "e20297935e73dd0154104d4ea53040ab".into(),
+                digest: "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
                 size: 24,
             },
         ];
@@ -340,7 +472,7 @@ mod test {
     fn test_draw_deeper_tree() {
         let files = vec![FileChecksum {
             relpath: "foo/bar/baz/quux.dat".try_into().unwrap(),
-            checksum: "9e30a0a1a465e24220d4132fdd544634".into(),
+            digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
             size: 315,
         }];
         let sample = ChecksumTree::from_files(files).unwrap();
@@ -356,4 +488,198 @@ mod test {
             )
         );
     }
+
+    fn sample_files() -> Vec<FileChecksum> {
+        vec![
+            FileChecksum {
+                relpath: "arr_0/.zarray".try_into().unwrap(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
+                size: 315,
+            },
+            FileChecksum {
+                relpath: "arr_0/0".try_into().unwrap(),
+                digest: "ed4e934a474f1d2096846c6248f18c00".parse().unwrap(),
+                size: 431,
+            },
+            FileChecksum {
+                relpath: "arr_1/.zarray".try_into().unwrap(),
+                digest: "9e30a0a1a465e24220d4132fdd544634".parse().unwrap(),
+                size: 315,
+            },
+            FileChecksum {
+                relpath: "arr_1/0".try_into().unwrap(),
+                digest: "fba4dee03a51bde314e9713b00284a93".parse().unwrap(),
+                size: 431,
+            },
+            FileChecksum {
+                relpath: ".zgroup".try_into().unwrap(),
+                digest: "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
+                size: 24,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut files = sample_files();
+        let theirs = files.split_off(2);
+        let mut tree = ChecksumTree::from_files(files).unwrap();
+        tree.merge(ChecksumTree::from_files(theirs).unwrap())
+            .unwrap();
+        assert_eq!(tree.checksum(), "4313ab36412db2981c3ed391b38604d6-5--1516");
+    }
+
+    #[test]
+    fn test_merge_shared_directory() {
+        let mut files = sample_files();
+        let theirs = files.split_off(1);
+        let mut tree = ChecksumTree::from_files(files).unwrap();
+        tree.merge(ChecksumTree::from_files(theirs).unwrap())
+            .unwrap();
+        assert_eq!(tree.checksum(), "4313ab36412db2981c3ed391b38604d6-5--1516");
+    }
+
+    #[test]
+    fn test_merge_double_add() {
+        let mut tree = ChecksumTree::from_files(sample_files()).unwrap();
+        let other = ChecksumTree::from_files(sample_files().split_off(3)).unwrap();
+        let before = tree.clone();
+        assert_matches!(
+            tree.merge(other),
+            Err(ChecksumTreeError::DoubleAdd { path }) => {
+                assert!(path.to_string() == "arr_1/0" || path.to_string() == ".zgroup");
+            }
+        );
+        assert_eq!(tree, before);
+    }
+
+    #[test]
+    fn test_merge_path_type_conflict() {
+        let mut tree = ChecksumTree::from_files(sample_files()).unwrap();
+        let other = ChecksumTree::from_files([FileChecksum {
+            relpath: "arr_0".try_into().unwrap(),
+            digest: "d41d8cd98f00b204e9800998ecf8427e".parse().unwrap(),
+            size: 0,
+        }])
+        .unwrap();
+        let before = tree.clone();
+        assert_matches!(
+            tree.merge(other),
+            Err(ChecksumTreeError::PathTypeConflict { path }) => {
+                assert_eq!(path.to_string(), "arr_0");
+            }
+        );
+        assert_eq!(tree, before);
+    }
+
+    #[test]
+    fn test_add_directory() {
+        let mut files = sample_files();
+        files.drain(..2);
+        let mut tree = ChecksumTree::from_files(files).unwrap();
+        tree.add_directory(
+            DirChecksum::from_checksum(
+                "arr_0".try_into().unwrap(),
+                "51c74ec257069ce3a555bdddeb50230a-2--746",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(tree.checksum(), "4313ab36412db2981c3ed391b38604d6-5--1516");
+        assert_eq!(
+            tree.into_termtree().to_string(),
+            concat!(
+                "4313ab36412db2981c3ed391b38604d6-5--1516\n",
+                "├── .zgroup = e20297935e73dd0154104d4ea53040ab\n",
+                "├── arr_0/ = 51c74ec257069ce3a555bdddeb50230a-2--746\n",
+                "└── arr_1/ = 7b99a0ad9bd8bb3331657e54755b1a31-2--746\n",
+                "    ├── .zarray = 9e30a0a1a465e24220d4132fdd544634\n",
+                "    └── 0 = fba4dee03a51bde314e9713b00284a93\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_graft_conflicts() {
+        let arr_0 = DirChecksum::from_checksum(
+            "arr_0".try_into().unwrap(),
+            "51c74ec257069ce3a555bdddeb50230a-2--746",
+        )
+        .unwrap();
+        let mut tree = ChecksumTree::from_files(sample_files()).unwrap();
+        assert_matches!(
+            tree.add_directory(arr_0.clone()),
+            Err(ChecksumTreeError::GraftConflict { path }) => {
+                assert_eq!(path.to_string(), "arr_0");
+            }
+        );
+        let mut tree = ChecksumTree::new();
+        tree.add_directory(arr_0.clone()).unwrap();
+        assert_matches!(
+            tree.add_directory(arr_0),
+            Err(ChecksumTreeError::DoubleAdd { path }) => {
+                assert_eq!(path.to_string(), "arr_0");
+            }
+        );
+        assert_matches!(
+            tree.add_file(sample_files().swap_remove(0)),
+            Err(ChecksumTreeError::GraftConflict { path }) => {
+                assert_eq!(path.to_string(), "arr_0");
+            }
+        );
+        let other = ChecksumTree::from_files(sample_files()).unwrap();
+        assert_matches!(
+            tree.merge(other),
+            Err(ChecksumTreeError::GraftConflict { path }) => {
+                assert_eq!(path.to_string(), "arr_0");
+            }
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut tree = ChecksumTree::from_files(sample_files()).unwrap();
+        let mut root = tree.entries(None).unwrap();
+        root.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(
+            root.iter()
+                .map(|e| (e.name(), e.is_dir(), e.checksum()))
+                .collect::<Vec<_>>(),
+            [
+                (".zgroup", false, "e20297935e73dd0154104d4ea53040ab".into()),
+                (
+                    "arr_0",
+                    true,
+                    "51c74ec257069ce3a555bdddeb50230a-2--746".into()
+                ),
+                (
+                    "arr_1",
+                    true,
+                    "7b99a0ad9bd8bb3331657e54755b1a31-2--746".into()
+                ),
+            ]
+        );
+        let arr_1 = EntryPath::try_from("arr_1").unwrap();
+        let mut entries = tree.entries(Some(&arr_1)).unwrap();
+        entries.sort_unstable_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(
+            entries,
+            sample_files()[2..4]
+                .iter()
+                .cloned()
+                .map(EntryChecksum::from)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(tree.entries(Some(&"arr_1/0".try_into().unwrap())), None);
+        assert_eq!(tree.entries(Some(&"arr_2".try_into().unwrap())), None);
+        tree.add_directory(
+            DirChecksum::from_checksum(
+                "arr_2".try_into().unwrap(),
+                "51c74ec257069ce3a555bdddeb50230a-2--746",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(tree.entries(Some(&"arr_2".try_into().unwrap())), None);
+    }
 }
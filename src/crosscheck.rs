@@ -0,0 +1,601 @@
+//! Comparing the results of different implementations on the same Zarr
+//!
+//! Every implementation in [`walkers`][crate::walkers] should produce the
+//! same checksum for the same Zarr, so a difference between any two of them
+//! indicates a bug.  To help locate such a bug, each implementation can be
+//! run with a [`Recorder`] attached to the [`Zarr`][crate::zarr::Zarr] as its
+//! [`ProgressObserver`], which records every file & directory checksum
+//! computed during the traversal.  The resulting [`Run`]s are then passed to
+//! [`cross_check()`], which checks each run's output against the file
+//! checksums it reported and compares the runs with each other, returning a
+//! [`Divergence`] for each disagreement found.
+use crate::checksum::{
+    Checksum, ChecksumTree, DirChecksum, Dirsummer, EntryChecksum, FileChecksum,
+};
+use crate::errors::ChecksumTreeError;
+use crate::progress::ProgressObserver;
+use crate::zarr::EntryPath;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::mem::take;
+use std::sync::{Arc, Mutex};
+
+/// A [`ProgressObserver`] that records the checksums of all files & directories
+/// computed during a traversal.  Clones share the same record.
+#[derive(Clone, Debug, Default)]
+pub struct Recorder(Arc<Mutex<Recorded>>);
+
+#[derive(Debug, Default)]
+struct Recorded {
+    files: Vec<FileChecksum>,
+    directories: Vec<DirChecksum>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Take the checksums recorded so far and return them as a [`Run`] with
+    /// the given name & final checksum
+    pub fn into_run(self, name: String, checksum: String) -> Run {
+        let recorded = take(&mut *self.0.lock().expect("Mutex should not have been poisoned"));
+        Run {
+            name,
+            checksum,
+            files: recorded.files,
+            directories: recorded.directories,
+        }
+    }
+}
+
+impl ProgressObserver for Recorder {
+    fn file_hashed(&self, file: &FileChecksum) {
+        self.0
+            .lock()
+            .expect("Mutex should not have been poisoned")
+            .files
+            .push(file.clone());
+    }
+
+    fn dir_collapsed(&self, dir: &DirChecksum) {
+        self.0
+            .lock()
+            .expect("Mutex should not have been poisoned")
+            .directories
+            .push(dir.clone());
+    }
+}
+
+/// The results of running a single implementation on a Zarr
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Run {
+    /// The name of the implementation
+    pub name: String,
+    /// The checksum output by the implementation
+    pub checksum: String,
+    /// The checksums of the files hashed during the run
+    pub files: Vec<FileChecksum>,
+    /// The checksums of the directories collapsed during the run.  This is
+    /// empty for implementations that build a tree of all file checksums.
+    pub directories: Vec<DirChecksum>,
+}
+
+/// Check each run's checksum against the file checksums that it reported, and
+/// compare each run after the first with the first run.  Returns all
+/// disagreements found, in order; if the result is empty, all of the runs
+/// agree.
+pub fn cross_check(runs: &[Run]) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+    let mut trees = Vec::with_capacity(runs.len());
+    for run in runs {
+        match ChecksumTree::from_files(run.files.iter().cloned()) {
+            Ok(tree) => {
+                if let Some(inconsistency) = Inconsistency::check(run, &tree) {
+                    divergences.push(Divergence::Inconsistent(inconsistency));
+                }
+                trees.push(Some(tree));
+            }
+            Err(source) => {
+                divergences.push(Divergence::InvalidFiles {
+                    name: run.name.clone(),
+                    source,
+                });
+                trees.push(None);
+            }
+        }
+    }
+    if let Some((first, first_tree)) = runs.first().zip(trees.first()) {
+        for (run, tree) in runs.iter().zip(&trees).skip(1) {
+            if run.checksum != first.checksum {
+                let first_difference = match (first_tree, tree) {
+                    (Some(left), Some(right)) => first_difference(left, right),
+                    _ => None,
+                };
+                divergences.push(Divergence::Mismatch(Mismatch {
+                    left: first.name.clone(),
+                    left_checksum: first.checksum.clone(),
+                    right: run.name.clone(),
+                    right_checksum: run.checksum.clone(),
+                    first_difference,
+                }));
+            }
+        }
+    }
+    divergences
+}
+
+/// A disagreement found by [`cross_check()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+    /// Two runs output different checksums
+    Mismatch(Mismatch),
+    /// A run output a checksum that does not match the file checksums that it
+    /// reported
+    Inconsistent(Inconsistency),
+    /// The file checksums reported by a run do not form a valid tree, e.g.,
+    /// because the same file was reported twice
+    InvalidFiles {
+        name: String,
+        source: ChecksumTreeError,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Mismatch(m) => write!(f, "{m}"),
+            Divergence::Inconsistent(i) => write!(f, "{i}"),
+            Divergence::InvalidFiles { name, source } => {
+                write!(f, "{name} reported invalid file checksums: {source}")
+            }
+        }
+    }
+}
+
+/// Two runs that output different checksums, along with the first directory
+/// in which their file checksums differ.
+///
+/// When displayed, the differing entries of the directory are shown as a
+/// unified diff from the left run to the right run in the manifest format
+/// accepted by the `combine` subcommand: `<md5> <size> <path>` for files and
+/// `<checksum> <path>` for directories.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// The name of the first run
+    pub left: String,
+    /// The checksum output by the first run
+    pub left_checksum: String,
+    /// The name of the second run
+    pub right: String,
+    /// The checksum output by the second run
+    pub right_checksum: String,
+    /// The first directory in which the file checksums reported by the runs
+    /// differ, or `None` if they reported the same file checksums or if
+    /// either run's file checksums were invalid
+    pub first_difference: Option<DirDiff>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} output {}, but {} output {}",
+            self.left, self.left_checksum, self.right, self.right_checksum
+        )?;
+        let Some(diff) = &self.first_difference else {
+            return Ok(());
+        };
+        match &diff.dir {
+            Some(dir) => write!(f, "\nFirst differing directory: {dir}")?,
+            None => write!(f, "\nFirst differing directory: <root>")?,
+        }
+        write!(f, "\n--- {}\n+++ {}", self.left, self.right)?;
+        for entry in &diff.entries {
+            if let Some(left) = &entry.left {
+                write!(f, "\n-{}", ManifestLine(left))?;
+            }
+            if let Some(right) = &entry.right {
+                write!(f, "\n+{}", ManifestLine(right))?;
+            }
+        }
+        match diff.identical {
+            0 => (),
+            1 => write!(f, "\n(1 identical entry omitted)")?,
+            n => write!(f, "\n({n} identical entries omitted)")?,
+        }
+        Ok(())
+    }
+}
+
+/// The differences between two runs' listings of a directory
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirDiff {
+    /// The directory, or `None` for the root of the Zarr
+    pub dir: Option<EntryPath>,
+    /// The entries of the directory that differ between the runs, sorted by
+    /// name
+    pub entries: Vec<EntryDiff>,
+    /// The number of entries of the directory that are the same in both runs
+    pub identical: usize,
+}
+
+/// A directory entry that differs between two runs
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntryDiff {
+    /// The name of the entry
+    pub name: String,
+    /// The entry's checksum in the left run, or `None` if it is absent
+    pub left: Option<EntryChecksum>,
+    /// The entry's checksum in the right run, or `None` if it is absent
+    pub right: Option<EntryChecksum>,
+}
+
+/// Find the first directory in which `left` and `right` differ.  Starting at
+/// the root, this descends into the first differing entry (in order of name)
+/// as long as it is a directory in both trees, so the directory returned is
+/// one in which a file or the type or presence of an entry differs.
+fn first_difference(left: &ChecksumTree, right: &ChecksumTree) -> Option<DirDiff> {
+    let mut dir = None;
+    loop {
+        let left_entries = listing(left, dir.as_ref());
+        let right_entries = listing(right, dir.as_ref());
+        let names = left_entries
+            .keys()
+            .chain(right_entries.keys())
+            .collect::<BTreeSet<_>>();
+        let mut entries = Vec::new();
+        let mut identical = 0;
+        for name in names {
+            let l = left_entries.get(name);
+            let r = right_entries.get(name);
+            if l == r {
+                identical += 1;
+            } else {
+                entries.push(EntryDiff {
+                    name: name.clone(),
+                    left: l.cloned(),
+                    right: r.cloned(),
+                });
+            }
+        }
+        match entries.first() {
+            None => return None,
+            Some(EntryDiff {
+                left: Some(EntryChecksum::Directory(sub)),
+                right: Some(EntryChecksum::Directory(_)),
+                ..
+            }) => dir = Some(sub.relpath().clone()),
+            Some(_) => {
+                return Some(DirDiff {
+                    dir,
+                    entries,
+                    identical,
+                })
+            }
+        }
+    }
+}
+
+/// Return the entries of the directory `dir` in `tree`, keyed by name
+fn listing(tree: &ChecksumTree, dir: Option<&EntryPath>) -> BTreeMap<String, EntryChecksum> {
+    tree.entries(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| (e.name().to_string(), e))
+        .collect()
+}
+
+/// A run whose output checksum does not match the file checksums that it
+/// reported
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Inconsistency {
+    /// The name of the run
+    pub name: String,
+    /// The checksum output by the run
+    pub checksum: String,
+    /// The checksum computed from the file checksums reported by the run
+    pub from_files: String,
+    /// The first directory (in path order) that the run reported collapsing
+    /// to a checksum that does not match the file checksums within, and
+    /// within which no other directory was collapsed incorrectly
+    pub directory: Option<BadCollapse>,
+}
+
+impl Inconsistency {
+    /// Check `run` against `tree`, which must have been built from the run's
+    /// file checksums
+    fn check(run: &Run, tree: &ChecksumTree) -> Option<Inconsistency> {
+        let from_files = tree.checksum();
+        if run.checksum == from_files {
+            return None;
+        }
+        let mut bad = run
+            .directories
+            .iter()
+            .filter_map(|dir| {
+                let expected = dir_checksum(tree, dir.relpath());
+                // Empty directories (including those containing only empty
+                // directories) do not appear in trees built from files.
+                let mismatched = match &expected {
+                    Some(cs) => *cs != dir.checksum(),
+                    None => dir.file_count() > 0,
+                };
+                mismatched.then(|| BadCollapse {
+                    reported: dir.clone(),
+                    from_files: expected,
+                })
+            })
+            .collect::<Vec<_>>();
+        // Sort the root first, followed by the other directories in path
+        // order, so that each directory is immediately followed by its
+        // descendants.
+        bad.sort_unstable_by(|a, b| {
+            let (a, b) = (a.reported.relpath(), b.reported.relpath());
+            (!is_root(a), a).cmp(&(!is_root(b), b))
+        });
+        let mut directory = None;
+        for (i, bc) in bad.iter().enumerate() {
+            let path = bc.reported.relpath();
+            let has_bad_descendant = bad.get(i + 1).is_some_and(|next| {
+                is_root(path) || next.reported.relpath().parents().any(|p| p == *path)
+            });
+            if !has_bad_descendant {
+                directory = Some(bc.clone());
+                break;
+            }
+        }
+        Some(Inconsistency {
+            name: run.name.clone(),
+            checksum: run.checksum.clone(),
+            from_files,
+            directory,
+        })
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} output {}, but the file checksums it reported give {}",
+            self.name, self.checksum, self.from_files
+        )?;
+        if let Some(bc) = &self.directory {
+            write!(
+                f,
+                "\n{} computed {} for directory {}, but ",
+                self.name,
+                bc.reported.checksum(),
+                bc.reported.relpath(),
+            )?;
+            match &bc.from_files {
+                Some(cs) => write!(f, "the file checksums within give {cs}")?,
+                None => write!(f, "no files were reported within it")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A directory checksum reported by a run that does not match the file
+/// checksums reported by the same run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BadCollapse {
+    /// The directory checksum reported by the run
+    pub reported: DirChecksum,
+    /// The checksum computed from the file checksums within the directory, or
+    /// `None` if no files were reported within it
+    pub from_files: Option<String>,
+}
+
+/// Compute the checksum of the directory at `path` in `tree`
+fn dir_checksum(tree: &ChecksumTree, path: &EntryPath) -> Option<String> {
+    if let Some(entries) = tree.entries(Some(path)) {
+        let mut ds = Dirsummer::new(path.clone());
+        ds.extend(entries);
+        Some(ds.checksum().into_checksum())
+    } else if is_root(path) {
+        Some(tree.checksum())
+    } else {
+        None
+    }
+}
+
+/// Test whether `path` is the path that walkers use for the root of the Zarr
+/// when reporting its checksum
+fn is_root(path: &EntryPath) -> bool {
+    path.parent().is_none() && path.file_name() == "<root>"
+}
+
+/// Adapter for displaying an [`EntryChecksum`] as a line of a manifest for
+/// the `combine` subcommand
+struct ManifestLine<'a>(&'a EntryChecksum);
+
+impl fmt::Display for ManifestLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            EntryChecksum::File(fc) => {
+                write!(f, "{} {} {}", fc.checksum(), fc.size(), fc.relpath())
+            }
+            EntryChecksum::Directory(dc) => write!(f, "{} {}", dc.checksum(), dc.relpath()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::compile_checksum;
+    use crate::walkers::recursive_checksum;
+    use crate::zarr::Zarr;
+    use assert_matches::assert_matches;
+
+    fn file(path: &str, digest: &str, size: u64) -> FileChecksum {
+        FileChecksum::new(path.try_into().unwrap(), digest.parse().unwrap(), size)
+    }
+
+    fn sample_files() -> Vec<FileChecksum> {
+        vec![
+            file("arr_0/.zarray", "9e30a0a1a465e24220d4132fdd544634", 315),
+            file("arr_0/0", "ed4e934a474f1d2096846c6248f18c00", 431),
+            file("arr_1/.zarray", "9e30a0a1a465e24220d4132fdd544634", 315),
+            file("arr_1/0", "fba4dee03a51bde314e9713b00284a93", 431),
+            file(".zgroup", "e20297935e73dd0154104d4ea53040ab", 24),
+        ]
+    }
+
+    fn run(name: &str, files: Vec<FileChecksum>) -> Run {
+        Run {
+            name: name.into(),
+            checksum: compile_checksum(files.iter().cloned()).unwrap(),
+            files,
+            directories: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let recorder = Recorder::new();
+        let zarr = Zarr::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample.zarr"
+        ))
+        .progress(recorder.clone());
+        let checksum = recursive_checksum(&zarr).unwrap();
+        let mut r = recorder.into_run("recursive".into(), checksum);
+        assert_eq!(r.checksum, "4313ab36412db2981c3ed391b38604d6-5--1516");
+        r.files
+            .sort_unstable_by(|a, b| a.relpath().cmp(b.relpath()));
+        let mut expected = sample_files();
+        expected.sort_unstable_by(|a, b| a.relpath().cmp(b.relpath()));
+        assert_eq!(r.files, expected);
+        assert_eq!(r.directories.len(), 3);
+        assert!(cross_check(std::slice::from_ref(&r)).is_empty());
+    }
+
+    #[test]
+    fn test_agreement() {
+        let mut reversed = sample_files();
+        reversed.reverse();
+        let runs = [run("a", sample_files()), run("b", reversed)];
+        assert_eq!(cross_check(&runs), Vec::new());
+    }
+
+    #[test]
+    fn test_file_mismatch() {
+        let mut files = sample_files();
+        files[3] = file("arr_1/0", "00000000000000000000000000000000", 431);
+        let runs = [
+            run("a", sample_files()),
+            run("b", sample_files()),
+            run("c", files),
+        ];
+        let divergences = cross_check(&runs);
+        assert_matches!(divergences.as_slice(), [Divergence::Mismatch(m)] => {
+            assert_eq!(m.left, "a");
+            assert_eq!(m.right, "c");
+            let diff = m.first_difference.as_ref().unwrap();
+            assert_eq!(diff.dir.as_ref().unwrap().to_string(), "arr_1");
+            assert_eq!(diff.entries.len(), 1);
+            assert_eq!(diff.identical, 1);
+        });
+        assert_eq!(
+            divergences[0].to_string(),
+            concat!(
+                "a output 4313ab36412db2981c3ed391b38604d6-5--1516, but c output ",
+                "739dad3019fb8dba77a7b9441966cabf-5--1516\n",
+                "First differing directory: arr_1\n",
+                "--- a\n",
+                "+++ c\n",
+                "-fba4dee03a51bde314e9713b00284a93 431 arr_1/0\n",
+                "+00000000000000000000000000000000 431 arr_1/0\n",
+                "(1 identical entry omitted)",
+            )
+        );
+    }
+
+    #[test]
+    fn test_missing_and_type_changed_entries() {
+        let mut files = sample_files();
+        files.remove(4);
+        files.truncate(2);
+        files.push(file("arr_1", "d41d8cd98f00b204e9800998ecf8427e", 0));
+        let runs = [run("a", sample_files()), run("b", files)];
+        let divergences = cross_check(&runs);
+        assert_matches!(divergences.as_slice(), [Divergence::Mismatch(m)] => {
+            let diff = m.first_difference.as_ref().unwrap();
+            assert_eq!(diff.dir, None);
+            assert_eq!(diff.identical, 1);
+            assert_eq!(
+                diff.entries.iter().map(|e| (e.name.as_str(), e.left.is_some(), e.right.as_ref().map(EntryChecksum::is_dir))).collect::<Vec<_>>(),
+                [(".zgroup", true, None), ("arr_1", true, Some(false))]
+            );
+        });
+        let s = divergences[0].to_string();
+        assert!(s.contains("\nFirst differing directory: <root>\n"));
+        assert!(s.contains("\n-7b99a0ad9bd8bb3331657e54755b1a31-2--746 arr_1\n+d41d8cd98f00b204e9800998ecf8427e 0 arr_1\n"));
+    }
+
+    #[test]
+    fn test_inconsistent_collapse() {
+        let mut r = run("a", sample_files());
+        let tree = ChecksumTree::from_files(sample_files()).unwrap();
+        let arr_0 = EntryPath::try_from("arr_0").unwrap();
+        let good = dir_checksum(&tree, &arr_0).unwrap();
+        r.directories = vec![
+            DirChecksum::from_checksum(arr_0, &good).unwrap(),
+            DirChecksum::from_checksum(
+                "arr_1".try_into().unwrap(),
+                "00000000000000000000000000000000-2--746",
+            )
+            .unwrap(),
+            DirChecksum::from_checksum(
+                "<root>".try_into().unwrap(),
+                "11111111111111111111111111111111-5--1516",
+            )
+            .unwrap(),
+            DirChecksum::from_checksum(
+                "empty".try_into().unwrap(),
+                "d41d8cd98f00b204e9800998ecf8427e-0--0",
+            )
+            .unwrap(),
+        ];
+        r.checksum = String::from("11111111111111111111111111111111-5--1516");
+        let divergences = cross_check(&[r]);
+        assert_matches!(divergences.as_slice(), [Divergence::Inconsistent(i)] => {
+            assert_eq!(i.from_files, "4313ab36412db2981c3ed391b38604d6-5--1516");
+            let bc = i.directory.as_ref().unwrap();
+            assert_eq!(bc.reported.relpath().to_string(), "arr_1");
+            assert_eq!(
+                bc.from_files.as_deref(),
+                Some("7b99a0ad9bd8bb3331657e54755b1a31-2--746")
+            );
+        });
+        assert_eq!(
+            divergences[0].to_string(),
+            concat!(
+                "a output 11111111111111111111111111111111-5--1516, but the file ",
+                "checksums it reported give 4313ab36412db2981c3ed391b38604d6-5--1516\n",
+                "a computed 00000000000000000000000000000000-2--746 for directory ",
+                "arr_1, but the file checksums within give ",
+                "7b99a0ad9bd8bb3331657e54755b1a31-2--746",
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let mut files = sample_files();
+        files.push(files[0].clone());
+        let mut r = run("b", sample_files());
+        r.files = files;
+        let divergences = cross_check(&[run("a", sample_files()), r]);
+        assert_matches!(
+            divergences.as_slice(),
+            [Divergence::InvalidFiles { name, source: ChecksumTreeError::DoubleAdd { .. } }] => {
+                assert_eq!(name, "b");
+            }
+        );
+    }
+}
@@ -1,6 +1,7 @@
 //! Error types
 use crate::zarr::EntryPath;
 use std::path::PathBuf;
+use std::process::ExitStatus;
 use thiserror::Error;
 
 /// Error returned when something goes wrong while interacting with the
@@ -18,6 +19,18 @@ pub enum FSError {
     #[error("final component of path {path:?} is not valid UTF-8")]
     UndecodableName { path: PathBuf },
 
+    /// Returned when a file's size, modification time, or status change time
+    /// changed while its contents were being digested, and the Zarr's
+    /// [`ModifiedPolicy`][crate::zarr::ModifiedPolicy] does not permit
+    /// continuing
+    #[error("file {} was modified while it was being read", .path.display())]
+    ModifiedDuringRead { path: PathBuf },
+
+    /// Returned when an operation is aborted because the traversal was
+    /// cancelled or its deadline passed
+    #[error("operation cancelled")]
+    Cancelled,
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
@@ -41,15 +54,52 @@ pub enum ChecksumTreeError {
         /// The path of the node that would have been added
         path: EntryPath,
     },
+
+    /// Returned when a node would be added to a `ChecksumTree` beneath a
+    /// directory whose checksum was grafted on with
+    /// [`add_directory()`][crate::checksum::ChecksumTree::add_directory], or
+    /// when a directory would be grafted at a path that already has
+    /// file-level detail
+    #[error("directory {path:?} has both a precomputed checksum and file-level detail")]
+    GraftConflict {
+        /// The path of the precomputed directory
+        path: EntryPath,
+    },
+
+    /// Returned when a node would be added to a
+    /// [`SortedChecksumBuilder`][crate::checksum::SortedChecksumBuilder]
+    /// after a node whose path sorts after it
+    #[error("file {path:?} added out of order after {previous:?}")]
+    OutOfOrder {
+        /// The path of the node that would have been added
+        path: EntryPath,
+        /// The path of the previously-added node
+        previous: EntryPath,
+    },
 }
 
-/// An enum of [`ChecksumTreeError`] and [`FSError`]
+/// An enum of [`ChecksumTreeError`] and [`FSError`], plus cancellation
 #[derive(Debug, Error)]
 pub enum ChecksumError {
     #[error(transparent)]
     ChecksumTreeError(#[from] ChecksumTreeError),
     #[error(transparent)]
-    FSError(#[from] FSError),
+    FSError(FSError),
+    /// Returned when checksumming was cancelled via a
+    /// [`CancelToken`][crate::cancel::CancelToken] or deadline
+    #[error("checksumming was cancelled")]
+    Cancelled,
+}
+
+impl From<FSError> for ChecksumError {
+    /// Converts [`FSError::Cancelled`] to [`ChecksumError::Cancelled`] and
+    /// wraps all other errors in [`ChecksumError::FSError`]
+    fn from(e: FSError) -> ChecksumError {
+        match e {
+            FSError::Cancelled => ChecksumError::Cancelled,
+            e => ChecksumError::FSError(e),
+        }
+    }
 }
 
 /// Error returned when trying to construct an [`EntryPath`] from an invalid,
@@ -63,3 +113,56 @@ pub struct EntryPathError(pub PathBuf);
 #[derive(Clone, Debug, Eq, Error, PartialEq)]
 #[error("invalid path name: {0:?}")]
 pub struct EntryNameError(pub String);
+
+/// Error returned when trying to parse an
+/// [`Md5Digest`][crate::checksum::Md5Digest] from a string that is not 32
+/// hexadecimal digits
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("invalid MD5 digest: {0:?}")]
+pub struct DigestParseError(pub String);
+
+/// Error returned when trying to parse a
+/// [`DirChecksum`][crate::checksum::DirChecksum] from a string that is not of
+/// the form `{digest}-{file_count}--{size}`
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("invalid directory checksum: {0:?}")]
+pub struct DirChecksumParseError(pub String);
+
+/// Error returned when trying to parse a
+/// [`ShapeSpec`][crate::generate::ShapeSpec] or
+/// [`SizeDistribution`][crate::generate::SizeDistribution] from an invalid
+/// string
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("invalid shape spec: {0}")]
+pub struct ShapeSpecError(pub String);
+
+/// Error returned when a [`Benchmark`][crate::bench::Benchmark] fails
+#[derive(Debug, Error)]
+pub enum BenchError {
+    /// Returned when a target could not be run
+    #[error("failed to run {name}: {source}")]
+    Spawn {
+        name: String,
+        source: std::io::Error,
+    },
+
+    /// Returned when a target exits unsuccessfully
+    #[error("{name} exited unsuccessfully: {status}")]
+    Failed { name: String, status: ExitStatus },
+
+    /// Returned when a target prints different output on different runs
+    #[error("{name} output {got:?} after previously outputting {expected:?}")]
+    Inconsistent {
+        name: String,
+        expected: String,
+        got: String,
+    },
+
+    /// Returned when dropping the tree from the page cache fails
+    #[error("failed to drop files from the page cache: {0}")]
+    Evict(FSError),
+
+    /// Returned when scanning the tree fails
+    #[error(transparent)]
+    Scan(#[from] FSError),
+}
@@ -0,0 +1,12 @@
+//! Internal functions exposed for the fuzz targets in `fuzz/`.  This module
+//! is only compiled when building with `--cfg fuzzing`, as `cargo fuzz` does.
+use crate::checksum::json::write_json_str;
+
+/// Serialize `s` as a JSON string the same way that entry names are
+/// serialized in the JSON listings from which directory checksums are
+/// computed
+pub fn json_str(s: &str) -> String {
+    let mut buf = String::new();
+    write_json_str(s, &mut buf).expect("formatting a String should not fail");
+    buf
+}
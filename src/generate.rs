@@ -0,0 +1,848 @@
+//! Deterministic generation of synthetic Zarr directory trees
+//!
+//! [`generate()`] builds a directory tree whose layout is described by a
+//! [`ShapeSpec`] and whose file sizes & contents are drawn from a
+//! pseudorandom number generator seeded with a caller-supplied seed.  The same
+//! spec & seed always produce the same tree, byte for byte, on any platform,
+//! making the results suitable as reproducible workloads for comparing
+//! implementations and catching regressions.
+//!
+//! The generated tree is laid out like a Zarr v2 hierarchy: the root and every
+//! directory down to `depth` levels below it is a group containing a
+//! `.zgroup` file and `fanout` subdirectories, and the directories at the
+//! bottom level are arrays, each containing a `.zarray` file and a number of
+//! chunk files of random bytes.  Empty directories, dotfiles, and symlinks are
+//! then scattered through the groups & arrays.
+use crate::errors::ShapeSpecError;
+use fs_err::File;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The dotfiles & dot-directories placed in generated trees, together with
+/// whether each one is created as a directory
+static DOTFILES: &[(&str, bool)] = &[
+    (".dandi", true),
+    (".datalad", true),
+    (".git", true),
+    (".gitattributes", false),
+    (".gitmodules", false),
+];
+
+/// Contents of the `.zgroup` file in each group
+static ZGROUP: &str = "{\"zarr_format\": 2}\n";
+
+/// A description of the shape of a tree to generate
+///
+/// A `ShapeSpec` can be parsed from & displayed as a comma-separated list of
+/// `key=value` pairs, e.g., `depth=2,fanout=4,chunks=16-64,sizes=log-uniform:1-1M`.
+/// The keys are:
+///
+/// - `depth` — [`ShapeSpec::depth`]
+/// - `fanout` — [`ShapeSpec::fanout`]
+/// - `chunks` — [`ShapeSpec::chunks`], given as either a single count or a
+///   range of the form `MIN-MAX`
+/// - `sizes` — [`ShapeSpec::sizes`]; see [`SizeDistribution`] for the syntax
+/// - `empty-dirs` — [`ShapeSpec::empty_dirs`]
+/// - `dotfiles` — [`ShapeSpec::dotfiles`]
+/// - `symlinks` — [`ShapeSpec::symlinks`]
+///
+/// When parsing, keys that are not given take their values from
+/// [`ShapeSpec::default()`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ShapeSpec {
+    /// The number of levels of groups above the arrays.  At depth 0, the root
+    /// directory is itself an array.
+    pub depth: u32,
+
+    /// The number of subdirectories in each group
+    pub fanout: usize,
+
+    /// The range from which the number of chunk files in each array is chosen
+    /// uniformly at random
+    pub chunks: RangeInclusive<usize>,
+
+    /// The distribution from which the size of each chunk file is drawn
+    pub sizes: SizeDistribution,
+
+    /// The number of empty directories to create in randomly-chosen groups &
+    /// arrays
+    pub empty_dirs: usize,
+
+    /// The number of dotfiles & dot-directories that are excluded from
+    /// checksumming under [`Zarr::exclude_dotfiles()`][crate::zarr::Zarr::exclude_dotfiles]
+    /// to create in randomly-chosen groups & arrays.  Each group or array
+    /// contains at most one of each such name, so fewer may be created if the
+    /// tree is small.
+    pub dotfiles: usize,
+
+    /// The number of symlinks to create in randomly-chosen groups & arrays.
+    /// Even-numbered symlinks point to files and odd-numbered symlinks point
+    /// to arrays.  Symlinks to arrays are only placed in groups, never in
+    /// arrays, so that following symlinks can never lead to a cycle; if
+    /// there are no groups, all symlinks point to files.  All symlinks use
+    /// relative paths.
+    pub symlinks: usize,
+}
+
+impl Default for ShapeSpec {
+    /// Two levels of groups with four subdirectories each, for sixteen arrays
+    /// of 64 chunks whose sizes are log-uniformly distributed between one
+    /// byte and one MiB, with no empty directories, dotfiles, or symlinks
+    fn default() -> ShapeSpec {
+        ShapeSpec {
+            depth: 2,
+            fanout: 4,
+            chunks: 64..=64,
+            sizes: SizeDistribution::LogUniform {
+                min: 1,
+                max: 1 << 20,
+            },
+            empty_dirs: 0,
+            dotfiles: 0,
+            symlinks: 0,
+        }
+    }
+}
+
+impl fmt::Display for ShapeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "depth={},fanout={},chunks=", self.depth, self.fanout)?;
+        if self.chunks.start() == self.chunks.end() {
+            write!(f, "{}", self.chunks.start())?;
+        } else {
+            write!(f, "{}-{}", self.chunks.start(), self.chunks.end())?;
+        }
+        write!(
+            f,
+            ",sizes={},empty-dirs={},dotfiles={},symlinks={}",
+            self.sizes, self.empty_dirs, self.dotfiles, self.symlinks
+        )
+    }
+}
+
+impl FromStr for ShapeSpec {
+    type Err = ShapeSpecError;
+
+    fn from_str(s: &str) -> Result<ShapeSpec, ShapeSpecError> {
+        let mut spec = ShapeSpec::default();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let Some((key, value)) = item.split_once('=') else {
+                return Err(ShapeSpecError(format!("expected key=value, got {item:?}")));
+            };
+            match key.trim() {
+                "depth" => spec.depth = parse_count(value)?,
+                "fanout" => spec.fanout = parse_count(value)?,
+                "chunks" => {
+                    let (min, max) = parse_range(value, parse_count)?;
+                    spec.chunks = min..=max;
+                }
+                "sizes" => spec.sizes = value.parse()?,
+                "empty-dirs" => spec.empty_dirs = parse_count(value)?,
+                "dotfiles" => spec.dotfiles = parse_count(value)?,
+                "symlinks" => spec.symlinks = parse_count(value)?,
+                k => return Err(ShapeSpecError(format!("unknown key {k:?}"))),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// A distribution of file sizes
+///
+/// A `SizeDistribution` can be parsed from & displayed as one of the
+/// following, where sizes are numbers of bytes optionally followed by `K`,
+/// `M`, or `G` (case-insensitive) to multiply them by 1024, 1024², or 1024³:
+///
+/// - `fixed:SIZE` — [`SizeDistribution::Fixed`]
+/// - `uniform:MIN-MAX` — [`SizeDistribution::Uniform`]
+/// - `log-uniform:MIN-MAX` — [`SizeDistribution::LogUniform`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SizeDistribution {
+    /// Every file has the given size
+    Fixed(u64),
+
+    /// Sizes are distributed uniformly between `min` and `max`, inclusive
+    Uniform { min: u64, max: u64 },
+
+    /// Sizes between `min` and `max` (inclusive) are distributed so that each
+    /// power-of-two size class is equally likely, giving many small files and
+    /// a few large ones
+    LogUniform { min: u64, max: u64 },
+}
+
+impl SizeDistribution {
+    fn sample(&self, rng: &mut Rng) -> u64 {
+        match *self {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform { min, max } => rng.range(min, max),
+            SizeDistribution::LogUniform { min, max } => {
+                // Pick a bit length, then pick uniformly among the sizes in
+                // range with that bit length.
+                let bits = rng.range(bit_length(min), bit_length(max));
+                let (lo, hi) = match bits {
+                    0 => (0, 0),
+                    64 => (1 << 63, u64::MAX),
+                    b => (1 << (b - 1), (1 << b) - 1),
+                };
+                rng.range(lo.max(min), hi.min(max))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "fixed:{size}"),
+            SizeDistribution::Uniform { min, max } => write!(f, "uniform:{min}-{max}"),
+            SizeDistribution::LogUniform { min, max } => write!(f, "log-uniform:{min}-{max}"),
+        }
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = ShapeSpecError;
+
+    fn from_str(s: &str) -> Result<SizeDistribution, ShapeSpecError> {
+        let Some((kind, params)) = s.split_once(':') else {
+            return Err(ShapeSpecError(format!(
+                "expected size distribution of the form KIND:PARAMS, got {s:?}"
+            )));
+        };
+        match kind.trim() {
+            "fixed" => Ok(SizeDistribution::Fixed(parse_size(params)?)),
+            "uniform" => {
+                let (min, max) = parse_range(params, parse_size)?;
+                Ok(SizeDistribution::Uniform { min, max })
+            }
+            "log-uniform" => {
+                let (min, max) = parse_range(params, parse_size)?;
+                Ok(SizeDistribution::LogUniform { min, max })
+            }
+            k => Err(ShapeSpecError(format!("unknown size distribution {k:?}"))),
+        }
+    }
+}
+
+/// Statistics on a tree created by [`generate()`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Summary {
+    /// The number of directories created, not counting the root
+    pub directories: u64,
+
+    /// The number of regular files created
+    pub files: u64,
+
+    /// The number of symlinks created
+    pub symlinks: u64,
+
+    /// The total size in bytes of all regular files created
+    pub bytes: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files ({} bytes), {} directories, {} symlinks",
+            self.files, self.bytes, self.directories, self.symlinks
+        )
+    }
+}
+
+/// Create a directory tree at `root` with the shape described by `spec`,
+/// using `seed` to seed the pseudorandom choices of file sizes, file
+/// contents, and the placement of extra entries
+///
+/// `root` must not already exist; its parent directories are created if
+/// necessary.
+///
+/// Symlinks are only created on Unix and Windows; on other platforms, an
+/// error is returned if `spec.symlinks` is nonzero.
+pub fn generate<P: AsRef<Path>>(root: P, spec: &ShapeSpec, seed: u64) -> io::Result<Summary> {
+    let root = root.as_ref();
+    if let Some(parent) = root.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs_err::create_dir_all(parent)?;
+    }
+    fs_err::create_dir(root)?;
+    let mut generator = Generator {
+        root,
+        spec,
+        rng: Rng::new(seed),
+        containers: vec![Vec::new()],
+        arrays: Vec::new(),
+        files: Vec::new(),
+        summary: Summary::default(),
+    };
+    generator.run()?;
+    Ok(generator.summary)
+}
+
+/// A path relative to the root of the tree being generated, as a list of
+/// components
+type RelPath = Vec<String>;
+
+/// The state of a call to [`generate()`]
+#[derive(Debug)]
+struct Generator<'a> {
+    root: &'a Path,
+    spec: &'a ShapeSpec,
+    rng: Rng,
+    /// All groups & arrays, starting with the root
+    containers: Vec<RelPath>,
+    /// Indices into `containers` of all arrays
+    arrays: Vec<usize>,
+    /// All regular files outside of dotfiles, for use as symlink targets
+    files: Vec<RelPath>,
+    summary: Summary,
+}
+
+impl Generator<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        let mut level = vec![0];
+        for d in 0..self.spec.depth {
+            let mut next = Vec::with_capacity(level.len().saturating_mul(self.spec.fanout));
+            for &g in &level {
+                self.write_file(child(&self.containers[g], ".zgroup"), ZGROUP.as_bytes())?;
+                for i in 0..self.spec.fanout {
+                    let name = if d + 1 == self.spec.depth {
+                        format!("arr{i}")
+                    } else {
+                        format!("grp{i}")
+                    };
+                    let path = child(&self.containers[g], &name);
+                    self.mkdir(&path)?;
+                    next.push(self.containers.len());
+                    self.containers.push(path);
+                }
+            }
+            level = next;
+        }
+        for a in level {
+            self.populate_array(a)?;
+            self.arrays.push(a);
+        }
+        self.add_empty_dirs()?;
+        self.add_dotfiles()?;
+        self.add_symlinks()?;
+        Ok(())
+    }
+
+    /// Write the `.zarray` file and chunk files for the array at
+    /// `containers[a]`
+    fn populate_array(&mut self, a: usize) -> io::Result<()> {
+        let count = self.rng.range(
+            *self.spec.chunks.start() as u64,
+            *self.spec.chunks.end() as u64,
+        );
+        let mut cols = 1;
+        while cols * cols < count {
+            cols += 1;
+        }
+        let rows = count.div_ceil(cols);
+        let zarray = format!(
+            concat!(
+                "{{\"chunks\": [1, 1], \"compressor\": null, \"dtype\": \"|u1\", ",
+                "\"fill_value\": 0, \"filters\": null, \"order\": \"C\", ",
+                "\"shape\": [{}, {}], \"zarr_format\": 2}}\n"
+            ),
+            rows, cols
+        );
+        self.write_file(child(&self.containers[a], ".zarray"), zarray.as_bytes())?;
+        for i in 0..count {
+            let path = child(&self.containers[a], &format!("{}.{}", i / cols, i % cols));
+            let size = self.spec.sizes.sample(&mut self.rng);
+            self.write_random(&path, size)?;
+            self.files.push(path);
+        }
+        Ok(())
+    }
+
+    fn add_empty_dirs(&mut self) -> io::Result<()> {
+        for i in 0..self.spec.empty_dirs {
+            let c = self.rng.index(self.containers.len());
+            self.mkdir(&child(&self.containers[c], &format!("empty{i}")))?;
+        }
+        Ok(())
+    }
+
+    fn add_dotfiles(&mut self) -> io::Result<()> {
+        let n = self.containers.len();
+        let mut used = HashSet::new();
+        for i in 0..self.spec.dotfiles {
+            let (name, is_dir) = DOTFILES[i % DOTFILES.len()];
+            let start = self.rng.index(n);
+            let Some(c) = (0..n)
+                .map(|j| (start + j) % n)
+                .find(|&c| !used.contains(&(c, name)))
+            else {
+                continue;
+            };
+            used.insert((c, name));
+            let path = child(&self.containers[c], name);
+            let size = self.rng.range(1, 64);
+            if is_dir {
+                self.mkdir(&path)?;
+                self.write_random(&child(&path, "config"), size)?;
+            } else {
+                self.write_random(&path, size)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_symlinks(&mut self) -> io::Result<()> {
+        // Only groups may contain symlinks to arrays, and arrays contain no
+        // directory symlinks, so no chain of symlinks can loop back on itself.
+        let groups = (0..self.containers.len())
+            .filter(|g| !self.arrays.contains(g))
+            .collect::<Vec<_>>();
+        for i in 0..self.spec.symlinks {
+            let (c, target, is_dir) = if i % 2 == 1 && !groups.is_empty() && !self.arrays.is_empty()
+            {
+                let c = groups[self.rng.index(groups.len())];
+                let a = self.arrays[self.rng.index(self.arrays.len())];
+                (c, self.containers[a].clone(), true)
+            } else {
+                let c = self.rng.index(self.containers.len());
+                (c, self.file_link_target(), false)
+            };
+            let container = &self.containers[c];
+            let mut relative = container.iter().map(|_| "..").collect::<PathBuf>();
+            relative.extend(&target);
+            let link = self.fspath(&child(container, &format!("link{i}")));
+            symlink(&relative, &link, is_dir)?;
+            self.summary.symlinks += 1;
+        }
+        Ok(())
+    }
+
+    /// Choose a file for a symlink to point to
+    fn file_link_target(&mut self) -> RelPath {
+        if self.files.is_empty() {
+            // No chunk files were generated, so link to the root's
+            // metadata file instead
+            let meta = if self.spec.depth == 0 {
+                ".zarray"
+            } else {
+                ".zgroup"
+            };
+            vec![String::from(meta)]
+        } else {
+            self.files[self.rng.index(self.files.len())].clone()
+        }
+    }
+
+    fn fspath(&self, path: &[String]) -> PathBuf {
+        let mut p = self.root.to_path_buf();
+        p.extend(path);
+        p
+    }
+
+    fn mkdir(&mut self, path: &[String]) -> io::Result<()> {
+        fs_err::create_dir(self.fspath(path))?;
+        self.summary.directories += 1;
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: RelPath, contents: &[u8]) -> io::Result<()> {
+        fs_err::write(self.fspath(&path), contents)?;
+        self.summary.files += 1;
+        self.summary.bytes += contents.len() as u64;
+        Ok(())
+    }
+
+    /// Create a file of `size` pseudorandom bytes
+    fn write_random(&mut self, path: &[String], size: u64) -> io::Result<()> {
+        let mut fp = File::create(self.fspath(path))?;
+        let mut buf = vec![0u8; 1 << 16];
+        let mut remaining = size;
+        while remaining > 0 {
+            let n = usize::try_from(remaining).map_or(buf.len(), |r| r.min(buf.len()));
+            let chunk = &mut buf[..n];
+            self.rng.fill(chunk);
+            fp.write_all(chunk)?;
+            remaining -= n as u64;
+        }
+        self.summary.files += 1;
+        self.summary.bytes += size;
+        Ok(())
+    }
+}
+
+fn child(parent: &[String], name: &str) -> RelPath {
+    let mut path = parent.to_vec();
+    path.push(name.to_owned());
+    path
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    fs_err::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        fs_err::os::windows::fs::symlink_dir(target, link)
+    } else {
+        fs_err::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "cannot create symlink {}: unsupported platform",
+            link.display()
+        ),
+    ))
+}
+
+/// The number of bits needed to represent `n`
+fn bit_length(n: u64) -> u64 {
+    u64::from(u64::BITS - n.leading_zeros())
+}
+
+fn parse_count<T: FromStr>(s: &str) -> Result<T, ShapeSpecError> {
+    s.trim()
+        .parse()
+        .map_err(|_| ShapeSpecError(format!("invalid count {s:?}")))
+}
+
+/// Parse a size in bytes with an optional binary multiplier suffix
+fn parse_size(s: &str) -> Result<u64, ShapeSpecError> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| ShapeSpecError(format!("invalid size {s:?}")))
+}
+
+/// Parse either a single value or a range of the form `MIN-MAX`
+fn parse_range<T, F>(s: &str, parse: F) -> Result<(T, T), ShapeSpecError>
+where
+    T: Copy + PartialOrd,
+    F: Fn(&str) -> Result<T, ShapeSpecError>,
+{
+    let (min, max) = if let Some((min, max)) = s.split_once('-') {
+        (parse(min)?, parse(max)?)
+    } else {
+        let n = parse(s)?;
+        (n, n)
+    };
+    if min > max {
+        return Err(ShapeSpecError(format!(
+            "range {s:?} has minimum greater than maximum"
+        )));
+    }
+    Ok((min, max))
+}
+
+/// The `SplitMix64` pseudorandom number generator.  This is implemented here
+/// rather than taken from a library so that generated trees do not change
+/// when dependencies are upgraded.
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Return a value between `min` and `max`, inclusive
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        match (max - min).checked_add(1) {
+            Some(span) => min + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+
+    /// Return an index into a nonempty slice of length `len`
+    fn index(&mut self, len: usize) -> usize {
+        let i = self.range(0, len.saturating_sub(1) as u64);
+        usize::try_from(i).unwrap_or_default()
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::walkers::recursive_checksum;
+    use crate::zarr::{is_excluded_dotfile, Zarr};
+    use rstest::rstest;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dotfiles_are_excluded() {
+        for &(name, _) in DOTFILES {
+            assert!(is_excluded_dotfile(name), "{name} is not excluded");
+        }
+    }
+
+    #[rstest]
+    #[case("", ShapeSpec::default())]
+    #[case("depth=0,chunks=3-5", ShapeSpec {depth: 0, chunks: 3..=5, ..ShapeSpec::default()})]
+    #[case(
+        " fanout = 2 , sizes=uniform:1k-2M, symlinks=3,",
+        ShapeSpec {
+            fanout: 2,
+            sizes: SizeDistribution::Uniform { min: 1024, max: 2 << 20 },
+            symlinks: 3,
+            ..ShapeSpec::default()
+        }
+    )]
+    #[case(
+        "sizes=fixed:1G,empty-dirs=4,dotfiles=5",
+        ShapeSpec {
+            sizes: SizeDistribution::Fixed(1 << 30),
+            empty_dirs: 4,
+            dotfiles: 5,
+            ..ShapeSpec::default()
+        }
+    )]
+    fn test_parse_spec(#[case] s: &str, #[case] spec: ShapeSpec) {
+        assert_eq!(s.parse::<ShapeSpec>().unwrap(), spec);
+        assert_eq!(spec.to_string().parse::<ShapeSpec>().unwrap(), spec);
+    }
+
+    #[rstest]
+    #[case("depth")]
+    #[case("depth=-1")]
+    #[case("width=3")]
+    #[case("chunks=5-3")]
+    #[case("sizes=uniform")]
+    #[case("sizes=normal:1-2")]
+    #[case("sizes=fixed:12X")]
+    #[case("sizes=fixed:99999999999G")]
+    fn test_parse_bad_spec(#[case] s: &str) {
+        assert!(s.parse::<ShapeSpec>().is_err());
+    }
+
+    #[test]
+    fn test_default_spec_display() {
+        assert_eq!(
+            ShapeSpec::default().to_string(),
+            "depth=2,fanout=4,chunks=64,sizes=log-uniform:1-1048576,empty-dirs=0,dotfiles=0,symlinks=0"
+        );
+    }
+
+    #[rstest]
+    #[case(SizeDistribution::Fixed(42))]
+    #[case(SizeDistribution::Uniform { min: 10, max: 20 })]
+    #[case(SizeDistribution::LogUniform { min: 0, max: 5 })]
+    #[case(SizeDistribution::LogUniform { min: 100, max: 1 << 20 })]
+    #[case(SizeDistribution::LogUniform { min: 0, max: u64::MAX })]
+    fn test_sample_in_range(#[case] dist: SizeDistribution) {
+        let (min, max) = match dist {
+            SizeDistribution::Fixed(n) => (n, n),
+            SizeDistribution::Uniform { min, max } | SizeDistribution::LogUniform { min, max } => {
+                (min, max)
+            }
+        };
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let n = dist.sample(&mut rng);
+            assert!((min..=max).contains(&n), "{n} not in {min}..={max}");
+        }
+    }
+
+    #[test]
+    fn test_log_uniform_skews_small() {
+        let dist = SizeDistribution::LogUniform {
+            min: 1,
+            max: 1 << 20,
+        };
+        let mut rng = Rng::new(0);
+        let small = std::iter::repeat_with(|| dist.sample(&mut rng))
+            .take(1000)
+            .filter(|&n| n < 1024)
+            .count();
+        // About half of the 21 size classes are below 1 KiB.
+        assert!((400..600).contains(&small), "{small} small files");
+    }
+
+    /// Recursively list everything under `root` as `(path, kind)` pairs,
+    /// where `kind` describes the entry & its contents
+    fn snapshot(root: &Path) -> Vec<(PathBuf, String)> {
+        let mut entries = Vec::new();
+        let mut stack = vec![root.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                let md = fs::symlink_metadata(&path).unwrap();
+                let kind = if md.is_symlink() {
+                    format!("link to {}", fs::read_link(&path).unwrap().display())
+                } else if md.is_dir() {
+                    stack.push(path.clone());
+                    String::from("dir")
+                } else {
+                    hex::encode(fs::read(&path).unwrap())
+                };
+                entries.push((path.strip_prefix(root).unwrap().to_path_buf(), kind));
+            }
+        }
+        entries.sort();
+        entries
+    }
+
+    fn small_spec() -> ShapeSpec {
+        ShapeSpec {
+            depth: 2,
+            fanout: 3,
+            chunks: 0..=6,
+            sizes: SizeDistribution::LogUniform { min: 0, max: 4096 },
+            empty_dirs: 3,
+            dotfiles: 4,
+            symlinks: if cfg!(any(unix, windows)) { 4 } else { 0 },
+        }
+    }
+
+    #[test]
+    fn test_generate_deterministic() {
+        let tmp = tempdir().unwrap();
+        let spec = small_spec();
+        let s1 = generate(tmp.path().join("a"), &spec, 42).unwrap();
+        let s2 = generate(tmp.path().join("b"), &spec, 42).unwrap();
+        let s3 = generate(tmp.path().join("c"), &spec, 43).unwrap();
+        assert_eq!(s1, s2);
+        let a = snapshot(&tmp.path().join("a"));
+        assert_eq!(a, snapshot(&tmp.path().join("b")));
+        assert_ne!(a, snapshot(&tmp.path().join("c")));
+        assert_ne!(s1, s3);
+    }
+
+    #[test]
+    fn test_generate_counts() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("zarr");
+        let spec = ShapeSpec {
+            depth: 2,
+            fanout: 3,
+            chunks: 5..=5,
+            sizes: SizeDistribution::Fixed(10),
+            ..ShapeSpec::default()
+        };
+        let summary = generate(&root, &spec, 0).unwrap();
+        // 4 groups + 9 arrays × (1 + 5 chunks)
+        assert_eq!(summary.files, 58);
+        assert_eq!(summary.directories, 12);
+        assert_eq!(summary.symlinks, 0);
+        let checksum = recursive_checksum(&Zarr::new(&root)).unwrap();
+        assert!(
+            checksum.ends_with(&format!("-{}--{}", summary.files, summary.bytes)),
+            "{checksum} does not match {summary}"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("grp1").join("arr2").join(".zarray")).unwrap(),
+            concat!(
+                "{\"chunks\": [1, 1], \"compressor\": null, \"dtype\": \"|u1\", ",
+                "\"fill_value\": 0, \"filters\": null, \"order\": \"C\", ",
+                "\"shape\": [2, 3], \"zarr_format\": 2}\n"
+            )
+        );
+        assert_eq!(fs::metadata(root.join("grp1/arr2/1.1")).unwrap().len(), 10);
+        assert!(!root.join("grp1/arr2/1.2").exists());
+    }
+
+    #[test]
+    fn test_generate_extras() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("zarr");
+        let spec = small_spec();
+        let summary = generate(&root, &spec, 0).unwrap();
+        let entries = snapshot(&root);
+        let names = |pred: &dyn Fn(&Path, &str) -> bool| {
+            entries.iter().filter(|(p, kind)| pred(p, kind)).count()
+        };
+        assert_eq!(
+            names(&|p, kind| kind == "dir"
+                && p.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("empty")),
+            3
+        );
+        assert_eq!(names(&|p, _| is_excluded_dotfile(p)), 4);
+        let links = names(&|_, kind| kind.starts_with("link to "));
+        assert_eq!(links, spec.symlinks);
+        assert_eq!(summary.symlinks, spec.symlinks as u64);
+        // Every symlink resolves.
+        for (p, _) in &entries {
+            assert!(root.join(p).exists(), "{} is dangling", p.display());
+        }
+    }
+
+    #[test]
+    fn test_generate_depth_zero() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("zarr");
+        let spec = ShapeSpec {
+            depth: 0,
+            chunks: 0..=0,
+            symlinks: if cfg!(any(unix, windows)) { 2 } else { 0 },
+            ..ShapeSpec::default()
+        };
+        generate(&root, &spec, 0).unwrap();
+        assert!(root.join(".zarray").exists());
+        if cfg!(any(unix, windows)) {
+            assert_eq!(
+                fs::read_link(root.join("link1")).unwrap(),
+                Path::new(".zarray")
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_existing_root() {
+        let tmp = tempdir().unwrap();
+        let r = generate(tmp.path(), &ShapeSpec::default(), 0);
+        assert_eq!(r.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_generate_stable() {
+        // Guard against accidental changes to the generator, which would
+        // invalidate previously-recorded benchmark results
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().join("zarr");
+        let spec = ShapeSpec {
+            symlinks: 0,
+            ..small_spec()
+        };
+        generate(&root, &spec, 2024).unwrap();
+        assert_eq!(
+            recursive_checksum(&Zarr::new(&root)).unwrap(),
+            "84832a931943c37fffd96d5cfb97ad1c-50--15514"
+        );
+    }
+}
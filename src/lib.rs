@@ -1,6 +1,13 @@
 //! Various implementations of Dandi Zarr checksumming
+pub mod bench;
+pub mod cancel;
 pub mod checksum;
+pub mod crosscheck;
 pub mod errors;
+#[cfg(fuzzing)]
+pub mod fuzzing;
+pub mod generate;
+pub mod progress;
 mod util;
 pub mod walkers;
 pub mod zarr;
@@ -1,24 +1,81 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, FromArgMatches, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader};
+use std::iter;
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread::available_parallelism;
-use tokio::runtime::Builder;
-use zarr_checksum_gallery::zarr::Zarr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::runtime::{Builder, Runtime};
+use zarr_checksum_gallery::bench::{Benchmark, CacheState, Report, Target};
+use zarr_checksum_gallery::checksum::{ChecksumTree, DirChecksum, FileChecksum, Md5Digest};
+use zarr_checksum_gallery::crosscheck::{cross_check, Recorder};
+use zarr_checksum_gallery::generate::ShapeSpec;
+use zarr_checksum_gallery::progress::{scan, ProgressObserver};
+use zarr_checksum_gallery::zarr::{
+    EntryPath, HashBackend, JobOrder, ModifiedPolicy, Zarr, DEFAULT_MMAP_THRESHOLD,
+};
 use zarr_checksum_gallery::*;
 
 /// Compute the Dandi Zarr checksum for a directory
 #[derive(Clone, Debug, Eq, Parser, PartialEq)]
 #[command(version)]
 struct Arguments {
+    /// Also run the given implementation on the same directory and compare
+    /// its results with those of the selected subcommand.  May be given
+    /// multiple times.  If any implementations disagree, a diff of the first
+    /// directory in which they differ is printed, and the program exits with
+    /// status 3.
+    #[arg(long, value_enum, value_name = "IMPL", conflicts_with = "progress")]
+    cross_check: Vec<Implementation>,
+
     /// Show DEBUG log messages
     #[arg(long)]
     debug: bool,
 
+    /// Only read the contents of each hardlinked file once
+    #[arg(long)]
+    dedup_hardlinks: bool,
+
     /// Exclude special dotfiles from checksumming
     #[arg(short = 'E', long)]
     exclude_dotfiles: bool,
 
+    /// How to read file contents for hashing
+    #[arg(long, value_enum, default_value_t = Backend::Buffered)]
+    hash_backend: Backend,
+
+    /// The order in which the `fastio` and `collapsio-arc` implementations
+    /// take jobs from their shared job stack.  Ignored by `fastio
+    /// --max-in-flight`, which always uses `lifo`.
+    #[arg(long, value_enum, default_value_t = Order::Lifo)]
+    job_order: Order,
+
+    /// Under `--hash-backend auto`, memory-map files of at least this many
+    /// bytes and read smaller files into a buffer
+    #[arg(long, default_value_t = DEFAULT_MMAP_THRESHOLD, value_name = "BYTES")]
+    mmap_threshold: u64,
+
+    /// What to do when a file is modified while it is being read
+    #[arg(long, value_enum, default_value_t = OnModified::Fail)]
+    on_modified: OnModified,
+
+    /// Display a progress bar with throughput and an estimated time of
+    /// completion on stderr
+    #[arg(long)]
+    progress: bool,
+
+    /// Abort checksumming if it has not completed after the given number of
+    /// seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
     /// Show TRACE log messages
     #[arg(long)]
     trace: bool,
@@ -28,8 +85,253 @@ struct Arguments {
     command: Command,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum Backend {
+    /// Read files into a buffer
+    Buffered,
+    /// Memory-map files
+    Mmap,
+    /// Memory-map files of at least `--mmap-threshold` bytes and read smaller
+    /// files into a buffer
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum Cache {
+    /// Run with file contents left in the page cache by previous runs
+    Warm,
+    /// Drop file contents from the page cache before each run (Linux only)
+    Cold,
+    /// Do both warm and cold runs
+    Both,
+}
+
+impl Cache {
+    fn states(self) -> &'static [CacheState] {
+        match self {
+            Cache::Warm => &[CacheState::Warm],
+            Cache::Cold => &[CacheState::Cold],
+            Cache::Both => &[CacheState::Warm, CacheState::Cold],
+        }
+    }
+}
+
+/// An implementation that can be run by the `bench` subcommand or
+/// `--cross-check`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum Implementation {
+    BreadthFirst,
+    CollapsioArc,
+    CollapsioArcStealing,
+    CollapsioMpsc,
+    DepthFirst,
+    Fastasync,
+    Fastio,
+    FastioBounded,
+    FastioStealing,
+    Multibuffer,
+    Offload,
+    Pipelined,
+    Rayon,
+    Recursive,
+    #[cfg(target_os = "linux")]
+    Uring,
+}
+
+impl Implementation {
+    fn name(self) -> String {
+        value_name(&self)
+    }
+
+    /// Return the subcommand & options for running this implementation on
+    /// `dirpath`, using `threads` threads if given
+    fn args(self, threads: Option<NonZeroUsize>, dirpath: &Path) -> Vec<OsString> {
+        let (args, thread_opts): (&[&str], &[&str]) = match self {
+            Implementation::BreadthFirst => (&["breadth-first"], &[]),
+            Implementation::CollapsioArc => (&["collapsio-arc"], &["--threads"]),
+            Implementation::CollapsioArcStealing => {
+                (&["collapsio-arc", "--work-stealing"], &["--threads"])
+            }
+            Implementation::CollapsioMpsc => (&["collapsio-mpsc"], &["--threads"]),
+            Implementation::DepthFirst => (&["depth-first"], &[]),
+            Implementation::Fastasync => (&["fastasync"], &["--threads"]),
+            Implementation::Fastio => (&["fastio"], &["--threads"]),
+            Implementation::FastioBounded => {
+                (&["fastio", "--max-in-flight", "1024"], &["--threads"])
+            }
+            Implementation::FastioStealing => (&["fastio", "--work-stealing"], &["--threads"]),
+            Implementation::Multibuffer => (&["multibuffer"], &["--threads"]),
+            Implementation::Offload => (&["offload"], &["--threads"]),
+            Implementation::Pipelined => (&["pipelined"], &["--readers", "--hashers"]),
+            Implementation::Rayon => (&["rayon"], &["--threads"]),
+            Implementation::Recursive => (&["recursive"], &[]),
+            #[cfg(target_os = "linux")]
+            Implementation::Uring => (&["uring"], &["--threads"]),
+        };
+        let mut argv = args.iter().map(OsString::from).collect::<Vec<_>>();
+        if let Some(threads) = threads {
+            for &opt in thread_opts {
+                argv.push(opt.into());
+                argv.push(threads.to_string().into());
+            }
+        }
+        argv.push(dirpath.into());
+        argv
+    }
+
+    /// Return the subcommand for running this implementation on `dirpath`
+    /// with default options
+    fn command(self, dirpath: &Path) -> TraversalCommand {
+        let argv =
+            iter::once(OsString::from("zarr-checksum-gallery")).chain(self.args(None, dirpath));
+        let matches =
+            TraversalCommand::augment_subcommands(clap::Command::new("zarr-checksum-gallery"))
+                .try_get_matches_from(argv)
+                .expect("implementation arguments should be valid");
+        TraversalCommand::from_arg_matches(&matches)
+            .expect("implementation arguments should be valid")
+    }
+}
+
+/// Return the name of a [`ValueEnum`] value as it appears on the command line
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .expect("value should not be skipped")
+        .get_name()
+        .to_owned()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum Order {
+    /// Take the most recently added job first
+    Lifo,
+    /// Take the least recently added job first
+    Fifo,
+    /// Take directories first, then files in decreasing order of size
+    LargestFirst,
+}
+
+impl From<Order> for JobOrder {
+    fn from(value: Order) -> JobOrder {
+        match value {
+            Order::Lifo => JobOrder::Lifo,
+            Order::Fifo => JobOrder::Fifo,
+            Order::LargestFirst => JobOrder::LargestFirst,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum OnModified {
+    /// Read the file again, failing if it is still being modified after
+    /// several attempts
+    Retry,
+    /// Emit a warning and use the checksum anyway
+    Warn,
+    /// Fail with an error
+    Fail,
+}
+
+impl From<OnModified> for ModifiedPolicy {
+    fn from(value: OnModified) -> ModifiedPolicy {
+        match value {
+            OnModified::Retry => ModifiedPolicy::Retry,
+            OnModified::Warn => ModifiedPolicy::Warn,
+            OnModified::Fail => ModifiedPolicy::Fail,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Subcommand)]
 enum Command {
+    /// Benchmark implementations on a directory.
+    ///
+    /// Each implementation is run as a subprocess of this program with the
+    /// same global options, first `--warmups` times without measurement and
+    /// then `--runs` times while measuring wall-clock time and peak memory
+    /// usage.
+    Bench(BenchArgs),
+    /// Compute the checksum for a Zarr from manifests of file checksums
+    /// and/or precomputed subdirectory checksums.
+    ///
+    /// Each line of a manifest is either `<md5> <size> <path>` for a file or
+    /// `<checksum> <path>` for a directory, where `<checksum>` is of the form
+    /// `<md5>-<file_count>--<size>` and paths are relative to the root of the
+    /// Zarr.  The contents of all manifests are merged, and it is an error
+    /// for two manifests to provide the same path.
+    Combine {
+        /// Manifests to read.  If no manifests are given, or if "-" is given,
+        /// standard input is read.
+        manifests: Vec<PathBuf>,
+    },
+    /// Create a synthetic Zarr directory tree with pseudorandom contents.
+    ///
+    /// The same seed & shape always produce the same tree.  The shape is a
+    /// comma-separated list of `key=value` pairs, where the keys are `depth`
+    /// (levels of groups above the arrays), `fanout` (subdirectories per
+    /// group), `chunks` (chunk files per array, as `N` or `MIN-MAX`), `sizes`
+    /// (chunk file sizes, as `fixed:SIZE`, `uniform:MIN-MAX`, or
+    /// `log-uniform:MIN-MAX`, with optional `K`/`M`/`G` suffixes),
+    /// `empty-dirs`, `dotfiles`, and `symlinks`.  Omitted keys take their
+    /// default values.
+    Generate {
+        /// Seed for the pseudorandom number generator
+        #[arg(short, long, default_value_t = 0)]
+        seed: u64,
+
+        /// The shape of the tree to generate
+        #[arg(long, default_value_t = ShapeSpec::default(), value_name = "SPEC")]
+        shape: ShapeSpec,
+
+        /// Path at which to create the tree.  It must not already exist.
+        dirpath: PathBuf,
+    },
+    #[command(flatten)]
+    Traversal(TraversalCommand),
+}
+
+/// Options for the `bench` subcommand
+#[derive(Clone, Debug, Eq, PartialEq, Args)]
+struct BenchArgs {
+    /// An implementation to benchmark.  May be given multiple times.  If
+    /// not given, all implementations are benchmarked.
+    #[arg(short, long = "implementation", value_enum, value_name = "IMPL")]
+    implementations: Vec<Implementation>,
+
+    /// Set the number of threads used by each implementation that takes a
+    /// `--threads` option (or `--readers` & `--hashers` for `pipelined`)
+    #[arg(short, long)]
+    threads: Option<NonZeroUsize>,
+
+    /// Set the number of unmeasured runs of each implementation
+    #[arg(short, long, default_value_t = 1)]
+    warmups: usize,
+
+    /// Set the number of measured runs of each implementation
+    #[arg(short, long, default_value = "5")]
+    runs: NonZeroUsize,
+
+    /// Whether to run with a warm page cache, a cold page cache, or both
+    #[arg(long, value_enum, default_value_t = Cache::Warm)]
+    cache: Cache,
+
+    /// Output the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Run the implementations with the given `zarr-checksum-gallery`
+    /// executable (e.g., a build of an earlier commit) instead of this one
+    #[arg(long, value_name = "PATH")]
+    program: Option<PathBuf>,
+
+    /// Path to the directory to checksum
+    dirpath: PathBuf,
+}
+
+/// A subcommand that traverses a directory
+#[derive(Clone, Debug, Eq, PartialEq, Subcommand)]
+enum TraversalCommand {
     /// Traverse the directory breadth-first and build a tree of checksums
     BreadthFirst {
         /// Path to the directory to checksum
@@ -43,6 +345,11 @@ enum Command {
         #[arg(short, long, default_value_t = default_jobs())]
         threads: NonZeroUsize,
 
+        /// Give each thread its own queue of jobs and let idle threads steal
+        /// jobs from the others instead of sharing a single stack
+        #[arg(short = 'S', long)]
+        work_stealing: bool,
+
         /// Path to the directory to checksum
         dirpath: PathBuf,
     },
@@ -82,6 +389,107 @@ enum Command {
         #[arg(short, long, default_value_t = default_jobs())]
         threads: NonZeroUsize,
 
+        /// Instead of building a tree of checksums, compute each directory's
+        /// checksum as soon as possible, and stop listing directories while
+        /// this many listed entries are still waiting to be processed
+        #[arg(
+            short = 'M',
+            long,
+            value_name = "INT",
+            conflicts_with = "work_stealing"
+        )]
+        max_in_flight: Option<NonZeroUsize>,
+
+        /// Give each thread its own queue of jobs and let idle threads steal
+        /// jobs from the others instead of sharing a single stack
+        #[arg(short = 'S', long)]
+        work_stealing: bool,
+
+        /// Path to the directory to checksum
+        dirpath: PathBuf,
+    },
+    /// List the directory on the main thread while reading files on a pool of
+    /// worker threads that hash small files in batches using multi-buffer
+    /// SIMD MD5, building a tree of checksums
+    Multibuffer {
+        /// Set the number of worker threads to use
+        #[arg(short, long, default_value_t = default_jobs())]
+        threads: NonZeroUsize,
+
+        /// Set the number of small files each thread hashes together
+        #[arg(short, long, default_value = "64")]
+        batch_size: NonZeroUsize,
+
+        /// Files no larger than this many bytes are read into memory and
+        /// hashed in batches
+        #[arg(long, default_value_t = 65536, value_name = "BYTES")]
+        small_file_size: u64,
+
+        /// Path to the directory to checksum
+        dirpath: PathBuf,
+    },
+    /// Do an asynchronous directory traversal, hashing each file in a single
+    /// call to the async runtime's blocking thread pool, and build a tree of
+    /// checksums
+    Offload {
+        /// Set the number of threads for the async runtime to use for running
+        /// tasks
+        #[arg(short, long, default_value_t = default_jobs())]
+        threads: NonZeroUsize,
+
+        /// Set the maximum number of files & directories to have open at once
+        #[arg(short = 'j', long, default_value = "128")]
+        max_open: NonZeroUsize,
+
+        /// Set the size in bytes of the buffer each file is read into
+        #[arg(short = 'B', long, default_value = "1048576", value_name = "BYTES")]
+        buffer_size: NonZeroUsize,
+
+        /// Path to the directory to checksum
+        dirpath: PathBuf,
+    },
+    /// List the directory on the main thread while reading files on a pool of
+    /// reader threads and hashing large files on a separate pool of hashing
+    /// threads, building a tree of checksums
+    Pipelined {
+        /// Set the number of reader threads to use
+        #[arg(short, long, default_value_t = default_jobs())]
+        readers: NonZeroUsize,
+
+        /// Set the number of hashing threads to use
+        #[arg(long, default_value_t = default_jobs())]
+        hashers: NonZeroUsize,
+
+        /// Set the size in bytes of the buffers that large files are read
+        /// into
+        #[arg(short = 'B', long, default_value = "1048576", value_name = "BYTES")]
+        buffer_size: NonZeroUsize,
+
+        /// Set the maximum number of buffers per large file that may be read
+        /// ahead of hashing
+        #[arg(short = 'Q', long, default_value = "4")]
+        queue_depth: NonZeroUsize,
+
+        /// Files no larger than this many bytes are read in one go and hashed
+        /// by the reader thread
+        #[arg(long, default_value_t = 65536, value_name = "BYTES")]
+        small_file_size: u64,
+
+        /// Do not advise the kernel that large files will be read
+        /// sequentially
+        #[arg(long)]
+        no_fadvise: bool,
+
+        /// Path to the directory to checksum
+        dirpath: PathBuf,
+    },
+    /// Traverse & checksum the directory recursively, checksumming the
+    /// entries of each directory in parallel on a rayon thread pool
+    Rayon {
+        /// Set the number of threads to use
+        #[arg(short, long, default_value_t = default_jobs())]
+        threads: NonZeroUsize,
+
         /// Path to the directory to checksum
         dirpath: PathBuf,
     },
@@ -96,13 +504,70 @@ enum Command {
         #[arg(short, long, default_value_t = default_jobs())]
         threads: NonZeroUsize,
 
+        /// Path to the directory to checksum
+        dirpath: PathBuf,
+    },
+    /// Read files using `io_uring` and hash them on a pool of threads, building
+    /// a tree of checksums
+    #[cfg(target_os = "linux")]
+    Uring {
+        /// Set the number of hashing threads to use
+        #[arg(short, long, default_value_t = default_jobs())]
+        threads: NonZeroUsize,
+
+        /// Set the maximum number of files to read concurrently
+        #[arg(short = 'Q', long, default_value = "64")]
+        queue_depth: NonZeroUsize,
+
         /// Path to the directory to checksum
         dirpath: PathBuf,
     },
 }
 
+impl TraversalCommand {
+    /// Return the directory to traverse
+    fn dirpath(&self) -> &Path {
+        match self {
+            TraversalCommand::BreadthFirst { dirpath }
+            | TraversalCommand::CollapsioArc { dirpath, .. }
+            | TraversalCommand::CollapsioMpsc { dirpath, .. }
+            | TraversalCommand::DepthFirst { dirpath }
+            | TraversalCommand::Fastasync { dirpath, .. }
+            | TraversalCommand::Fastio { dirpath, .. }
+            | TraversalCommand::Multibuffer { dirpath, .. }
+            | TraversalCommand::Offload { dirpath, .. }
+            | TraversalCommand::Pipelined { dirpath, .. }
+            | TraversalCommand::Rayon { dirpath, .. }
+            | TraversalCommand::Recursive { dirpath }
+            | TraversalCommand::Tree { dirpath, .. } => dirpath,
+            #[cfg(target_os = "linux")]
+            TraversalCommand::Uring { dirpath, .. } => dirpath,
+        }
+    }
+
+    /// Return the name of the subcommand
+    fn name(&self) -> &'static str {
+        match self {
+            TraversalCommand::BreadthFirst { .. } => "breadth-first",
+            TraversalCommand::CollapsioArc { .. } => "collapsio-arc",
+            TraversalCommand::CollapsioMpsc { .. } => "collapsio-mpsc",
+            TraversalCommand::DepthFirst { .. } => "depth-first",
+            TraversalCommand::Fastasync { .. } => "fastasync",
+            TraversalCommand::Fastio { .. } => "fastio",
+            TraversalCommand::Multibuffer { .. } => "multibuffer",
+            TraversalCommand::Offload { .. } => "offload",
+            TraversalCommand::Pipelined { .. } => "pipelined",
+            TraversalCommand::Rayon { .. } => "rayon",
+            TraversalCommand::Recursive { .. } => "recursive",
+            TraversalCommand::Tree { .. } => "tree",
+            #[cfg(target_os = "linux")]
+            TraversalCommand::Uring { .. } => "uring",
+        }
+    }
+}
+
 impl Arguments {
-    fn run(self) -> Result<String, ChecksumError> {
+    fn init_logging(&self) {
         let log_level = if self.trace {
             log::LevelFilter::Trace
         } else if self.debug {
@@ -115,80 +580,535 @@ impl Arguments {
                 out.finish(format_args!("[{:<5}] {}", record.level(), message));
             })
             .level(log_level)
-            .chain(std::io::stderr())
+            .chain(io::stderr())
             .apply()
             .expect("no other logger should have been previously initialized");
-        match self.command {
-            Command::BreadthFirst { dirpath } => {
-                breadth_first_checksum(&Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles))
+    }
+
+    fn hash_backend(&self) -> HashBackend {
+        match self.hash_backend {
+            Backend::Buffered => HashBackend::Buffered,
+            Backend::Mmap => HashBackend::Mmap,
+            Backend::Auto => HashBackend::Auto {
+                threshold: self.mmap_threshold,
+            },
+        }
+    }
+
+    /// Return a [`Zarr`] for `dirpath` configured according to the global
+    /// options
+    fn zarr(&self, dirpath: &Path) -> Zarr {
+        Zarr::new(dirpath)
+            .exclude_dotfiles(self.exclude_dotfiles)
+            .dedup_hardlinks(self.dedup_hardlinks)
+            .on_modified(self.on_modified.into())
+            .hash_backend(self.hash_backend())
+            .job_order(self.job_order.into())
+    }
+
+    /// Return the global options that affect checksumming, for passing to a
+    /// subprocess.  Options left at their default values are omitted so that
+    /// the subprocess may be a build that predates them.
+    fn global_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if self.dedup_hardlinks {
+            args.push("--dedup-hardlinks".into());
+        }
+        if self.exclude_dotfiles {
+            args.push("--exclude-dotfiles".into());
+        }
+        if self.hash_backend != Backend::Buffered {
+            args.push(format!("--hash-backend={}", value_name(&self.hash_backend)).into());
+        }
+        if self.job_order != Order::Lifo {
+            args.push(format!("--job-order={}", value_name(&self.job_order)).into());
+        }
+        if self.mmap_threshold != DEFAULT_MMAP_THRESHOLD {
+            args.push(format!("--mmap-threshold={}", self.mmap_threshold).into());
+        }
+        if self.on_modified != OnModified::Fail {
+            args.push(format!("--on-modified={}", value_name(&self.on_modified)).into());
+        }
+        args
+    }
+
+    /// Run the traversal subcommand `command` and return the output
+    fn run(&self, command: &TraversalCommand) -> Result<String, ChecksumError> {
+        let mut zarr = self.zarr(command.dirpath());
+        if let Some(secs) = self.timeout {
+            zarr = zarr.deadline(Instant::now() + Duration::from_secs(secs));
+        }
+        let display = if self.progress {
+            let display = ProgressDisplay::start(&zarr)?;
+            zarr = zarr.progress(display.clone());
+            Some(display)
+        } else {
+            None
+        };
+        let r = checksum(command, &zarr);
+        if let Some(display) = display {
+            display.finish();
+        }
+        r
+    }
+
+    /// Run the traversal subcommand `command` followed by each
+    /// `--cross-check` implementation on the same directory, and compare the
+    /// results.  If they all agree, the checksum is printed; otherwise, the
+    /// disagreements are printed, and the program exits with status 3.
+    fn run_cross_check(&self, command: &TraversalCommand) -> ExitCode {
+        let dirpath = command.dirpath();
+        let commands = iter::once((command.name().to_owned(), command.clone())).chain(
+            self.cross_check
+                .iter()
+                .map(|imp| (imp.name(), imp.command(dirpath))),
+        );
+        let mut runs = Vec::with_capacity(self.cross_check.len() + 1);
+        for (name, command) in commands {
+            eprintln!("Running {name} ...");
+            let recorder = Recorder::new();
+            let mut zarr = self.zarr(dirpath).progress(recorder.clone());
+            if let Some(secs) = self.timeout {
+                zarr = zarr.deadline(Instant::now() + Duration::from_secs(secs));
             }
-            Command::CollapsioArc { threads, dirpath } => collapsio_arc_checksum(
-                &Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles),
-                threads,
-            ),
-            Command::CollapsioMpsc { threads, dirpath } => collapsio_mpsc_checksum(
-                &Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles),
-                threads,
-            ),
-            Command::DepthFirst { dirpath } => {
-                depth_first_checksum(&Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles))
+            match checksum(&command, &zarr) {
+                Ok(checksum) => runs.push(recorder.into_run(name, checksum)),
+                Err(e) => {
+                    eprintln!("{name}: {}", error_message(e));
+                    return ExitCode::FAILURE;
+                }
             }
-            Command::Fastasync {
+        }
+        let divergences = cross_check(&runs);
+        if divergences.is_empty() {
+            if let Some(first) = runs.first() {
+                println!("{}", first.checksum);
+            }
+            ExitCode::SUCCESS
+        } else {
+            let report = divergences
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            println!("{}", report.join("\n\n"));
+            ExitCode::from(3)
+        }
+    }
+}
+
+/// Run the traversal subcommand `command` on `zarr` and return the output
+fn checksum(command: &TraversalCommand, zarr: &Zarr) -> Result<String, ChecksumError> {
+    match *command {
+        TraversalCommand::BreadthFirst { .. } => breadth_first_checksum(zarr),
+        TraversalCommand::CollapsioArc {
+            threads,
+            work_stealing: false,
+            ..
+        } => collapsio_arc_checksum(zarr, threads),
+        TraversalCommand::CollapsioArc {
+            threads,
+            work_stealing: true,
+            ..
+        } => collapsio_arc_stealing_checksum(zarr, threads),
+        TraversalCommand::CollapsioMpsc { threads, .. } => collapsio_mpsc_checksum(zarr, threads),
+        TraversalCommand::DepthFirst { .. } => depth_first_checksum(zarr),
+        TraversalCommand::Fastasync {
+            threads, workers, ..
+        } => build_runtime(threads).block_on(fastasync_checksum(zarr, workers)),
+        TraversalCommand::Fastio {
+            threads,
+            max_in_flight: None,
+            work_stealing: false,
+            ..
+        } => fastio_checksum(zarr, threads),
+        TraversalCommand::Fastio {
+            threads,
+            max_in_flight: None,
+            work_stealing: true,
+            ..
+        } => fastio_stealing_checksum(zarr, threads),
+        TraversalCommand::Fastio {
+            threads,
+            max_in_flight: Some(max_in_flight),
+            ..
+        } => fastio_bounded_checksum(zarr, threads, max_in_flight),
+        TraversalCommand::Multibuffer {
+            threads,
+            batch_size,
+            small_file_size,
+            ..
+        } => multibuffer_checksum(
+            zarr,
+            MultiBufferConfig {
                 threads,
-                workers,
-                dirpath,
-            } => {
-                let threads = threads.get();
-                let rt = if threads > 1 {
-                    Builder::new_multi_thread()
-                        .worker_threads(threads)
-                        .enable_all()
-                        .build()
-                        .expect("Buiding a multithreaded tokio runtime should not fail")
-                } else {
-                    Builder::new_current_thread()
-                        .enable_all()
-                        .build()
-                        .expect("Buiding a single-threaded tokio runtime should not fail")
+                small_file_size,
+                batch_size,
+            },
+        ),
+        TraversalCommand::Offload {
+            threads,
+            max_open,
+            buffer_size,
+            ..
+        } => build_runtime(threads).block_on(offload_checksum(zarr, max_open, buffer_size)),
+        TraversalCommand::Pipelined {
+            readers,
+            hashers,
+            buffer_size,
+            queue_depth,
+            small_file_size,
+            no_fadvise,
+            ..
+        } => pipelined_checksum(
+            zarr,
+            PipelineConfig {
+                readers,
+                hashers,
+                buffer_size,
+                queue_depth,
+                small_file_size,
+                fadvise: !no_fadvise,
+            },
+        ),
+        TraversalCommand::Rayon { threads, .. } => rayon_checksum(zarr, threads),
+        TraversalCommand::Recursive { .. } => recursive_checksum(zarr),
+        TraversalCommand::Tree { threads, .. } => {
+            fastio_checksum_tree(zarr, threads).map(|chktree| chktree.into_termtree().to_string())
+        }
+        #[cfg(target_os = "linux")]
+        TraversalCommand::Uring {
+            threads,
+            queue_depth,
+            ..
+        } => uring_checksum(zarr, threads, queue_depth),
+    }
+}
+
+impl Arguments {
+    /// Run the `bench` subcommand, returning the report to print
+    fn bench(&self, bench: &BenchArgs) -> Result<String, BenchError> {
+        let BenchArgs {
+            implementations,
+            threads,
+            warmups,
+            runs,
+            cache,
+            json,
+            program,
+            dirpath,
+        } = bench;
+        let program = match program {
+            Some(p) => p.clone(),
+            None => env::current_exe().map_err(|source| BenchError::Spawn {
+                name: String::from("zarr-checksum-gallery"),
+                source,
+            })?,
+        };
+        let implementations = if implementations.is_empty() {
+            Implementation::value_variants()
+        } else {
+            implementations.as_slice()
+        };
+        let mut states = cache.states().to_vec();
+        if !CacheState::cold_supported() && states.contains(&CacheState::Cold) {
+            log::warn!("Cold-cache runs are not supported on this platform; skipping");
+            states.retain(|&st| st != CacheState::Cold);
+        }
+        let benchmark = Benchmark {
+            zarr: self.zarr(dirpath),
+            warmups: *warmups,
+            runs: runs.get(),
+        };
+        let mut report = benchmark.start_report()?;
+        for &imp in implementations {
+            for &state in &states {
+                let mut args = self.global_args();
+                args.extend(imp.args(*threads, dirpath));
+                let target = Target {
+                    name: imp.name(),
+                    program: program.clone(),
+                    args,
                 };
-                rt.block_on(fastasync_checksum(
-                    &Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles),
-                    workers,
-                ))
+                eprintln!("Benchmarking {} ({}) ...", target.name, cache_name(state));
+                let result = benchmark.run_target(&target, state, report.files, report.bytes)?;
+                report.results.push(result);
             }
-            Command::Fastio { threads, dirpath } => fastio_checksum(
-                &Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles),
-                threads,
-            ),
-            Command::Recursive { dirpath } => {
-                recursive_checksum(&Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles))
+        }
+        if let Some(first) = report.results.first() {
+            for r in &report.results {
+                if r.checksum != first.checksum {
+                    log::warn!(
+                        "{} output {:?}, but {} output {:?}",
+                        r.name,
+                        r.checksum,
+                        first.name,
+                        first.checksum
+                    );
+                }
             }
-            Command::Tree { threads, dirpath } => fastio_checksum_tree(
-                &Zarr::new(dirpath).exclude_dotfiles(self.exclude_dotfiles),
-                threads,
+        }
+        if *json {
+            Ok(serde_json::to_string_pretty(&report).expect("report should be serializable"))
+        } else {
+            Ok(format_report(&report))
+        }
+    }
+}
+
+fn cache_name(state: CacheState) -> &'static str {
+    match state {
+        CacheState::Warm => "warm",
+        CacheState::Cold => "cold",
+    }
+}
+
+/// Format a benchmark report as a table
+fn format_report(report: &Report) -> String {
+    let mut lines = vec![
+        format!(
+            "{} files, {} bytes, {} warmup(s), {} run(s)",
+            report.files, report.bytes, report.warmups, report.runs
+        ),
+        String::new(),
+        format!(
+            "{:<24} {:<5} {:>10} {:>10} {:>10} {:>10} {:>12} {:>10} {:>14}",
+            "implementation",
+            "cache",
+            "mean (s)",
+            "stddev (s)",
+            "min (s)",
+            "max (s)",
+            "files/s",
+            "MB/s",
+            "peak RSS (KiB)",
+        ),
+    ];
+    for r in &report.results {
+        let rss = r
+            .peak_rss
+            .map_or_else(|| String::from("-"), |rss| (rss / 1024).to_string());
+        lines.push(format!(
+            "{:<24} {:<5} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>12.1} {:>10.1} {:>14}",
+            r.name,
+            cache_name(r.cache),
+            r.stats.mean,
+            r.stats.stddev,
+            r.stats.min,
+            r.stats.max,
+            r.files_per_sec,
+            r.mb_per_sec,
+            rss,
+        ));
+    }
+    lines.join("\n")
+}
+
+/// A [`ProgressObserver`] that draws a progress bar on stderr
+#[derive(Clone, Debug)]
+struct ProgressDisplay {
+    bar: ProgressBar,
+    files_done: Arc<AtomicU64>,
+    files_total: u64,
+}
+
+impl ProgressDisplay {
+    /// Pre-scan the Zarr to determine the total number of files & bytes to
+    /// checksum and then start displaying a progress bar
+    fn start(zarr: &Zarr) -> Result<ProgressDisplay, ChecksumError> {
+        let spinner = ProgressBar::new_spinner().with_message("Scanning directory tree ...");
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        let totals = scan(zarr);
+        spinner.finish_and_clear();
+        let totals = totals?;
+        let bar = ProgressBar::new(totals.bytes).with_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta}) {msg}",
             )
-            .map(|chktree| chktree.into_termtree().to_string()),
+            .expect("progress bar template should be valid"),
+        );
+        bar.set_message(format!("0/{} files", totals.files));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        Ok(ProgressDisplay {
+            bar,
+            files_done: Arc::new(AtomicU64::new(0)),
+            files_total: totals.files,
+        })
+    }
+
+    fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+impl ProgressObserver for ProgressDisplay {
+    fn file_hashed(&self, _file: &FileChecksum) {
+        let done = self.files_done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.bar
+            .set_message(format!("{done}/{} files", self.files_total));
+    }
+
+    fn bytes_read(&self, n: u64) {
+        self.bar.inc(n);
+    }
+}
+
+/// Error returned by the `combine` subcommand
+#[derive(Debug, Error)]
+enum CombineError {
+    #[error("{}:{line}: {reason}", .file.display())]
+    Parse {
+        file: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    #[error("{}:{line}: {source}", .file.display())]
+    Add {
+        file: PathBuf,
+        line: usize,
+        source: ChecksumTreeError,
+    },
+    #[error("{}: {source}", .file.display())]
+    Merge {
+        file: PathBuf,
+        source: ChecksumTreeError,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Read each manifest into a [`ChecksumTree`], merge them together, and
+/// return the checksum for the result
+fn combine(manifests: &[PathBuf]) -> Result<String, CombineError> {
+    let stdin = [PathBuf::from("-")];
+    let manifests = if manifests.is_empty() {
+        &stdin
+    } else {
+        manifests
+    };
+    let mut tree = ChecksumTree::new();
+    for file in manifests {
+        let part = if file == Path::new("-") {
+            read_manifest(io::stdin().lock(), Path::new("<stdin>"))?
+        } else {
+            read_manifest(BufReader::new(fs_err::File::open(file)?), file)?
+        };
+        tree.merge(part).map_err(|source| CombineError::Merge {
+            file: file.clone(),
+            source,
+        })?;
+    }
+    Ok(tree.into_checksum())
+}
+
+/// Parse a manifest into a [`ChecksumTree`].  `file` is used for error
+/// messages.
+fn read_manifest<R: BufRead>(reader: R, file: &Path) -> Result<ChecksumTree, CombineError> {
+    let mut tree = ChecksumTree::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
         }
+        let parse_err = |reason: String| CombineError::Parse {
+            file: file.into(),
+            line: i + 1,
+            reason,
+        };
+        let (checksum, rest) = line
+            .split_once(' ')
+            .ok_or_else(|| parse_err(String::from("expected checksum followed by path")))?;
+        let r = if checksum.contains('-') {
+            let path = EntryPath::try_from(rest).map_err(|e| parse_err(e.to_string()))?;
+            let node =
+                DirChecksum::from_checksum(path, checksum).map_err(|e| parse_err(e.to_string()))?;
+            tree.add_directory(node)
+        } else {
+            let (size, path) = rest
+                .split_once(' ')
+                .ok_or_else(|| parse_err(String::from("expected size followed by path")))?;
+            let digest = checksum
+                .parse::<Md5Digest>()
+                .map_err(|e| parse_err(e.to_string()))?;
+            let size = size
+                .parse::<u64>()
+                .map_err(|e| parse_err(format!("invalid size {size:?}: {e}")))?;
+            let path = EntryPath::try_from(path).map_err(|e| parse_err(e.to_string()))?;
+            tree.add_file(FileChecksum::new(path, digest, size))
+        };
+        r.map_err(|source| CombineError::Add {
+            file: file.into(),
+            line: i + 1,
+            source,
+        })?;
     }
+    Ok(tree)
 }
 
 fn main() -> ExitCode {
-    match Arguments::parse().run() {
+    let args = Arguments::parse();
+    args.init_logging();
+    if !args.cross_check.is_empty() {
+        return match &args.command {
+            Command::Traversal(command) if !matches!(command, TraversalCommand::Tree { .. }) => {
+                args.run_cross_check(command)
+            }
+            _ => {
+                eprintln!("--cross-check can only be used with subcommands that output a checksum");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let r = match &args.command {
+        Command::Bench(bench) => args.bench(bench).map_err(|e| e.to_string()),
+        Command::Combine { manifests } => combine(manifests).map_err(|e| e.to_string()),
+        Command::Generate {
+            seed,
+            shape,
+            dirpath,
+        } => generate::generate(dirpath, shape, *seed)
+            .map(|summary| summary.to_string())
+            .map_err(|e| e.to_string()),
+        Command::Traversal(command) => args.run(command).map_err(error_message),
+    };
+    match r {
         Ok(checksum) => {
             println!("{checksum}");
             ExitCode::SUCCESS
         }
-        Err(ChecksumError::ChecksumTreeError(e)) => {
-            eprintln!("INTERNAL ERROR: {e}");
-            ExitCode::FAILURE
-        }
-        Err(ChecksumError::FSError(e)) => {
-            eprintln!("{e}");
+        Err(msg) => {
+            eprintln!("{msg}");
             ExitCode::FAILURE
         }
     }
 }
 
+/// Return the message to show the user for a traversal error
+fn error_message(e: ChecksumError) -> String {
+    match e {
+        ChecksumError::ChecksumTreeError(e) => format!("INTERNAL ERROR: {e}"),
+        ChecksumError::FSError(e) => e.to_string(),
+        ChecksumError::Cancelled => String::from("Checksumming timed out"),
+    }
+}
+
+/// Build a tokio runtime with the given number of worker threads
+fn build_runtime(threads: NonZeroUsize) -> Runtime {
+    let threads = threads.get();
+    if threads > 1 {
+        Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build()
+            .expect("Buiding a multithreaded tokio runtime should not fail")
+    } else {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Buiding a single-threaded tokio runtime should not fail")
+    }
+}
+
 fn default_jobs() -> NonZeroUsize {
     available_parallelism().expect("Could not determine number of available CPUs")
 }
@@ -0,0 +1,115 @@
+//! Hooks for observing the progress of a Zarr traversal
+//!
+//! A [`ProgressObserver`] can be attached to a [`Zarr`] with
+//! [`Zarr::progress()`], after which every implementation in
+//! [`walkers`][crate::walkers] will report events to it as the traversal
+//! proceeds.  Observers are shared between threads and so must be `Send` and
+//! `Sync`; all methods have no-op default implementations.
+use crate::checksum::{DirChecksum, FileChecksum};
+use crate::errors::FSError;
+use crate::zarr::{DirPath, Zarr, ZarrEntry};
+use fs_err::metadata;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Trait for receiving progress events from a Zarr traversal
+pub trait ProgressObserver: fmt::Debug + Send + Sync {
+    /// Called after the entries of a directory have been listed.  `entries`
+    /// is the number of entries found in the directory, not counting excluded
+    /// dotfiles.
+    fn dir_listed(&self, _dir: &DirPath, _entries: usize) {}
+
+    /// Called after the checksum for a file has been computed
+    fn file_hashed(&self, _file: &FileChecksum) {}
+
+    /// Called each time a block of `n` bytes has been read from a file while
+    /// computing its checksum
+    fn bytes_read(&self, _n: u64) {}
+
+    /// Called after the checksum for a directory has been computed from the
+    /// checksums of its entries.  Implementations that build an in-memory
+    /// tree of all file checksums do not emit this event.
+    fn dir_collapsed(&self, _dir: &DirChecksum) {}
+}
+
+/// A [`ProgressObserver`] that ignores all events
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {}
+
+/// Totals gathered by [`scan()`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ScanTotals {
+    /// The number of files in the Zarr
+    pub files: u64,
+    /// The total size in bytes of all files in the Zarr
+    pub bytes: u64,
+}
+
+/// Perform a quick traversal of a Zarr, counting the files within and their
+/// total size without reading any file contents.
+///
+/// This is intended for computing an estimated time of completion for a
+/// subsequent checksumming run.  Any [`ProgressObserver`] attached to `zarr`
+/// is not notified of the scan.  If `zarr` has
+/// [`dedup_hardlinks()`][Zarr::dedup_hardlinks] enabled, the size of each
+/// hardlinked file only counts towards `bytes` once.
+pub fn scan(zarr: &Zarr) -> Result<ScanTotals, FSError> {
+    let zarr = zarr.clone().progress(NoProgress);
+    let mut totals = ScanTotals::default();
+    let mut seen = HashSet::new();
+    let mut stack = vec![zarr.root_dir()];
+    while let Some(zd) = stack.pop() {
+        for entry in zd.iter_entries()? {
+            match entry? {
+                ZarrEntry::File(zf) => {
+                    totals.files += 1;
+                    let md = metadata(zf.path())?;
+                    let first_link = match inode_key(&md) {
+                        Some(key) if zarr.dedups_hardlinks() => seen.insert(key),
+                        _ => true,
+                    };
+                    if first_link {
+                        totals.bytes += md.len();
+                    }
+                }
+                ZarrEntry::Directory(sub) => stack.push(sub),
+            }
+        }
+    }
+    Ok(totals)
+}
+
+/// Return a key identifying the inode described by `md` if it has multiple
+/// hardlinks
+#[cfg(unix)]
+fn inode_key(md: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (md.nlink() > 1).then(|| (md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_key(_md: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scan_sample() {
+        let zarr = Zarr::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample.zarr"
+        ));
+        assert_eq!(
+            scan(&zarr).unwrap(),
+            ScanTotals {
+                files: 5,
+                bytes: 1516
+            }
+        );
+    }
+}
@@ -1,39 +1,211 @@
+mod mmap;
+mod multimd5;
+use crate::cancel::Cancellation;
+use crate::checksum::Md5Digest;
 use crate::errors::FSError;
+use crate::progress::ProgressObserver;
 use fs_err::{tokio::File as TokioFile, File};
 use md5::{Digest, Md5};
+pub(crate) use mmap::md5_file_mmap;
+pub(crate) use multimd5::Md5Engine;
+use std::fmt;
+use std::fs::Metadata;
+use std::io::{ErrorKind, Read};
 use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+use std::time::SystemTime;
 use tokio::io::AsyncReadExt;
 
-/// Compute the MD5 hash of a string (encoded in UTF-8) and return the hash as
-/// a string of lowercase hexadecimal digits
-pub(crate) fn md5_string(s: &str) -> String {
-    hex::encode(Md5::digest(s))
+/// Default size of the buffer passed to [`md5_file()`]
+pub(crate) const BUFFER_SIZE: usize = 8192;
+
+/// A [`fmt::Write`] adapter that feeds everything written to it (encoded in
+/// UTF-8) into an MD5 hasher
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Md5Writer(Md5);
+
+impl Md5Writer {
+    pub(crate) fn new() -> Md5Writer {
+        Md5Writer::default()
+    }
+
+    /// Return the MD5 digest of everything written so far
+    pub(crate) fn finish(self) -> Md5Digest {
+        Md5Digest::from(<[u8; 16]>::from(self.0.finalize()))
+    }
 }
 
-/// Compute the MD5 hash of the contents of the given file, returning a string
-/// of lowercase hexadecimal digits
-pub(crate) fn md5_file<P: AsRef<Path>>(path: P) -> Result<String, FSError> {
-    let path = path.as_ref();
-    let mut file = File::open(path)?;
+impl fmt::Write for Md5Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.update(s);
+        Ok(())
+    }
+}
+
+/// The MD5 digest of a file's contents along with the file's size, as
+/// determined by the number of bytes read
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct FileDigest {
+    pub(crate) digest: Md5Digest,
+    pub(crate) size: u64,
+    /// True if the file's size, modification time, or status change time
+    /// changed while it was being read, or if the number of bytes read did
+    /// not match the file's size
+    pub(crate) modified: bool,
+}
+
+/// Attributes of a file that are compared before & after reading it in order
+/// to detect concurrent modification
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct FileStamp {
+    size: u64,
+    mtime: Option<SystemTime>,
+    #[cfg(unix)]
+    ctime: (i64, i64),
+}
+
+impl From<&Metadata> for FileStamp {
+    fn from(md: &Metadata) -> FileStamp {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        FileStamp {
+            size: md.len(),
+            mtime: md.modified().ok(),
+            #[cfg(unix)]
+            ctime: (md.ctime(), md.ctime_nsec()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&libc::statx> for FileStamp {
+    /// Convert the result of a `statx()` call requesting at least
+    /// `STATX_SIZE`, `STATX_MTIME`, and `STATX_CTIME`.  Only compare the
+    /// result to other stamps obtained the same way.
+    fn from(stx: &libc::statx) -> FileStamp {
+        let mtime = u64::try_from(stx.stx_mtime.tv_sec).ok().and_then(|secs| {
+            SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, stx.stx_mtime.tv_nsec))
+        });
+        FileStamp {
+            size: stx.stx_size,
+            mtime,
+            ctime: (stx.stx_ctime.tv_sec, i64::from(stx.stx_ctime.tv_nsec)),
+        }
+    }
+}
+
+impl FileStamp {
+    /// Returns true if the file's attributes differ from `before` or if its
+    /// size differs from the number of bytes read
+    pub(crate) fn modified_since(&self, before: &FileStamp, bytes_read: u64) -> bool {
+        self != before || self.size != bytes_read
+    }
+}
+
+/// A file that has been opened for hashing, along with its metadata as of
+/// when it was opened
+#[derive(Debug)]
+pub(crate) struct OpenFile {
+    file: File,
+    metadata: Metadata,
+}
+
+impl OpenFile {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<OpenFile, FSError> {
+        let file = File::open(path.as_ref())?;
+        let metadata = file.metadata()?;
+        Ok(OpenFile { file, metadata })
+    }
+
+    pub(crate) fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub(crate) fn into_parts(self) -> (File, Metadata) {
+        (self.file, self.metadata)
+    }
+}
+
+/// Compute the MD5 hash of the contents of the given file, reading it into
+/// `buffer` one bufferful at a time.  The number of bytes read is reported to
+/// `progress` as reading proceeds, and reading is aborted with
+/// [`FSError::Cancelled`] if `cancellation` is triggered.
+///
+/// The file's metadata as of opening is compared to its metadata after reading
+/// in order to determine whether the file was modified in the meantime; it is
+/// up to the caller to decide what to do about it.
+pub(crate) fn md5_file(
+    file: OpenFile,
+    buffer: &mut [u8],
+    progress: &dyn ProgressObserver,
+    cancellation: &Cancellation,
+) -> Result<FileDigest, FSError> {
+    let (mut file, md) = file.into_parts();
+    let before = FileStamp::from(&md);
+    read_md5(&mut file, before, buffer, progress, cancellation)
+}
+
+/// Hash the remaining contents of an open file by reading it into `buffer`,
+/// returning a [`FileDigest`] whose `modified` field compares the file's
+/// current attributes to `before`
+fn read_md5(
+    file: &mut File,
+    before: FileStamp,
+    buffer: &mut [u8],
+    progress: &dyn ProgressObserver,
+    cancellation: &Cancellation,
+) -> Result<FileDigest, FSError> {
     let mut hasher = Md5::new();
-    std::io::copy(&mut file, &mut hasher).map_err(|source| FSError::Digest {
-        path: path.into(),
-        source,
-    })?;
-    Ok(hex::encode(hasher.finalize()))
+    let mut size = 0;
+    loop {
+        cancellation.check()?;
+        match file.read(buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                size += n as u64;
+                progress.bytes_read(n as u64);
+                hasher.update(&buffer[..n]);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(source) => {
+                return Err(FSError::Digest {
+                    path: file.path().into(),
+                    source,
+                })
+            }
+        }
+    }
+    let after = FileStamp::from(&file.metadata()?);
+    Ok(FileDigest {
+        digest: Md5Digest::from(<[u8; 16]>::from(hasher.finalize())),
+        size,
+        modified: after.modified_since(&before, size),
+    })
 }
 
-/// Compute the MD5 hash of the contents of the given file asynchronously,
-/// returning a string of lowercase hexadecimal digits
-pub(crate) async fn async_md5_file<P: AsRef<Path> + Send>(path: P) -> Result<String, FSError> {
+/// Compute the MD5 hash of the contents of the given file asynchronously.  The
+/// number of bytes read is reported to `progress` as reading proceeds, and
+/// reading is aborted with [`FSError::Cancelled`] if `cancellation` is
+/// triggered.
+pub(crate) async fn async_md5_file<P: AsRef<Path> + Send>(
+    path: P,
+    progress: &dyn ProgressObserver,
+    cancellation: &Cancellation,
+) -> Result<FileDigest, FSError> {
     let path = path.as_ref();
     let mut fp = TokioFile::open(path).await?;
+    let before = FileStamp::from(&fp.metadata().await?);
     let mut hasher = Md5::new();
+    let mut size = 0;
     let mut buffer = bytes::BytesMut::with_capacity(4096);
     loop {
+        cancellation.check()?;
         match fp.read_buf(&mut buffer).await {
             Ok(0) => break,
-            Ok(_) => {
+            Ok(n) => {
+                size += n as u64;
+                progress.bytes_read(n as u64);
                 hasher.update(&buffer);
                 buffer.clear();
             }
@@ -45,5 +217,27 @@ pub(crate) async fn async_md5_file<P: AsRef<Path> + Send>(path: P) -> Result<Str
             }
         }
     }
-    Ok(hex::encode(hasher.finalize()))
+    let after = FileStamp::from(&fp.metadata().await?);
+    Ok(FileDigest {
+        digest: Md5Digest::from(<[u8; 16]>::from(hasher.finalize())),
+        size,
+        modified: after.modified_since(&before, size),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt::Write;
+
+    #[test]
+    fn test_md5_writer() {
+        let mut w = Md5Writer::new();
+        w.write_str("foo").unwrap();
+        let n = 42;
+        write!(w, "bar-{n}").unwrap();
+        w.write_char('\u{1F410}').unwrap();
+        let expected = Md5::digest("foobar-42\u{1F410}");
+        assert_eq!(w.finish(), Md5Digest::from(<[u8; 16]>::from(expected)));
+    }
 }
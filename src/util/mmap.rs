@@ -0,0 +1,374 @@
+// Memory-mapping a file requires `unsafe`, as the mapped memory can change or
+// disappear out from under us if the file is modified by another process.
+#![allow(unsafe_code)]
+use super::{read_md5, FileDigest, FileStamp, OpenFile};
+use crate::cancel::Cancellation;
+use crate::checksum::Md5Digest;
+use crate::errors::FSError;
+use crate::progress::ProgressObserver;
+use md5::{Digest, Md5};
+use memmap2::Mmap;
+use std::path::Path;
+
+/// The number of bytes of a mapped file that are hashed at a time.  Between
+/// windows, the file's size is checked to make sure that it still covers the
+/// next window, and cancellation & progress are handled.
+const WINDOW_SIZE: usize = 1 << 20;
+
+/// Compute the MD5 hash of the contents of the given file by mapping it into
+/// memory.  Files smaller than `min_size` bytes, empty files, and files that
+/// cannot be mapped are instead read into `buffer` as in
+/// [`md5_file()`][super::md5_file].  The number of bytes hashed is reported
+/// to `progress` as hashing proceeds, and hashing is aborted with
+/// [`FSError::Cancelled`] if `cancellation` is triggered.
+///
+/// Accessing a page of a mapping that lies entirely past the end of the
+/// underlying file raises `SIGBUS`, which would normally kill the process if
+/// the file is truncated while it is being hashed.  To prevent this, the
+/// file's size is checked on the open handle before each window of
+/// [`WINDOW_SIZE`] bytes is hashed, and hashing stops early if the file no
+/// longer covers the window.  On Linux, a `SIGBUS` handler additionally
+/// catches faults that occur if the file is truncated while a window is being
+/// hashed, replacing the rest of the mapping with zeroes.  Either way, the
+/// partial digest is discarded and [`FSError::ModifiedDuringRead`] is
+/// returned, as the data hashed may include zeroes that were never in the
+/// file.
+pub(crate) fn md5_file_mmap(
+    file: OpenFile,
+    min_size: u64,
+    buffer: &mut [u8],
+    progress: &dyn ProgressObserver,
+    cancellation: &Cancellation,
+) -> Result<FileDigest, FSError> {
+    let (mut file, md) = file.into_parts();
+    let before = FileStamp::from(&md);
+    if !md.is_file() || md.len() == 0 || md.len() < min_size {
+        return read_md5(&mut file, before, buffer, progress, cancellation);
+    }
+    // SAFETY: The mapping is only ever read from, and reads of pages that the
+    // file no longer covers are guarded against as described above.  Other
+    // modifications to the file may cause us to hash inconsistent data, but
+    // such modifications are detected by comparing the file's attributes
+    // before & after hashing.
+    let map = match unsafe { Mmap::map(file.file()) } {
+        Ok(map) => map,
+        Err(e) => {
+            log::debug!(
+                "Could not memory-map {}: {e}; reading it instead",
+                file.path().display()
+            );
+            return read_md5(&mut file, before, buffer, progress, cancellation);
+        }
+    };
+    advise_sequential(&map);
+    let mut hasher = Md5::new();
+    let mut size = 0;
+    let guard = sigbus::Guard::new(&map);
+    for window in map.chunks(WINDOW_SIZE) {
+        cancellation.check()?;
+        let end = size + window.len() as u64;
+        if file.metadata()?.len() < end {
+            return Err(truncated(file.path()));
+        }
+        hasher.update(window);
+        if guard.faulted() {
+            return Err(truncated(file.path()));
+        }
+        size = end;
+        progress.bytes_read(window.len() as u64);
+    }
+    drop(guard);
+    let after = FileStamp::from(&file.metadata()?);
+    Ok(FileDigest {
+        digest: Md5Digest::from(<[u8; 16]>::from(hasher.finalize())),
+        size,
+        modified: after.modified_since(&before, size),
+    })
+}
+
+fn truncated(path: &Path) -> FSError {
+    log::debug!(
+        "File {} was truncated while it was being hashed",
+        path.display()
+    );
+    FSError::ModifiedDuringRead {
+        path: path.to_path_buf(),
+    }
+}
+
+#[cfg(unix)]
+fn advise_sequential(map: &Mmap) {
+    if let Err(e) = map.advise(memmap2::Advice::Sequential) {
+        log::debug!("madvise() failed: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn advise_sequential(_map: &Mmap) {}
+
+#[cfg(target_os = "linux")]
+mod sigbus {
+    //! Recovering from `SIGBUS` raised by reading past the end of a truncated
+    //! file's mapping
+    //!
+    //! While a [`Guard`] exists, the address range of its mapping is recorded
+    //! in thread-local storage.  As `SIGBUS` is delivered to the thread that
+    //! caused it, the process-wide handler can tell whether a fault was
+    //! caused by a guarded read on the current thread; if so, it replaces the
+    //! pages of the mapping from the faulting address onwards with anonymous
+    //! zero pages and records the fault, so that the read can be resumed and
+    //! the read failed.  All other faults are passed on to the
+    //! previously-installed handler, or else to the default action.
+    use memmap2::Mmap;
+    use std::cell::Cell;
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    thread_local! {
+        /// The start & end addresses of the mapping being read on this
+        /// thread, if any
+        static GUARDED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+        /// Set by the handler when a guarded read faults
+        static FAULTED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// The state saved when the handler is installed
+    struct Installed {
+        page_size: usize,
+        previous: libc::sigaction,
+    }
+
+    /// `None` if the handler could not be installed
+    static INSTALLED: OnceLock<Option<Installed>> = OnceLock::new();
+
+    #[derive(Debug)]
+    pub(super) struct Guard(());
+
+    impl Guard {
+        pub(super) fn new(map: &Mmap) -> Guard {
+            if INSTALLED.get_or_init(install).is_some() {
+                let start = map.as_ptr() as usize;
+                GUARDED.set((start, start + map.len()));
+                FAULTED.set(false);
+            }
+            Guard(())
+        }
+
+        /// Returns `true` if a read of the guarded mapping has faulted
+        pub(super) fn faulted(&self) -> bool {
+            FAULTED.get()
+        }
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            GUARDED.set((0, 0));
+            FAULTED.set(false);
+        }
+    }
+
+    fn install() -> Option<Installed> {
+        // SAFETY: `sysconf()` has no preconditions.
+        let page_size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+        // SAFETY: An all-zero `sigaction` is valid.
+        let mut action: libc::sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
+        // `sigaction` stores the handler's address as an integer.
+        #[allow(clippy::fn_to_numeric_cast_any)]
+        {
+            action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        }
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        let mut previous = MaybeUninit::<libc::sigaction>::zeroed();
+        // The handler reads `INSTALLED`, which isn't set until after we
+        // return, but it only needs it for faults in a guarded range, and no
+        // range is guarded until after `INSTALLED` is set.
+        // SAFETY: `action` is a valid `sigaction`, `previous` is a valid
+        // location to store the old one, and `handler()` only calls
+        // async-signal-safe functions.
+        let r = unsafe { libc::sigaction(libc::SIGBUS, &action, previous.as_mut_ptr()) };
+        if r != 0 {
+            log::warn!(
+                "Could not install SIGBUS handler: {}; truncation of memory-mapped files will not be caught",
+                io::Error::last_os_error()
+            );
+            return None;
+        }
+        Some(Installed {
+            page_size,
+            // SAFETY: `sigaction()` succeeded, so it filled in `previous`.
+            previous: unsafe { previous.assume_init() },
+        })
+    }
+
+    extern "C" fn handler(signum: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+        // SAFETY: The kernel passes a valid `siginfo_t` to `SA_SIGINFO`
+        // handlers, and `si_addr` is set for `SIGBUS`.
+        let addr = unsafe { (*info).si_addr() } as usize;
+        let (start, end) = GUARDED.get();
+        if let Some(Some(inst)) = INSTALLED.get() {
+            if start <= addr && addr < end {
+                let page = addr & !(inst.page_size - 1);
+                // SAFETY: `page..end` lies within the guarded mapping, which
+                // is still mapped while the guard exists, and is replaced by
+                // a private read-only mapping of the same size.  `mmap()` is
+                // a plain system call and safe to use in a signal handler.
+                let r = unsafe {
+                    libc::mmap(
+                        page as *mut libc::c_void,
+                        end - page,
+                        libc::PROT_READ,
+                        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                        -1,
+                        0,
+                    )
+                };
+                if r != libc::MAP_FAILED {
+                    FAULTED.set(true);
+                    // Returning retries the faulting read, which now sees
+                    // zeroes.
+                    return;
+                }
+            }
+            let previous = inst.previous.sa_sigaction;
+            if previous != libc::SIG_DFL && previous != libc::SIG_IGN {
+                if inst.previous.sa_flags & libc::SA_SIGINFO != 0 {
+                    // SAFETY: The previous handler was installed with
+                    // `SA_SIGINFO`, so it has this signature.
+                    let f: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                        unsafe { std::mem::transmute(previous) };
+                    f(signum, info, ctx);
+                } else {
+                    // SAFETY: The previous handler was installed without
+                    // `SA_SIGINFO`, so it has this signature.
+                    let f: extern "C" fn(libc::c_int) = unsafe { std::mem::transmute(previous) };
+                    f(signum);
+                }
+                return;
+            }
+        }
+        // Restore the default action so that returning re-raises the fault
+        // and kills the process as it would have without us.
+        // SAFETY: An all-zero `sigaction` with `SIG_DFL` is valid, and
+        // `sigaction()` is async-signal-safe.
+        unsafe {
+            let mut action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+            action.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(signum, &action, ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sigbus {
+    //! On platforms other than Linux, truncation is only detected between
+    //! windows.
+    use memmap2::Mmap;
+
+    #[derive(Debug)]
+    pub(super) struct Guard(());
+
+    impl Guard {
+        pub(super) fn new(_map: &Mmap) -> Guard {
+            Guard(())
+        }
+
+        pub(super) fn faulted(&self) -> bool {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::progress::NoProgress;
+    use crate::util::md5_file;
+    use assert_matches::assert_matches;
+    use fs_err::File;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn sample(len: usize) -> NamedTempFile {
+        let mut tmp = NamedTempFile::new().unwrap();
+        let data = (0..len)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect::<Vec<_>>();
+        tmp.write_all(&data).unwrap();
+        tmp.flush().unwrap();
+        tmp
+    }
+
+    #[test]
+    fn test_matches_md5_file() {
+        for len in [
+            0,
+            1,
+            4096,
+            WINDOW_SIZE - 1,
+            WINDOW_SIZE,
+            3 * WINDOW_SIZE + 17,
+        ] {
+            let tmp = sample(len);
+            let mut buf = [0u8; 8192];
+            let expected = md5_file(
+                OpenFile::open(tmp.path()).unwrap(),
+                &mut buf,
+                &NoProgress,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            let actual = md5_file_mmap(
+                OpenFile::open(tmp.path()).unwrap(),
+                0,
+                &mut buf,
+                &NoProgress,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            assert_eq!(actual, expected, "len = {len}");
+            assert!(!actual.modified);
+        }
+    }
+
+    #[test]
+    fn test_truncated_between_windows() {
+        /// Truncates the file the first time that bytes are reported
+        #[derive(Debug)]
+        struct Truncator(std::fs::File);
+
+        impl ProgressObserver for Truncator {
+            fn bytes_read(&self, _n: u64) {
+                self.0.set_len(10).unwrap();
+            }
+        }
+
+        let tmp = sample(3 * WINDOW_SIZE);
+        let truncator = Truncator(tmp.reopen().unwrap());
+        let mut buf = [0u8; 8192];
+        let r = md5_file_mmap(
+            OpenFile::open(tmp.path()).unwrap(),
+            0,
+            &mut buf,
+            &truncator,
+            &Cancellation::default(),
+        );
+        assert_matches!(r, Err(FSError::ModifiedDuringRead { .. }));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sigbus_is_caught() {
+        let tmp = sample(4 * 4096);
+        let file = File::open(tmp.path()).unwrap();
+        // SAFETY: The file is private to this test.
+        let map = unsafe { Mmap::map(file.file()) }.unwrap();
+        tmp.as_file().set_len(4096).unwrap();
+        let guard = sigbus::Guard::new(&map);
+        let sum = map.iter().map(|&b| u64::from(b)).sum::<u64>();
+        assert!(guard.faulted());
+        let expected = (0..4096).map(|i| i % 251).sum::<u64>();
+        assert_eq!(sum, expected);
+    }
+}
@@ -0,0 +1,378 @@
+// Calling a function compiled for a CPU feature that is detected at runtime
+// requires `unsafe`.
+#![allow(unsafe_code)]
+//! Multi-buffer MD5: computing the digests of several independent messages at
+//! once by running the MD5 compression function on one message per SIMD lane
+use crate::checksum::Md5Digest;
+use md5::{Digest, Md5};
+use std::array;
+
+/// A strategy for computing the MD5 digests of a batch of messages
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Md5Engine {
+    /// Hash each message in turn with the `md-5` crate
+    Scalar,
+    /// Hash four messages at a time (SSE2 on x86-64 or NEON on 64-bit ARM)
+    Lanes4,
+    /// Hash eight messages at a time using AVX2
+    Lanes8,
+}
+
+impl Md5Engine {
+    /// Return the widest engine supported by the current CPU
+    pub(crate) fn detect() -> Md5Engine {
+        if Md5Engine::Lanes8.is_supported() {
+            Md5Engine::Lanes8
+        } else if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            Md5Engine::Lanes4
+        } else {
+            Md5Engine::Scalar
+        }
+    }
+
+    /// Returns `true` if the engine can be used on the current CPU.  The
+    /// four-lane engine is written in portable code and so is always
+    /// supported, though it is only vectorized on targets with baseline SIMD
+    /// support.
+    pub(crate) fn is_supported(self) -> bool {
+        match self {
+            Md5Engine::Scalar | Md5Engine::Lanes4 => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Md5Engine::Lanes8 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            Md5Engine::Lanes8 => false,
+        }
+    }
+
+    /// The number of messages hashed at once
+    pub(crate) fn lanes(self) -> usize {
+        match self {
+            Md5Engine::Scalar => 1,
+            Md5Engine::Lanes4 => 4,
+            Md5Engine::Lanes8 => 8,
+        }
+    }
+
+    /// Compute the MD5 digest of each message in `inputs`, returning the
+    /// digests in the same order
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine is not supported by the current CPU
+    pub(crate) fn digest_many(self, inputs: &[&[u8]]) -> Vec<Md5Digest> {
+        let mut out = vec![Md5Digest::from([0; 16]); inputs.len()];
+        match self {
+            Md5Engine::Scalar => {
+                for (d, data) in out.iter_mut().zip(inputs) {
+                    *d = Md5Digest::from(<[u8; 16]>::from(Md5::digest(data)));
+                }
+            }
+            Md5Engine::Lanes4 => hash_lanes::<4>(inputs, &mut out),
+            Md5Engine::Lanes8 => {
+                assert!(self.is_supported(), "AVX2 is not supported on this CPU");
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                // SAFETY: We just checked that AVX2 is available.
+                unsafe {
+                    hash_lanes_avx2(inputs, &mut out);
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn hash_lanes_avx2(inputs: &[&[u8]], out: &mut [Md5Digest]) {
+    hash_lanes::<8>(inputs, out);
+}
+
+/// Initial MD5 state
+const IV: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+/// Per-step additive constants
+#[rustfmt::skip]
+const K: [u32; 64] = [
+    0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee,
+    0xf57c_0faf, 0x4787_c62a, 0xa830_4613, 0xfd46_9501,
+    0x6980_98d8, 0x8b44_f7af, 0xffff_5bb1, 0x895c_d7be,
+    0x6b90_1122, 0xfd98_7193, 0xa679_438e, 0x49b4_0821,
+    0xf61e_2562, 0xc040_b340, 0x265e_5a51, 0xe9b6_c7aa,
+    0xd62f_105d, 0x0244_1453, 0xd8a1_e681, 0xe7d3_fbc8,
+    0x21e1_cde6, 0xc337_07d6, 0xf4d5_0d87, 0x455a_14ed,
+    0xa9e3_e905, 0xfcef_a3f8, 0x676f_02d9, 0x8d2a_4c8a,
+    0xfffa_3942, 0x8771_f681, 0x6d9d_6122, 0xfde5_380c,
+    0xa4be_ea44, 0x4bde_cfa9, 0xf6bb_4b60, 0xbebf_bc70,
+    0x289b_7ec6, 0xeaa1_27fa, 0xd4ef_3085, 0x0488_1d05,
+    0xd9d4_d039, 0xe6db_99e5, 0x1fa2_7cf8, 0xc4ac_5665,
+    0xf429_2244, 0x432a_ff97, 0xab94_23a7, 0xfc93_a039,
+    0x655b_59c3, 0x8f0c_cc92, 0xffef_f47d, 0x8584_5dd1,
+    0x6fa8_7e4f, 0xfe2c_e6e0, 0xa301_4314, 0x4e08_11a1,
+    0xf753_7e82, 0xbd3a_f235, 0x2ad7_d2bb, 0xeb86_d391,
+];
+
+/// Per-step rotation amounts
+#[rustfmt::skip]
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// One 32-bit word from each of `N` lanes.  Operations are written as loops
+/// over the lanes so that they compile to SIMD instructions of the appropriate
+/// width.
+#[derive(Clone, Copy, Debug)]
+struct Words<const N: usize>([u32; N]);
+
+impl<const N: usize> Words<N> {
+    #[inline(always)]
+    fn splat(x: u32) -> Self {
+        Words([x; N])
+    }
+
+    #[inline(always)]
+    fn map2(self, other: Self, f: impl Fn(u32, u32) -> u32) -> Self {
+        Words(array::from_fn(|i| f(self.0[i], other.0[i])))
+    }
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        self.map2(other, u32::wrapping_add)
+    }
+
+    #[inline(always)]
+    fn and(self, other: Self) -> Self {
+        self.map2(other, |x, y| x & y)
+    }
+
+    #[inline(always)]
+    fn or(self, other: Self) -> Self {
+        self.map2(other, |x, y| x | y)
+    }
+
+    #[inline(always)]
+    fn xor(self, other: Self) -> Self {
+        self.map2(other, |x, y| x ^ y)
+    }
+
+    /// `!self & other`
+    #[inline(always)]
+    fn andnot(self, other: Self) -> Self {
+        self.map2(other, |x, y| !x & y)
+    }
+
+    #[inline(always)]
+    fn not(self) -> Self {
+        Words(self.0.map(|x| !x))
+    }
+
+    #[inline(always)]
+    fn rotl(self, r: u32) -> Self {
+        Words(self.0.map(|x| x.rotate_left(r)))
+    }
+}
+
+/// Run the MD5 compression function on one 64-byte block per lane
+#[inline(always)]
+fn compress<const N: usize>(state: &mut [Words<N>; 4], block: &[Words<N>; 16]) {
+    let [mut a, mut b, mut c, mut d] = *state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => (b.and(c).or(b.andnot(d)), i),
+            1 => (d.and(b).or(d.andnot(c)), (5 * i + 1) % 16),
+            2 => (b.xor(c).xor(d), (3 * i + 5) % 16),
+            _ => (c.xor(b.or(d.not())), (7 * i) % 16),
+        };
+        let t = a.add(f).add(Words::splat(K[i])).add(block[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.add(t.rotl(S[i]));
+    }
+    for (s, x) in state.iter_mut().zip([a, b, c, d]) {
+        *s = s.add(x);
+    }
+}
+
+/// A message being hashed in a lane, split into 64-byte blocks with the MD5
+/// padding & length appended
+struct Message<'a> {
+    /// The index of the message in the input
+    index: usize,
+    data: &'a [u8],
+    /// The final partial block of `data` (if any) followed by the padding,
+    /// taking up one or two blocks
+    tail: [u8; 128],
+    /// The total number of blocks, including those in `tail`
+    blocks: usize,
+    /// The index of the next block to hash
+    next: usize,
+}
+
+impl<'a> Message<'a> {
+    fn new(index: usize, data: &'a [u8]) -> Message<'a> {
+        let full = data.len() / 64;
+        let rem = &data[(full * 64)..];
+        let mut tail = [0; 128];
+        tail[..rem.len()].copy_from_slice(rem);
+        tail[rem.len()] = 0x80;
+        let tail_len = if rem.len() < 56 { 64 } else { 128 };
+        let bits = (data.len() as u64).wrapping_mul(8);
+        tail[(tail_len - 8)..tail_len].copy_from_slice(&bits.to_le_bytes());
+        Message {
+            index,
+            data,
+            tail,
+            blocks: full + tail_len / 64,
+            next: 0,
+        }
+    }
+
+    fn block(&self, i: usize) -> &[u8] {
+        let full = self.data.len() / 64;
+        if i < full {
+            &self.data[(i * 64)..((i + 1) * 64)]
+        } else {
+            let offset = (i - full) * 64;
+            &self.tail[offset..(offset + 64)]
+        }
+    }
+}
+
+/// Hash `inputs` with `N` lanes, storing each message's digest at the same
+/// index in `out`.  Whenever the message in a lane is finished, the next
+/// unhashed message is started in that lane, so lanes stay busy until all
+/// messages have been started.
+#[inline(always)]
+fn hash_lanes<const N: usize>(inputs: &[&[u8]], out: &mut [Md5Digest]) {
+    let mut queue = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, data)| Message::new(index, data));
+    let mut lanes: [Option<Message<'_>>; N] = array::from_fn(|_| queue.next());
+    let mut state = IV.map(Words::splat);
+    let mut block = [Words([0; N]); 16];
+    while lanes.iter().any(Option::is_some) {
+        for (lane, msg) in lanes.iter().enumerate() {
+            if let Some(msg) = msg {
+                let bytes = msg.block(msg.next);
+                for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(4)) {
+                    let &[w, x, y, z] = chunk else {
+                        unreachable!("chunks_exact(4) should yield 4-byte chunks");
+                    };
+                    word.0[lane] = u32::from_le_bytes([w, x, y, z]);
+                }
+            }
+        }
+        compress(&mut state, &block);
+        for (lane, slot) in lanes.iter_mut().enumerate() {
+            let Some(msg) = slot else {
+                continue;
+            };
+            msg.next += 1;
+            if msg.next == msg.blocks {
+                let mut digest = [0; 16];
+                for (bytes, (s, iv)) in digest.chunks_exact_mut(4).zip(state.iter_mut().zip(IV)) {
+                    bytes.copy_from_slice(&s.0[lane].to_le_bytes());
+                    s.0[lane] = iv;
+                }
+                out[msg.index] = Md5Digest::from(digest);
+                *slot = queue.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter;
+
+    /// A small xorshift generator, so that failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            usize::try_from(self.next() % (n as u64)).unwrap()
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            iter::repeat_with(|| self.next().to_le_bytes()[0])
+                .take(len)
+                .collect()
+        }
+    }
+
+    fn engines() -> Vec<Md5Engine> {
+        [Md5Engine::Scalar, Md5Engine::Lanes4, Md5Engine::Lanes8]
+            .into_iter()
+            .filter(|e| e.is_supported())
+            .collect()
+    }
+
+    fn expected(inputs: &[&[u8]]) -> Vec<Md5Digest> {
+        inputs
+            .iter()
+            .map(|data| Md5Digest::from(<[u8; 16]>::from(Md5::digest(data))))
+            .collect()
+    }
+
+    #[test]
+    fn test_padding_boundaries() {
+        // Lengths around the points at which the padding spills into an
+        // extra block
+        let data = (0..=255).collect::<Vec<u8>>();
+        let inputs = [0, 1, 55, 56, 57, 63, 64, 65, 119, 120, 127, 128, 129, 255]
+            .into_iter()
+            .map(|n| &data[..n])
+            .collect::<Vec<_>>();
+        for engine in engines() {
+            assert_eq!(
+                engine.digest_many(&inputs),
+                expected(&inputs),
+                "engine = {engine:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_batch() {
+        for engine in engines() {
+            assert!(engine.digest_many(&[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_randomized() {
+        let mut rng = Rng(0x5eed_1234_abcd_ef01);
+        for _ in 0..50 {
+            let count = rng.below(40);
+            let messages = iter::repeat_with(|| {
+                // Mostly small messages, with the occasional larger one
+                // so that lanes finish at different times
+                let len = if rng.below(8) == 0 {
+                    rng.below(20000)
+                } else {
+                    rng.below(300)
+                };
+                rng.bytes(len)
+            })
+            .take(count)
+            .collect::<Vec<_>>();
+            let inputs = messages.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let expected = expected(&inputs);
+            for engine in engines() {
+                assert_eq!(engine.digest_many(&inputs), expected, "engine = {engine:?}");
+            }
+        }
+    }
+}
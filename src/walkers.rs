@@ -6,6 +6,11 @@
 //! [`FSError`][crate::errors::FSError] or a
 //! [`ChecksumTreeError`][crate::errors::ChecksumError].  The latter error type
 //! indicates a bug in the traversal function.
+//!
+//! If the Zarr's [cancel token][crate::zarr::Zarr::cancel_token] is cancelled
+//! or its [deadline][crate::zarr::Zarr::deadline] passes, the functions return
+//! [`ChecksumError::Cancelled`][crate::errors::ChecksumError::Cancelled] once
+//! all of their worker threads or tasks have finished.
 mod breadth_first;
 mod collapsio_arc;
 mod collapsio_mpsc;
@@ -13,7 +18,15 @@ mod depth_first;
 mod fastasync;
 mod fastio;
 mod jobstack;
+mod multibuffer;
+mod offload;
+mod pipelined;
+mod rayon;
 mod recursive;
+mod stealing;
+mod sync;
+#[cfg(target_os = "linux")]
+mod uring;
 mod util;
 pub use breadth_first::*;
 pub use collapsio_arc::*;
@@ -21,4 +34,10 @@ pub use collapsio_mpsc::*;
 pub use depth_first::*;
 pub use fastasync::*;
 pub use fastio::*;
+pub use multibuffer::*;
+pub use offload::*;
+pub use pipelined::*;
+pub use rayon::*;
 pub use recursive::*;
+#[cfg(target_os = "linux")]
+pub use uring::*;
@@ -1,13 +1,13 @@
-use super::jobstack::JobStack;
+use super::jobstack::{entry_size, JobStack, Scheduler};
+use super::stealing::StealingScheduler;
+use super::sync::{Arc, Mutex, WaitGroup};
 use super::util::Output;
 use crate::checksum::nodes::*;
 use crate::errors::ChecksumError;
 use crate::zarr::*;
-use crossbeam_utils::sync::WaitGroup;
 use std::fmt;
 use std::num::NonZeroUsize;
 use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
 use std::thread;
 
 #[derive(Debug)]
@@ -21,6 +21,16 @@ impl Job {
         Job::Entry(ZarrEntry::Directory(zarr.root_dir()), None)
     }
 
+    /// The size of the job for [`JobOrder::LargestFirst`].  Completed
+    /// directories are collapsed as soon as possible in order to free up
+    /// memory.
+    fn size(&self) -> u64 {
+        match self {
+            Job::Entry(entry, _) => entry_size(entry),
+            Job::CompletedDir(_) => u64::MAX,
+        }
+    }
+
     fn process(self, thread_no: usize) -> Output<Job, String> {
         match self {
             Job::Entry(ZarrEntry::Directory(zd), parent) => match zd.entries() {
@@ -200,61 +210,145 @@ impl std::ops::Deref for SharedDirectory {
 ///
 /// The `threads` argument determines the number of worker threads to use.
 pub fn collapsio_arc_checksum(zarr: &Zarr, threads: NonZeroUsize) -> Result<String, ChecksumError> {
-    let stack = Arc::new(JobStack::new([Job::mkroot(zarr)]));
+    let stack = JobStack::with_order([Job::mkroot(zarr)], zarr.get_job_order(), Job::size)
+        .with_cancellation(zarr.cancellation().clone());
+    run_workers(&stack, vec![(); threads.get()])
+}
+
+/// Traverse & checksum a Zarr directory like [`collapsio_arc_checksum()`], but
+/// using a work-stealing scheduler in which each thread keeps its own queue of
+/// jobs and steals jobs from the other threads when it runs out, rather than
+/// all threads sharing a single stack
+///
+/// The `threads` argument determines the number of worker threads to use.
+pub fn collapsio_arc_stealing_checksum(
+    zarr: &Zarr,
+    threads: NonZeroUsize,
+) -> Result<String, ChecksumError> {
+    let (scheduler, queues) = StealingScheduler::new([Job::mkroot(zarr)], threads);
+    let scheduler = scheduler.with_cancellation(zarr.cancellation().clone());
+    run_workers(&scheduler, queues)
+}
+
+/// Run one worker thread per element of `workers`, each handling jobs from
+/// `scheduler`, and return the checksum of the root directory
+fn run_workers<S>(scheduler: &S, workers: Vec<S::Worker>) -> Result<String, ChecksumError>
+where
+    S: Scheduler<Job> + Sync,
+    S::Worker: Send,
+{
     let (sender, receiver) = channel();
-    for thread_no in 0..threads.get() {
-        let stack = Arc::clone(&stack);
-        let sender = sender.clone();
-        thread::spawn(move || {
-            log::trace!("[{thread_no}] Starting thread");
-            let _ = stack.handle_many_jobs(|entry| {
-                log::trace!("[{thread_no}] Popped {entry:?} from stack");
-                match entry.process(thread_no) {
-                    Output::ToPush(to_push) => Ok(to_push),
-                    Output::ToSend(to_send) => {
-                        // If we've shut down, don't send anything except Errs
-                        if to_send.is_err() || !stack.is_shutdown() {
-                            if to_send.is_err() {
-                                stack.shutdown();
-                            }
-                            log::trace!("[{thread_no}] Sending {to_send:?} to output");
-                            if let Err(e) = sender.send(to_send) {
-                                log::warn!("[{thread_no}] Failed to send; exiting");
-                                return Err(e);
+    thread::scope(|scope| {
+        for (thread_no, worker) in workers.into_iter().enumerate() {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                log::trace!("[{thread_no}] Starting thread");
+                let _ = scheduler.handle_many_jobs(worker, |entry| {
+                    log::trace!("[{thread_no}] Popped {entry:?} from stack");
+                    match entry.process(thread_no) {
+                        Output::ToPush(to_push) => Ok(to_push),
+                        Output::ToSend(to_send) => {
+                            // If we've shut down, don't send anything except Errs
+                            if to_send.is_err() || !scheduler.is_shutdown() {
+                                if to_send.is_err() {
+                                    scheduler.shutdown();
+                                }
+                                log::trace!("[{thread_no}] Sending {to_send:?} to output");
+                                if let Err(e) = sender.send(to_send) {
+                                    log::warn!("[{thread_no}] Failed to send; exiting");
+                                    return Err(e);
+                                }
                             }
+                            Ok(Vec::new())
                         }
-                        Ok(Vec::new())
+                        Output::Nil => Ok(Vec::new()),
                     }
-                    Output::Nil => Ok(Vec::new()),
-                }
+                });
+                log::trace!("[{thread_no}] Ending thread");
             });
-            log::trace!("[{thread_no}] Ending thread");
-        });
-    }
-    drop(sender);
-    // Force the receiver to receive everything (rather than breaking out early
-    // on an Err) in order to ensure that all threads run to completion
-    let mut chksum = None;
-    let mut err = None;
-    for v in receiver {
-        match v {
-            Ok(s) => {
-                chksum.get_or_insert(s);
-            }
-            Err(e) => {
-                err.get_or_insert(e);
+        }
+        drop(sender);
+        // Force the receiver to receive everything (rather than breaking out
+        // early on an Err) in order to ensure that all threads run to
+        // completion
+        let mut chksum = None;
+        let mut err = None;
+        for v in receiver {
+            match v {
+                Ok(s) => {
+                    chksum.get_or_insert(s);
+                }
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
             }
         }
-    }
-    match err {
-        Some(e) => Err(e.into()),
-        None => {
-            if let Some(s) = chksum {
-                Ok(s)
-            } else {
-                log::error!("Neither checksum nor errors were received!");
-                panic!("Neither checksum nor errors were received!");
+        match err {
+            Some(e) => Err(e.into()),
+            None if scheduler.is_cancelled() => Err(ChecksumError::Cancelled),
+            None => {
+                if let Some(s) = chksum {
+                    Ok(s)
+                } else {
+                    log::error!("Neither checksum nor errors were received!");
+                    panic!("Neither checksum nor errors were received!");
+                }
             }
         }
+    })
+}
+
+/// Model-checked tests, run with `RUSTFLAGS="--cfg zarr_loom" cargo test
+/// --release --lib loom`
+#[cfg(test)]
+#[cfg(zarr_loom)]
+mod loom_test {
+    use super::*;
+    use crate::checksum::Md5Digest;
+    use crate::walkers::util::loom_util::{expected_checksum, mktree, model, run_jobs};
+    use loom::thread;
+    use std::slice;
+
+    fn node(name: &str) -> EntryChecksum {
+        let relpath = EntryPath::try_from(name).unwrap();
+        FileChecksum::new(relpath, Md5Digest::from([0; 16]), 1).into()
+    }
+
+    #[test]
+    fn test_loom_shared_directory_unwrap() {
+        loom::model(|| {
+            let root = Zarr::new("loom").root_dir();
+            let expected = root.get_checksum([node("a"), node("b")]);
+            let dir = SharedDirectory::new(Directory::new(root, 2, None));
+            let handles = ["a", "b"].map(|name| {
+                let dir = dir.clone();
+                thread::spawn(move || {
+                    // The thread that completes the directory unwraps it
+                    // while the other thread may still be holding its
+                    // reference, as when a `CompletedDir` job is popped
+                    // before the `Job::Entry` that added to it has finished
+                    dir.add(node(name)).then(|| dir.unwrap())
+                })
+            });
+            drop(dir);
+            let mut completed = handles
+                .into_iter()
+                .filter_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(completed.len(), 1);
+            assert_eq!(completed.pop().unwrap().checksum(), expected);
+        });
+    }
+
+    #[test]
+    fn test_loom_collapsio_arc_jobs() {
+        let tmp_path = mktree();
+        let root = tmp_path.path().to_owned();
+        let expected = expected_checksum(&root);
+        model(move || {
+            let zarr = Zarr::new(&root);
+            let stack = JobStack::new([Job::mkroot(&zarr)]);
+            assert_eq!(run_jobs(stack, Job::process), slice::from_ref(&expected));
+        });
     }
 }
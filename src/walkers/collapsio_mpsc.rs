@@ -1,11 +1,10 @@
 use super::jobstack::JobStack;
+use super::sync::mpsc::{self, Receiver, Sender};
 use super::util::Output;
 use crate::checksum::nodes::*;
 use crate::errors::ChecksumError;
 use crate::zarr::*;
 use std::num::NonZeroUsize;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
 use std::thread;
 
 #[derive(Debug)]
@@ -32,7 +31,7 @@ impl Job {
                         dir.relpath(),
                         entries.len(),
                     );
-                    let (sender, recv) = channel();
+                    let (sender, recv) = mpsc::channel();
                     let mut to_push = vec![Job::CompletedDir { dir, recv, parent }];
                     to_push.extend(
                         entries
@@ -77,65 +76,96 @@ impl Job {
 /// to parent jobs via MPSC channels.
 ///
 /// The `threads` argument determines the number of worker threads to use.
+/// Jobs are always taken in LIFO order, regardless of
+/// [`Zarr::job_order()`].
 pub fn collapsio_mpsc_checksum(
     zarr: &Zarr,
     threads: NonZeroUsize,
 ) -> Result<String, ChecksumError> {
-    let stack = Arc::new(JobStack::new([Job::mkroot(zarr)]));
-    let (sender, receiver) = channel();
-    for thread_no in 0..threads.get() {
-        let stack = Arc::clone(&stack);
-        let sender = sender.clone();
-        thread::spawn(move || {
-            log::trace!("[{thread_no}] Starting thread");
-            let _ = stack.handle_many_jobs(|entry| {
-                log::trace!("[{thread_no}] Popped {entry:?} from stack");
-                match entry.process(thread_no) {
-                    Output::ToPush(to_push) => Ok(to_push),
-                    Output::ToSend(to_send) => {
-                        // If we've shut down, don't send anything except Errs
-                        if to_send.is_err() || !stack.is_shutdown() {
-                            if to_send.is_err() {
-                                stack.shutdown();
-                            }
-                            log::trace!("[{thread_no}] Sending {to_send:?} to output");
-                            if let Err(e) = sender.send(to_send) {
-                                log::warn!("[{thread_no}] Failed to send; exiting");
-                                return Err(e);
+    // Jobs for completed directories block until all of the directory's
+    // entries have been checksummed, which can deadlock if the jobs for the
+    // entries are not taken first, so the Zarr's job order is ignored.
+    let stack = JobStack::new([Job::mkroot(zarr)]).with_cancellation(zarr.cancellation().clone());
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for thread_no in 0..threads.get() {
+            let stack = &stack;
+            let sender = sender.clone();
+            scope.spawn(move || {
+                log::trace!("[{thread_no}] Starting thread");
+                let _ = stack.handle_many_jobs(|entry| {
+                    log::trace!("[{thread_no}] Popped {entry:?} from stack");
+                    match entry.process(thread_no) {
+                        Output::ToPush(to_push) => Ok(to_push),
+                        Output::ToSend(to_send) => {
+                            // If we've shut down, don't send anything except Errs
+                            if to_send.is_err() || !stack.is_shutdown() {
+                                if to_send.is_err() {
+                                    stack.shutdown();
+                                }
+                                log::trace!("[{thread_no}] Sending {to_send:?} to output");
+                                if let Err(e) = sender.send(to_send) {
+                                    log::warn!("[{thread_no}] Failed to send; exiting");
+                                    return Err(e);
+                                }
                             }
+                            Ok(Vec::new())
                         }
-                        Ok(Vec::new())
+                        Output::Nil => Ok(Vec::new()),
                     }
-                    Output::Nil => Ok(Vec::new()),
-                }
+                });
+                log::trace!("[{thread_no}] Ending thread");
             });
-            log::trace!("[{thread_no}] Ending thread");
-        });
-    }
-    drop(sender);
-    // Force the receiver to receive everything (rather than breaking out early
-    // on an Err) in order to ensure that all threads run to completion
-    let mut chksum = None;
-    let mut err = None;
-    for v in receiver {
-        match v {
-            Ok(s) => {
-                chksum.get_or_insert(s);
-            }
-            Err(e) => {
-                err.get_or_insert(e);
+        }
+        drop(sender);
+        // Force the receiver to receive everything (rather than breaking out
+        // early on an Err) in order to ensure that all threads run to
+        // completion
+        let mut chksum = None;
+        let mut err = None;
+        for v in receiver {
+            match v {
+                Ok(s) => {
+                    chksum.get_or_insert(s);
+                }
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
             }
         }
-    }
-    match err {
-        Some(e) => Err(e.into()),
-        None => {
-            if let Some(s) = chksum {
-                Ok(s)
-            } else {
-                log::error!("Neither checksum nor errors were received!");
-                panic!("Neither checksum nor errors were received!");
+        match err {
+            Some(e) => Err(e.into()),
+            None if stack.is_cancelled() => Err(ChecksumError::Cancelled),
+            None => {
+                if let Some(s) = chksum {
+                    Ok(s)
+                } else {
+                    log::error!("Neither checksum nor errors were received!");
+                    panic!("Neither checksum nor errors were received!");
+                }
             }
         }
+    })
+}
+
+/// Model-checked tests, run with `RUSTFLAGS="--cfg zarr_loom" cargo test
+/// --release --lib loom`
+#[cfg(test)]
+#[cfg(zarr_loom)]
+mod loom_test {
+    use super::*;
+    use crate::walkers::util::loom_util::{expected_checksum, mktree, model, run_jobs};
+    use std::slice;
+
+    #[test]
+    fn test_loom_collapsio_mpsc_jobs() {
+        let tmp_path = mktree();
+        let root = tmp_path.path().to_owned();
+        let expected = expected_checksum(&root);
+        model(move || {
+            let zarr = Zarr::new(&root);
+            let stack = JobStack::new([Job::mkroot(&zarr)]);
+            assert_eq!(run_jobs(stack, Job::process), slice::from_ref(&expected));
+        });
     }
 }
@@ -5,6 +5,7 @@ use crate::zarr::*;
 struct OpenDir {
     handle: Entries,
     summer: Dirsummer,
+    dir: ZarrDirectory,
 }
 
 impl OpenDir {
@@ -13,8 +14,13 @@ impl OpenDir {
         Ok(OpenDir {
             handle,
             summer: dir.dirsummer(),
+            dir,
         })
     }
+
+    fn checksum(&self) -> DirChecksum {
+        self.dir.collapse(&self.summer)
+    }
 }
 
 /// Traverse & checksum a Zarr directory tree depth-first and iteratively
@@ -30,10 +36,10 @@ pub fn depth_first_checksum(zarr: &Zarr) -> Result<String, ChecksumError> {
             Some(Ok(ZarrEntry::File(zf))) => topdir.summer.push(zf.into_checksum()?),
             Some(Err(e)) => return Err(e.into()),
             None => {
-                let OpenDir { summer, .. } = dirstack.pop().expect("dirstack should be nonempty");
+                let done = dirstack.pop().expect("dirstack should be nonempty");
                 match dirstack.last_mut() {
-                    Some(od) => od.summer.push(summer.checksum()),
-                    None => return Ok(summer.checksum().into_checksum()),
+                    Some(od) => od.summer.push(done.checksum()),
+                    None => return Ok(done.checksum().into_checksum()),
                 }
             }
         }
@@ -1,28 +1,46 @@
 use super::util::Output;
-use crate::checksum::ChecksumTree;
-use crate::errors::ChecksumError;
+use crate::cancel::Cancellation;
+use crate::checksum::{ChecksumTree, FileChecksum};
+use crate::errors::{ChecksumError, FSError};
 use crate::zarr::*;
+use futures_core::Stream;
 use std::future::Future;
 use std::num::NonZeroUsize;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::channel;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{channel, Receiver};
 use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// The number of results that [`fastasync_checksum()`] buffers between the
+/// worker tasks and the tree-building task
+const BUFFER_SIZE: NonZeroUsize = NonZeroUsize::new(64).unwrap();
 
 // We need to use Tokio's Notify instead of the standard Condvar so that this
 // walker can function in a single-threaded runtime.
+#[derive(Debug)]
 struct AsyncJobStack<T> {
     data: Mutex<AsyncJobStackData<T>>,
     cond: Notify,
+    cancellation: Cancellation,
 }
 
+#[derive(Debug)]
 struct AsyncJobStackData<T> {
     queue: Vec<T>,
     jobs: usize,
     shutdown: bool,
+    /// Whether the stack was shut down because `cancellation` was triggered
+    cancelled: bool,
 }
 
 impl<T: Send> AsyncJobStack<T> {
-    fn new<I: IntoIterator<Item = T>>(items: I) -> Self {
+    /// Create a stack with the given initial jobs that shuts down once
+    /// `cancellation` is triggered.  Workers waiting for jobs periodically
+    /// wake up to check for this, which requires the Tokio runtime to have
+    /// timers enabled if the traversal can be cancelled.
+    fn new<I: IntoIterator<Item = T>>(items: I, cancellation: Cancellation) -> Self {
         let queue: Vec<T> = items.into_iter().collect();
         let jobs = queue.len();
         AsyncJobStack {
@@ -30,8 +48,10 @@ impl<T: Send> AsyncJobStack<T> {
                 queue,
                 jobs,
                 shutdown: false,
+                cancelled: false,
             }),
             cond: Notify::new(),
+            cancellation,
         }
     }
 
@@ -54,6 +74,10 @@ impl<T: Send> AsyncJobStack<T> {
             .data
             .lock()
             .expect("Mutex should not have been poisoned");
+        self.shutdown_locked(&mut data);
+    }
+
+    fn shutdown_locked(&self, data: &mut AsyncJobStackData<T>) {
         if !data.shutdown {
             log::trace!("Shutting down stack");
             data.jobs -= data.queue.len();
@@ -70,6 +94,15 @@ impl<T: Send> AsyncJobStack<T> {
             .shutdown
     }
 
+    /// Returns `true` if the stack was shut down because its cancellation
+    /// conditions were triggered
+    fn is_cancelled(&self) -> bool {
+        self.data
+            .lock()
+            .expect("Mutex should not have been poisoned")
+            .cancelled
+    }
+
     async fn handle_many_jobs<F, Fut, I, E>(&self, f: F) -> Result<(), E>
     where
         F: Fn(T) -> Fut + Send,
@@ -100,6 +133,11 @@ impl<T: Send> AsyncJobStack<T> {
                     .data
                     .lock()
                     .expect("Mutex should not have been poisoned");
+                if !data.shutdown && self.cancellation.is_cancelled() {
+                    log::trace!("[pop] traversal cancelled");
+                    self.shutdown_locked(&mut data);
+                    data.cancelled = true;
+                }
                 if data.jobs == 0 || data.shutdown {
                     log::trace!("[pop] no jobs; returning None");
                     return None;
@@ -109,7 +147,11 @@ impl<T: Send> AsyncJobStack<T> {
                 }
             }
             log::trace!("[pop] queue is empty; waiting");
-            self.cond.notified().await;
+            if let Some(interval) = self.cancellation.wait_interval() {
+                let _ = tokio::time::timeout(interval, self.cond.notified()).await;
+            } else {
+                self.cond.notified().await;
+            }
         }
     }
 
@@ -133,14 +175,64 @@ impl<T: Send> AsyncJobStack<T> {
 ///
 /// This builds an in-memory tree of all file checksums for computing the final
 /// Zarr checksum.
+///
+/// If the Zarr has a cancel token or deadline, the Tokio runtime must have
+/// timers enabled.
 pub async fn fastasync_checksum(
     zarr: &Zarr,
     workers: NonZeroUsize,
 ) -> Result<String, ChecksumError> {
-    let stack = Arc::new(AsyncJobStack::new([ZarrEntry::Directory(zarr.root_dir())]));
-    let (sender, mut receiver) = channel(64);
+    let mut stream = fastasync_file_checksums(zarr, workers, BUFFER_SIZE);
+    // Force the stream to receive everything (rather than breaking out early
+    // on an Err) in order to ensure that all workers run to completion
+    let mut tree = Ok(ChecksumTree::new());
+    let mut err = None;
+    while let Some(v) = stream.recv().await {
+        match v {
+            Ok(i) => {
+                tree = tree.and_then(|mut t| {
+                    t.add_file(i)?;
+                    Ok(t)
+                });
+            }
+            Err(e) => {
+                err.get_or_insert(e);
+            }
+        }
+    }
+    join_all(std::mem::take(&mut stream.handles)).await;
+    match err {
+        Some(e) => Err(e.into()),
+        None => tree.map(ChecksumTree::into_checksum),
+    }
+}
+
+/// Asynchronously traverse a Zarr directory using a stack of jobs distributed
+/// over multiple worker tasks, returning a [`Stream`] of the checksums for the
+/// files within as they are computed
+///
+/// The `workers` argument determines the number of worker tasks to use.  At
+/// most `buffer` results are held in memory at once; when the buffer is full,
+/// the workers wait for the consumer to catch up.  The first error
+/// encountered shuts down the traversal, after which the stream yields any
+/// remaining buffered items and then ends.
+///
+/// This function spawns the worker tasks immediately and thus must be called
+/// from within a Tokio runtime.  If the Zarr has a cancel token or deadline,
+/// the runtime must have timers enabled.
+pub fn fastasync_file_checksums(
+    zarr: &Zarr,
+    workers: NonZeroUsize,
+    buffer: NonZeroUsize,
+) -> FileChecksumStream {
+    let stack = Arc::new(AsyncJobStack::new(
+        [ZarrEntry::Directory(zarr.root_dir())],
+        zarr.cancellation().clone(),
+    ));
+    let (sender, receiver) = channel(buffer.get());
+    let mut handles = Vec::with_capacity(workers.get());
     for task_no in 0..workers.get() {
-        tokio::spawn({
+        handles.push(tokio::spawn({
             let stack = Arc::clone(&stack);
             let sender = sender.clone();
             async move {
@@ -188,28 +280,80 @@ pub async fn fastasync_checksum(
                     .await;
                 log::trace!("[{task_no}] Ending worker");
             }
-        });
+        }));
     }
-    drop(sender);
-    // Force the receiver to receive everything (rather than breaking out early
-    // on an Err) in order to ensure that all workers run to completion
-    let mut tree = Ok(ChecksumTree::new());
-    let mut err = None;
-    while let Some(v) = receiver.recv().await {
-        match v {
-            Ok(i) => {
-                tree = tree.and_then(|mut t| {
-                    t.add_file(i)?;
-                    Ok(t)
-                });
+    FileChecksumStream {
+        receiver,
+        stack,
+        handles,
+        failed: false,
+    }
+}
+
+/// A [`Stream`] of the checksums for the files in a Zarr, returned by
+/// [`fastasync_file_checksums()`]
+///
+/// Dropping the stream before it is exhausted shuts down the traversal.  The
+/// worker tasks stop shortly afterwards but are not awaited.
+#[derive(Debug)]
+pub struct FileChecksumStream {
+    receiver: Receiver<Result<FileChecksum, FSError>>,
+    stack: Arc<AsyncJobStack<ZarrEntry>>,
+    handles: Vec<JoinHandle<()>>,
+    /// Whether an error has been yielded
+    failed: bool,
+}
+
+impl FileChecksumStream {
+    /// Receive the next file checksum or error, or `None` if the traversal is
+    /// complete
+    pub async fn recv(&mut self) -> Option<Result<FileChecksum, FSError>> {
+        let r = self.receiver.recv().await;
+        self.track(r)
+    }
+
+    fn track(
+        &mut self,
+        r: Option<Result<FileChecksum, FSError>>,
+    ) -> Option<Result<FileChecksum, FSError>> {
+        match r {
+            Some(r) => {
+                self.failed |= r.is_err();
+                Some(r)
             }
-            Err(e) => {
-                err.get_or_insert(e);
+            // If the workers stopped because the traversal was cancelled
+            // while they were idle, no error was sent, so report one here.
+            None if !self.failed && self.stack.is_cancelled() => {
+                self.failed = true;
+                Some(Err(FSError::Cancelled))
             }
+            None => None,
         }
     }
-    match err {
-        Some(e) => Err(e.into()),
-        None => tree.map(ChecksumTree::into_checksum),
+}
+
+impl Stream for FileChecksumStream {
+    type Item = Result<FileChecksum, FSError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx).map(|r| self.track(r))
+    }
+}
+
+impl Drop for FileChecksumStream {
+    fn drop(&mut self) {
+        self.stack.shutdown();
+        self.receiver.close();
+    }
+}
+
+/// Wait for all of the given tasks to finish, propagating any panics
+async fn join_all(handles: Vec<JoinHandle<()>>) {
+    for h in handles {
+        if let Err(e) = h.await {
+            if let Ok(payload) = e.try_into_panic() {
+                std::panic::resume_unwind(payload);
+            }
+        }
     }
 }
@@ -1,12 +1,19 @@
-use super::jobstack::JobStack;
+use super::jobstack::{entry_size, JobStack, Scheduler};
+use super::stealing::StealingScheduler;
 use super::util::Output;
+use crate::checksum::nodes::*;
 use crate::checksum::ChecksumTree;
-use crate::errors::ChecksumError;
+use crate::errors::{ChecksumError, FSError};
 use crate::zarr::*;
 use std::num::NonZeroUsize;
-use std::sync::mpsc::channel;
-use std::sync::Arc;
-use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The number of results that [`fastio_checksum_tree()`] buffers between the
+/// worker threads and the tree-building thread
+const BUFFER_SIZE: NonZeroUsize = NonZeroUsize::new(64).unwrap();
 
 /// Traverse & checksum a Zarr directory using a stack of jobs distributed over
 /// multiple threads
@@ -23,55 +30,20 @@ pub fn fastio_checksum_tree(
     zarr: &Zarr,
     threads: NonZeroUsize,
 ) -> Result<ChecksumTree, ChecksumError> {
-    let stack = Arc::new(JobStack::new([ZarrEntry::Directory(zarr.root_dir())]));
-    let (sender, receiver) = channel();
-    for thread_no in 0..threads.get() {
-        let stack = Arc::clone(&stack);
-        let sender = sender.clone();
-        thread::spawn(move || {
-            log::trace!("[{thread_no}] Starting thread");
-            let _ = stack.handle_many_jobs(|entry| {
-                log::trace!("[{thread_no}] Popped {entry:?} from stack");
-                let output = match entry {
-                    ZarrEntry::Directory(zd) => match zd.entries() {
-                        Ok(entries) => {
-                            for n in &entries {
-                                log::trace!("[{thread_no}] Pushing {n:?} onto stack");
-                            }
-                            Output::ToPush(entries)
-                        }
-                        Err(e) => Output::ToSend(Err(e)),
-                    },
-                    ZarrEntry::File(zf) => Output::ToSend(zf.into_checksum()),
-                };
-                match output {
-                    Output::ToPush(to_push) => Ok(to_push),
-                    Output::ToSend(to_send) => {
-                        // If we've shut down, don't send anything except Errs
-                        if to_send.is_err() || !stack.is_shutdown() {
-                            if to_send.is_err() {
-                                stack.shutdown();
-                            }
-                            log::trace!("[{thread_no}] Sending {to_send:?} to output");
-                            if let Err(e) = sender.send(to_send) {
-                                log::warn!("[{thread_no}] Failed to send; exiting");
-                                return Err(e);
-                            }
-                        }
-                        Ok(Vec::new())
-                    }
-                    Output::Nil => Ok(Vec::new()),
-                }
-            });
-            log::trace!("[{thread_no}] Ending thread");
-        });
-    }
-    drop(sender);
-    // Force the receiver to receive everything (rather than breaking out early
+    build_tree(fastio_file_checksums(zarr, threads, BUFFER_SIZE))
+}
+
+/// Build a [`ChecksumTree`] from the results sent by the worker threads,
+/// returning the first error if there were any
+fn build_tree<I>(results: I) -> Result<ChecksumTree, ChecksumError>
+where
+    I: IntoIterator<Item = Result<FileChecksum, FSError>>,
+{
+    // Force the iterator to receive everything (rather than breaking out early
     // on an Err) in order to ensure that all threads run to completion
     let mut tree = Ok(ChecksumTree::new());
     let mut err = None;
-    for v in receiver {
+    for v in results {
         match v {
             Ok(i) => {
                 tree = tree.and_then(|mut t| {
@@ -89,3 +61,433 @@ pub fn fastio_checksum_tree(
         None => tree,
     }
 }
+
+/// Handle jobs from `scheduler` on the current thread until there are none
+/// left, sending file checksums and errors to `sender`
+fn run_worker<S: Scheduler<ZarrEntry>>(
+    scheduler: &S,
+    worker: S::Worker,
+    sender: &SyncSender<Result<FileChecksum, FSError>>,
+    thread_no: usize,
+) {
+    log::trace!("[{thread_no}] Starting thread");
+    let _ = scheduler.handle_many_jobs(worker, |entry| {
+        log::trace!("[{thread_no}] Popped {entry:?} from stack");
+        let output = match entry {
+            ZarrEntry::Directory(zd) => match zd.entries() {
+                Ok(entries) => {
+                    for n in &entries {
+                        log::trace!("[{thread_no}] Pushing {n:?} onto stack");
+                    }
+                    Output::ToPush(entries)
+                }
+                Err(e) => Output::ToSend(Err(e)),
+            },
+            ZarrEntry::File(zf) => Output::ToSend(zf.into_checksum()),
+        };
+        match output {
+            Output::ToPush(to_push) => Ok(to_push),
+            Output::ToSend(to_send) => {
+                // If we've shut down, don't send anything except Errs
+                if to_send.is_err() || !scheduler.is_shutdown() {
+                    if to_send.is_err() {
+                        scheduler.shutdown();
+                    }
+                    log::trace!("[{thread_no}] Sending {to_send:?} to output");
+                    if let Err(e) = sender.send(to_send) {
+                        log::warn!("[{thread_no}] Failed to send; exiting");
+                        return Err(e);
+                    }
+                }
+                Ok(Vec::new())
+            }
+            Output::Nil => Ok(Vec::new()),
+        }
+    });
+    log::trace!("[{thread_no}] Ending thread");
+}
+
+/// Traverse & checksum a Zarr directory using a work-stealing scheduler to
+/// distribute jobs over multiple threads
+///
+/// The `threads` argument determines the number of worker threads to use.
+///
+/// This behaves the same as [`fastio_checksum()`], except that each thread
+/// keeps its own queue of jobs and steals jobs from the other threads when it
+/// runs out, rather than all threads sharing a single stack.
+pub fn fastio_stealing_checksum(
+    zarr: &Zarr,
+    threads: NonZeroUsize,
+) -> Result<String, ChecksumError> {
+    let (scheduler, queues) =
+        StealingScheduler::new([ZarrEntry::Directory(zarr.root_dir())], threads);
+    let scheduler = scheduler.with_cancellation(zarr.cancellation().clone());
+    let (sender, receiver) = sync_channel(BUFFER_SIZE.get());
+    thread::scope(|scope| {
+        for (thread_no, queue) in queues.into_iter().enumerate() {
+            let scheduler = &scheduler;
+            let sender = sender.clone();
+            scope.spawn(move || run_worker(scheduler, queue, &sender, thread_no));
+        }
+        drop(sender);
+        let tree = build_tree(receiver)?;
+        if scheduler.is_cancelled() {
+            Err(ChecksumError::Cancelled)
+        } else {
+            Ok(tree.into_checksum())
+        }
+    })
+}
+
+/// Traverse a Zarr directory using a stack of jobs distributed over multiple
+/// threads, returning an iterator over the checksums for the files within as
+/// they are computed
+///
+/// The `threads` argument determines the number of worker threads to use.  At
+/// most `buffer` results are held in memory at once; when the buffer is full,
+/// the worker threads block until the consumer catches up.  The first error
+/// encountered shuts down the traversal, after which the iterator yields any
+/// remaining buffered items and then ends.
+pub fn fastio_file_checksums(
+    zarr: &Zarr,
+    threads: NonZeroUsize,
+    buffer: NonZeroUsize,
+) -> FileChecksumIter {
+    let stack = Arc::new(
+        JobStack::with_order(
+            [ZarrEntry::Directory(zarr.root_dir())],
+            zarr.get_job_order(),
+            entry_size,
+        )
+        .with_cancellation(zarr.cancellation().clone()),
+    );
+    let (sender, receiver) = sync_channel(buffer.get());
+    let mut handles = Vec::with_capacity(threads.get());
+    for thread_no in 0..threads.get() {
+        let stack = Arc::clone(&stack);
+        let sender = sender.clone();
+        handles.push(thread::spawn(move || {
+            run_worker(&*stack, (), &sender, thread_no);
+        }));
+    }
+    FileChecksumIter {
+        receiver: Some(receiver),
+        stack,
+        handles,
+        failed: false,
+    }
+}
+
+/// An iterator over the checksums for the files in a Zarr, returned by
+/// [`fastio_file_checksums()`]
+///
+/// Dropping the iterator shuts down the traversal (if it is still running) and
+/// waits for all worker threads to finish.
+#[derive(Debug)]
+pub struct FileChecksumIter {
+    // Only `None` while being dropped
+    receiver: Option<Receiver<Result<FileChecksum, FSError>>>,
+    stack: Arc<JobStack<ZarrEntry>>,
+    handles: Vec<JoinHandle<()>>,
+    /// Whether an error has been yielded
+    failed: bool,
+}
+
+impl Iterator for FileChecksumIter {
+    type Item = Result<FileChecksum, FSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.as_ref()?.recv() {
+            Ok(r) => {
+                self.failed |= r.is_err();
+                Some(r)
+            }
+            // If the workers stopped because the traversal was cancelled
+            // while they were idle, no error was sent, so report one here.
+            Err(_) if !self.failed && self.stack.is_cancelled() => {
+                self.failed = true;
+                Some(Err(FSError::Cancelled))
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+impl Drop for FileChecksumIter {
+    fn drop(&mut self) {
+        self.stack.shutdown();
+        // Drop the receiver so that any threads blocked on sending wake up
+        self.receiver = None;
+        for h in self.handles.drain(..) {
+            if let Err(payload) = h.join() {
+                if !thread::panicking() {
+                    std::panic::resume_unwind(payload);
+                }
+            }
+        }
+    }
+}
+
+/// Traverse & checksum a Zarr directory using a stack of jobs distributed over
+/// multiple threads, computing the checksum for each directory as soon as all
+/// of its entries have been checksummed
+///
+/// The `threads` argument determines the number of worker threads to use.
+///
+/// Directories are listed incrementally, and no more entries are listed while
+/// `max_in_flight` or more listed entries and partially listed directories
+/// are still waiting to be processed, so that memory usage and the number of
+/// open directories are bounded by the size of the largest directory and the
+/// depth of the hierarchy rather than by the total number of files.  (In
+/// order to guarantee progress, each worker thread may list one entry beyond
+/// the ceiling.)
+///
+/// Jobs are always taken in LIFO order, regardless of [`Zarr::job_order()`].
+pub fn fastio_bounded_checksum(
+    zarr: &Zarr,
+    threads: NonZeroUsize,
+    max_in_flight: NonZeroUsize,
+) -> Result<String, ChecksumError> {
+    let budget = Budget {
+        in_flight: AtomicUsize::new(1),
+        max: max_in_flight.get(),
+    };
+    // Under any other order, subdirectories may be opened before the entries
+    // listed ahead of them are processed, so that as many as `max_in_flight`
+    // directories can be open at once (running into the open file limit);
+    // hence, the Zarr's job order is ignored.
+    let stack = JobStack::new([BoundedJob::Entry(
+        ZarrEntry::Directory(zarr.root_dir()),
+        None,
+    )])
+    .with_cancellation(zarr.cancellation().clone());
+    let (sender, receiver) = channel();
+    thread::scope(|scope| {
+        for thread_no in 0..threads.get() {
+            let stack = &stack;
+            let budget = &budget;
+            let sender = sender.clone();
+            scope.spawn(move || {
+                log::trace!("[{thread_no}] Starting thread");
+                let _ = stack.handle_many_jobs(|job| {
+                    log::trace!("[{thread_no}] Popped {job:?} from stack");
+                    match job.process(budget) {
+                        Output::ToPush(to_push) => Ok(to_push),
+                        Output::ToSend(to_send) => {
+                            // If we've shut down, don't send anything except Errs
+                            if to_send.is_err() || !stack.is_shutdown() {
+                                if to_send.is_err() {
+                                    stack.shutdown();
+                                }
+                                log::trace!("[{thread_no}] Sending {to_send:?} to output");
+                                if let Err(e) = sender.send(to_send) {
+                                    log::warn!("[{thread_no}] Failed to send; exiting");
+                                    return Err(e);
+                                }
+                            }
+                            Ok(Vec::new())
+                        }
+                        Output::Nil => Ok(Vec::new()),
+                    }
+                });
+                log::trace!("[{thread_no}] Ending thread");
+            });
+        }
+        drop(sender);
+        // Force the receiver to receive everything (rather than breaking out
+        // early on an Err) in order to ensure that all threads run to
+        // completion
+        let mut chksum = None;
+        let mut err = None;
+        for v in receiver {
+            match v {
+                Ok(s) => {
+                    chksum.get_or_insert(s);
+                }
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
+            }
+        }
+        match (chksum, err) {
+            (_, Some(e)) => Err(e.into()),
+            (_, None) if stack.is_cancelled() => Err(ChecksumError::Cancelled),
+            (Some(s), None) => Ok(s),
+            (None, None) => panic!("No checksum or error emitted!"),
+        }
+    })
+}
+
+/// Bookkeeping for the ceiling on in-flight entries in
+/// [`fastio_bounded_checksum()`]
+#[derive(Debug)]
+struct Budget {
+    /// The number of jobs on the stack, i.e., listed entries that have not
+    /// yet been popped off the stack plus partially listed directories, each
+    /// of which holds an open directory handle
+    in_flight: AtomicUsize,
+    max: usize,
+}
+
+impl Budget {
+    /// Return the number of entries that may be listed right now, which is
+    /// always at least one
+    fn available(&self) -> usize {
+        self.max
+            .saturating_sub(self.in_flight.load(Ordering::Acquire))
+            .max(1)
+    }
+
+    fn acquire(&self, n: usize) {
+        self.in_flight.fetch_add(n, Ordering::AcqRel);
+    }
+
+    fn release(&self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug)]
+enum BoundedJob {
+    Entry(ZarrEntry, Option<Arc<PendingDir>>),
+    /// A directory that has been partially listed
+    Listing(Entries, Arc<PendingDir>),
+}
+
+impl BoundedJob {
+    fn process(self, budget: &Budget) -> Output<BoundedJob, String> {
+        match self {
+            BoundedJob::Entry(ZarrEntry::Directory(zd), parent) => {
+                // The directory's share of the budget is handed over to its
+                // listing, which releases it when processed.
+                match zd.iter_entries() {
+                    Ok(entries) => {
+                        let dir = Arc::new(PendingDir::new(zd, parent));
+                        BoundedJob::Listing(entries, dir).process(budget)
+                    }
+                    Err(e) => {
+                        budget.release();
+                        Output::ToSend(Err(e))
+                    }
+                }
+            }
+            BoundedJob::Entry(ZarrEntry::File(zf), parent) => {
+                budget.release();
+                let parent = parent.expect("File without a parent directory");
+                match zf.into_checksum() {
+                    Ok(node) => parent.resolve(Some(node.into()), false),
+                    Err(e) => Output::ToSend(Err(e)),
+                }
+            }
+            BoundedJob::Listing(mut entries, dir) => {
+                budget.release();
+                let mut to_push = Vec::new();
+                for r in entries.by_ref().take(budget.available()) {
+                    match r {
+                        Ok(entry) => to_push.push(entry),
+                        Err(e) => return Output::ToSend(Err(e)),
+                    }
+                }
+                if to_push.is_empty() {
+                    return dir.resolve(None, true);
+                }
+                // Account for the new entries before they can be popped by
+                // other threads, lest the directory be collapsed too early
+                dir.add_pending(to_push.len());
+                // The continuation holds the directory open, so it counts
+                // against the budget as well.
+                budget.acquire(to_push.len() + 1);
+                // The continuation goes at the bottom of the new jobs so that
+                // the entries listed so far are processed before listing more.
+                let mut jobs = Vec::with_capacity(to_push.len() + 1);
+                jobs.push(BoundedJob::Listing(entries, Arc::clone(&dir)));
+                jobs.extend(
+                    to_push
+                        .into_iter()
+                        .map(|entry| BoundedJob::Entry(entry, Some(Arc::clone(&dir)))),
+                );
+                Output::ToPush(jobs)
+            }
+        }
+    }
+}
+
+/// A directory in [`fastio_bounded_checksum()`] whose checksum has not yet
+/// been computed
+#[derive(Debug)]
+struct PendingDir {
+    dir: ZarrDirectory,
+    parent: Option<Arc<PendingDir>>,
+    data: Mutex<PendingDirData>,
+}
+
+#[derive(Debug)]
+struct PendingDirData {
+    summer: Dirsummer,
+    /// The number of listed entries whose checksums have not yet been added
+    pending: usize,
+    /// Whether the directory has been completely listed
+    listed: bool,
+}
+
+impl PendingDir {
+    fn new(dir: ZarrDirectory, parent: Option<Arc<PendingDir>>) -> PendingDir {
+        let summer = dir.dirsummer();
+        PendingDir {
+            dir,
+            parent,
+            data: Mutex::new(PendingDirData {
+                summer,
+                pending: 0,
+                listed: false,
+            }),
+        }
+    }
+
+    fn add_pending(&self, n: usize) {
+        self.data
+            .lock()
+            .expect("Mutex should not have been poisoned")
+            .pending += n;
+    }
+
+    /// Add the checksum for an entry of the directory (if any) and/or mark
+    /// the directory as completely listed.  If this leaves the directory with
+    /// nothing outstanding, collapse it into its checksum and add that to the
+    /// parent directory, and so on up the hierarchy.  If the root directory is
+    /// collapsed, its checksum is returned for sending.
+    fn resolve(
+        &self,
+        mut node: Option<EntryChecksum>,
+        mut listed: bool,
+    ) -> Output<BoundedJob, String> {
+        let mut dir = self;
+        loop {
+            let mut data = dir
+                .data
+                .lock()
+                .expect("Mutex should not have been poisoned");
+            if let Some(n) = node.take() {
+                data.summer.push(n);
+                data.pending -= 1;
+            }
+            data.listed |= listed;
+            if !data.listed || data.pending > 0 {
+                return Output::Nil;
+            }
+            log::trace!(
+                "Computed all checksums within directory {}; collapsing",
+                dir.dir.relpath()
+            );
+            let chksum = dir.dir.collapse(&data.summer);
+            match dir.parent {
+                Some(ref parent) => {
+                    node = Some(chksum.into());
+                    listed = false;
+                    dir = parent;
+                }
+                None => return Output::ToSend(Ok(chksum.into_checksum())),
+            }
+        }
+    }
+}
@@ -1,33 +1,75 @@
 #![allow(dead_code)]
-use std::sync::{Condvar, Mutex};
+use super::sync::{Condvar, Mutex};
+use crate::cancel::Cancellation;
+use crate::zarr::{JobOrder, ZarrEntry};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
 
 #[derive(Debug)]
 pub(crate) struct JobStack<T> {
     data: Mutex<JobStackData<T>>,
     cond: Condvar,
+    cancellation: Cancellation,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug)]
 struct JobStackData<T> {
-    queue: Vec<T>,
+    queue: Queue<T>,
     jobs: usize,
     shutdown: bool,
+    /// Whether the stack was shut down because `cancellation` was triggered
+    cancelled: bool,
 }
 
 impl<T> JobStack<T> {
+    /// Create a stack with the given initial jobs that hands out jobs in LIFO
+    /// order
     pub(crate) fn new<I: IntoIterator<Item = T>>(items: I) -> Self {
-        let queue: Vec<T> = items.into_iter().collect();
+        JobStack::with_queue(items, Queue::Lifo(Vec::new()))
+    }
+
+    /// Create a stack with the given initial jobs that hands out jobs in the
+    /// given order.  For [`JobOrder::LargestFirst`], `size_of` is called on
+    /// each job as it is pushed to determine its priority.
+    pub(crate) fn with_order<I: IntoIterator<Item = T>>(
+        items: I,
+        order: JobOrder,
+        size_of: fn(&T) -> u64,
+    ) -> Self {
+        let queue = match order {
+            JobOrder::Lifo => Queue::Lifo(Vec::new()),
+            JobOrder::Fifo => Queue::Fifo(VecDeque::new()),
+            JobOrder::LargestFirst => Queue::LargestFirst {
+                heap: BinaryHeap::new(),
+                size_of,
+                seq: 0,
+            },
+        };
+        JobStack::with_queue(items, queue)
+    }
+
+    fn with_queue<I: IntoIterator<Item = T>>(items: I, mut queue: Queue<T>) -> Self {
+        queue.extend(items);
         let jobs = queue.len();
         JobStack {
             data: Mutex::new(JobStackData {
                 queue,
                 jobs,
                 shutdown: false,
+                cancelled: false,
             }),
             cond: Condvar::new(),
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Shut the stack down once `cancellation` is triggered.  Workers waiting
+    /// for jobs periodically wake up to check for this.
+    pub(crate) fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
     pub(crate) fn handle_job<F, I, E>(&self, f: F) -> Result<bool, E>
     where
         F: FnOnce(T) -> Result<I, E>,
@@ -76,6 +118,10 @@ impl<T> JobStack<T> {
             .data
             .lock()
             .expect("Mutex should not have been poisoned");
+        self.shutdown_locked(&mut data);
+    }
+
+    fn shutdown_locked(&self, data: &mut JobStackData<T>) {
         if !data.shutdown {
             log::trace!("[JobStack] Shutting down stack");
             data.jobs -= data.queue.len();
@@ -92,6 +138,15 @@ impl<T> JobStack<T> {
             .shutdown
     }
 
+    /// Returns `true` if the stack was shut down because its cancellation
+    /// conditions were triggered
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.data
+            .lock()
+            .expect("Mutex should not have been poisoned")
+            .cancelled
+    }
+
     fn pop(&self) -> Option<T> {
         let mut data = self
             .data
@@ -99,12 +154,24 @@ impl<T> JobStack<T> {
             .expect("Mutex should not have been poisoned");
         loop {
             log::trace!("[JobStack] Looping through stack");
+            if !data.shutdown && self.cancellation.is_cancelled() {
+                log::trace!("[JobStack] Traversal cancelled");
+                self.shutdown_locked(&mut data);
+                data.cancelled = true;
+            }
             if data.jobs == 0 || data.shutdown {
                 log::trace!("[JobStack] no jobs; returning None");
                 return None;
             }
             if let value @ Some(_) = data.queue.pop() {
                 return value;
+            } else if let Some(interval) = self.cancellation.wait_interval() {
+                log::trace!("[JobStack] queue is empty; waiting");
+                data = self
+                    .cond
+                    .wait_timeout(data, interval)
+                    .expect("Mutex should not have been poisoned")
+                    .0;
             } else {
                 log::trace!("[JobStack] queue is empty; waiting");
                 data = self
@@ -142,3 +209,297 @@ impl<T> JobStack<T> {
         }
     }
 }
+
+/// The operations that the job-based walkers need from a scheduler that
+/// distributes jobs of type `T` over a number of worker threads
+pub(crate) trait Scheduler<T> {
+    /// The state that each worker thread needs in order to take part in
+    /// handling jobs
+    type Worker;
+
+    /// Repeatedly take jobs and pass them to `f`, scheduling the jobs that it
+    /// returns, until there are no jobs left or the scheduler is shut down.
+    /// If `f` returns an error, the scheduler is shut down and the error is
+    /// returned.
+    fn handle_many_jobs<F, I, E>(&self, worker: Self::Worker, f: F) -> Result<(), E>
+    where
+        F: FnMut(T) -> Result<I, E>,
+        I: IntoIterator<Item = T>;
+
+    /// Discard all pending jobs and make all workers stop once they are done
+    /// with their current job
+    fn shutdown(&self);
+
+    fn is_shutdown(&self) -> bool;
+
+    /// Returns `true` if the scheduler was shut down because its cancellation
+    /// conditions were triggered
+    fn is_cancelled(&self) -> bool;
+}
+
+impl<T> Scheduler<T> for JobStack<T> {
+    type Worker = ();
+
+    fn handle_many_jobs<F, I, E>(&self, (): (), f: F) -> Result<(), E>
+    where
+        F: FnMut(T) -> Result<I, E>,
+        I: IntoIterator<Item = T>,
+    {
+        JobStack::handle_many_jobs(self, f)
+    }
+
+    fn shutdown(&self) {
+        JobStack::shutdown(self);
+    }
+
+    fn is_shutdown(&self) -> bool {
+        JobStack::is_shutdown(self)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        JobStack::is_cancelled(self)
+    }
+}
+
+/// The size of a [`ZarrEntry`] job for [`JobOrder::LargestFirst`]: a
+/// directory counts as larger than any file so that listing (and thus the
+/// discovery of large files) happens as early as possible, and files count as
+/// their size as recorded at listing time
+pub(crate) fn entry_size(entry: &ZarrEntry) -> u64 {
+    match entry {
+        ZarrEntry::Directory(_) => u64::MAX,
+        ZarrEntry::File(zf) => zf.listed_size().unwrap_or(0),
+    }
+}
+
+/// The jobs waiting in a [`JobStack`], stored according to the order in which
+/// they are to be handed out
+#[derive(Clone, Debug)]
+enum Queue<T> {
+    Lifo(Vec<T>),
+    Fifo(VecDeque<T>),
+    LargestFirst {
+        heap: BinaryHeap<SizedJob<T>>,
+        size_of: fn(&T) -> u64,
+        /// The number of jobs pushed so far, used to break ties in favor of
+        /// the most recently pushed job
+        seq: u64,
+    },
+}
+
+impl<T> Queue<T> {
+    fn len(&self) -> usize {
+        match self {
+            Queue::Lifo(v) => v.len(),
+            Queue::Fifo(v) => v.len(),
+            Queue::LargestFirst { heap, .. } => heap.len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Queue::Lifo(v) => v.clear(),
+            Queue::Fifo(v) => v.clear(),
+            Queue::LargestFirst { heap, .. } => heap.clear(),
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        match self {
+            Queue::Lifo(v) => v.pop(),
+            Queue::Fifo(v) => v.pop_front(),
+            Queue::LargestFirst { heap, .. } => heap.pop().map(|s| s.item),
+        }
+    }
+
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        match self {
+            Queue::Lifo(v) => v.extend(iter),
+            Queue::Fifo(v) => v.extend(iter),
+            Queue::LargestFirst { heap, size_of, seq } => {
+                for item in iter {
+                    heap.push(SizedJob {
+                        size: size_of(&item),
+                        seq: *seq,
+                        item,
+                    });
+                    *seq += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A job in a [`Queue::LargestFirst`], ordered by size and then by recency
+#[derive(Clone, Debug)]
+struct SizedJob<T> {
+    size: u64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for SizedJob<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for SizedJob<T> {}
+
+impl<T> PartialOrd for SizedJob<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for SizedJob<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.size, self.seq).cmp(&(other.size, other.seq))
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(zarr_loom))]
+mod test {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Run a single-threaded stack to completion, returning the jobs in the
+    /// order they were handled.  Each job `n` with `n >= 10` spawns jobs
+    /// `n / 10 * 2` and `n / 10 * 2 + 1`.
+    fn run(order: JobOrder) -> Vec<u64> {
+        let stack = JobStack::with_order([30, 5, 7], order, |&n| n);
+        let mut handled = Vec::new();
+        let r: Result<(), ()> = stack.handle_many_jobs(|n| {
+            handled.push(n);
+            Ok(if n >= 10 {
+                vec![n / 10 * 2, n / 10 * 2 + 1]
+            } else {
+                Vec::new()
+            })
+        });
+        assert!(r.is_ok());
+        handled
+    }
+
+    #[test]
+    fn test_lifo() {
+        assert_eq!(run(JobOrder::Lifo), [7, 5, 30, 7, 6]);
+    }
+
+    #[test]
+    fn test_fifo() {
+        assert_eq!(run(JobOrder::Fifo), [30, 5, 7, 6, 7]);
+    }
+
+    #[test]
+    fn test_largest_first() {
+        assert_eq!(run(JobOrder::LargestFirst), [30, 7, 7, 6, 5]);
+    }
+
+    #[test]
+    fn test_largest_first_ties_lifo() {
+        let stack = JobStack::with_order(["a", "b", "c"], JobOrder::LargestFirst, |_| 1);
+        let mut handled = Vec::new();
+        let r: Result<(), ()> = stack.handle_many_jobs(|s| {
+            handled.push(s);
+            Ok(None)
+        });
+        assert!(r.is_ok());
+        assert_eq!(handled, ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_cancel_wakes_idle_worker() {
+        let token = CancelToken::new();
+        let stack = JobStack::new([()]).with_cancellation(Cancellation {
+            token: Some(token.clone()),
+            deadline: None,
+        });
+        // Whichever worker takes the only job gives the other worker time to
+        // start waiting for jobs, cancels the traversal, and then holds onto
+        // the job until the stack is shut down, which only the idle worker can
+        // do
+        let work = |()| {
+            thread::sleep(Duration::from_millis(100));
+            token.cancel();
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(5) {
+                if stack.is_shutdown() {
+                    return Ok(None);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err("Idle worker did not notice cancellation")
+        };
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| stack.handle_many_jobs(work));
+            let r1 = stack.handle_many_jobs(work);
+            let r2 = handle.join().unwrap();
+            assert_eq!(r1.and(r2), Ok(()));
+        });
+        assert!(stack.is_cancelled());
+    }
+}
+
+/// Model-checked tests, run with `RUSTFLAGS="--cfg zarr_loom" cargo test
+/// --release --lib loom`
+#[cfg(test)]
+#[cfg(zarr_loom)]
+mod loom_test {
+    use super::*;
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    #[test]
+    fn test_loom_all_jobs_handled() {
+        loom::model(|| {
+            // Each job `n` spawns jobs `0..n`, so a stack starting with job 2
+            // handles four jobs in total, with new jobs pushed while the
+            // other thread may be waiting on an empty stack
+            let stack = Arc::new(JobStack::new([2]));
+            let handled = Arc::new(AtomicUsize::new(0));
+            let handle = {
+                let stack = Arc::clone(&stack);
+                let handled = Arc::clone(&handled);
+                thread::spawn(move || {
+                    stack.handle_many_jobs(|n| {
+                        handled.fetch_add(1, Ordering::Relaxed);
+                        Ok::<_, ()>(0..n)
+                    })
+                })
+            };
+            let r = stack.handle_many_jobs(|n| {
+                handled.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, ()>(0..n)
+            });
+            assert!(r.is_ok());
+            assert!(handle.join().unwrap().is_ok());
+            assert_eq!(handled.load(Ordering::Relaxed), 4);
+            assert!(!stack.is_shutdown());
+        });
+    }
+
+    #[test]
+    fn test_loom_error_shuts_down() {
+        loom::model(|| {
+            let stack = Arc::new(JobStack::new([2, 3]));
+            let work = |n: u32| if n == 0 { Err(n) } else { Ok(0..n) };
+            let handle = {
+                let stack = Arc::clone(&stack);
+                thread::spawn(move || stack.handle_many_jobs(work))
+            };
+            let r1 = stack.handle_many_jobs(work);
+            let r2 = handle.join().unwrap();
+            // Whichever thread handles a job 0 shuts down the stack, after
+            // which the other thread must stop rather than wait for jobs
+            // forever
+            assert!(r1.is_err() || r2.is_err());
+            assert!(stack.is_shutdown());
+            assert_eq!(stack.data.lock().unwrap().jobs, 0);
+        });
+    }
+}
@@ -0,0 +1,225 @@
+use super::util::list_files;
+use crate::checksum::{ChecksumTree, FileChecksum};
+use crate::errors::{ChecksumError, FSError};
+use crate::util::{FileStamp, Md5Engine, OpenFile};
+use crate::zarr::*;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use std::io::Read;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, available_parallelism};
+
+/// Tuning parameters for [`multibuffer_checksum()`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MultiBufferConfig {
+    /// The number of worker threads
+    pub threads: NonZeroUsize,
+    /// Files no larger than this many bytes are read into memory and hashed
+    /// in batches; larger files are read & hashed one at a time
+    pub small_file_size: u64,
+    /// The number of small files that each thread collects before hashing
+    /// them together
+    pub batch_size: NonZeroUsize,
+}
+
+impl Default for MultiBufferConfig {
+    /// One thread per logical CPU, files up to 64 KiB treated as small, and
+    /// batches of 64 files
+    fn default() -> MultiBufferConfig {
+        MultiBufferConfig {
+            threads: available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            small_file_size: 64 << 10,
+            batch_size: NonZeroUsize::new(64).unwrap_or(NonZeroUsize::MIN),
+        }
+    }
+}
+
+/// Traverse & checksum a Zarr directory, hashing small files several at a
+/// time with a multi-buffer MD5 implementation
+///
+/// Directories are listed on the calling thread, and the files found are
+/// passed over a bounded channel to `config.threads` worker threads.  Each
+/// worker reads files no larger than `config.small_file_size` into memory
+/// until it has collected `config.batch_size` of them (or runs out of files),
+/// at which point it computes their digests together, running one file
+/// through each lane of the widest SIMD unit available (eight lanes with AVX2,
+/// four with SSE2 or NEON, or one file at a time on other CPUs).  Larger files
+/// are read & hashed individually as they are received.
+///
+/// This builds an in-memory tree of all file checksums for computing the final
+/// Zarr checksum.  Small hardlinked files are always read once per link,
+/// regardless of [`Zarr::dedup_hardlinks()`].
+pub fn multibuffer_checksum(
+    zarr: &Zarr,
+    config: MultiBufferConfig,
+) -> Result<String, ChecksumError> {
+    let engine = Md5Engine::detect();
+    log::debug!(
+        "Hashing small files with {engine:?} engine ({} lanes)",
+        engine.lanes()
+    );
+    let (file_tx, file_rx) = bounded::<ZarrFile>(config.threads.get() * 2);
+    let (result_tx, result_rx) = unbounded();
+    let failed = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for thread_no in 0..config.threads.get() {
+            let worker = Worker {
+                thread_no,
+                zarr,
+                config: &config,
+                engine,
+                failed: &failed,
+                result_tx: result_tx.clone(),
+            };
+            let file_rx = file_rx.clone();
+            scope.spawn(move || worker.run(&file_rx));
+        }
+        // Drop our copies of the channel ends so that the channels close once
+        // the threads that use them are done.
+        drop(file_rx);
+        drop(result_tx);
+        if let Err(e) = list_files(zarr, &failed, &file_tx) {
+            failed.store(true, Ordering::Release);
+            return Err(e.into());
+        }
+        drop(file_tx);
+        // Force the receiver to receive everything (rather than breaking out
+        // early on an Err) in order to ensure that all threads run to
+        // completion
+        let mut tree: Result<_, ChecksumError> = Ok(ChecksumTree::new());
+        let mut err = None;
+        for v in result_rx {
+            match v {
+                Ok(node) => {
+                    tree = tree.and_then(|mut t| {
+                        t.add_file(node)?;
+                        Ok(t)
+                    });
+                }
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
+            }
+        }
+        match err {
+            Some(e) => Err(e.into()),
+            None => tree.map(ChecksumTree::into_checksum),
+        }
+    })
+}
+
+/// The result of [`Worker::read_small()`]
+#[derive(Debug)]
+enum Contents {
+    /// The complete contents of a small file
+    Small(Vec<u8>),
+    /// An open handle to a file that is too large to read into memory whole
+    Large(OpenFile),
+}
+
+/// The state of a worker thread
+struct Worker<'a> {
+    thread_no: usize,
+    zarr: &'a Zarr,
+    config: &'a MultiBufferConfig,
+    engine: Md5Engine,
+    failed: &'a AtomicBool,
+    result_tx: Sender<Result<FileChecksum, FSError>>,
+}
+
+impl Worker<'_> {
+    fn run(&self, file_rx: &Receiver<ZarrFile>) {
+        let thread_no = self.thread_no;
+        log::trace!("[{thread_no}] Starting thread");
+        let mut batch = Vec::with_capacity(self.config.batch_size.get());
+        for zf in file_rx {
+            if self.failed.load(Ordering::Acquire) {
+                break;
+            }
+            match self.read_small(&zf) {
+                Ok(Contents::Small(data)) => {
+                    batch.push((zf, data));
+                    if batch.len() >= self.config.batch_size.get() {
+                        self.flush(&mut batch);
+                    }
+                }
+                Ok(Contents::Large(file)) => {
+                    log::trace!("[{thread_no}] Hashing large file {}", zf.relpath());
+                    self.send(zf.into_checksum_from(file));
+                }
+                Err(e) => self.send(Err(e)),
+            }
+        }
+        if !self.failed.load(Ordering::Acquire) {
+            self.flush(&mut batch);
+        }
+        log::trace!("[{thread_no}] Ending thread");
+    }
+
+    /// Read the given file into memory if it is no larger than
+    /// `config.small_file_size`, subject to the Zarr's
+    /// [`ModifiedPolicy`][crate::zarr::ModifiedPolicy].  If the file is too
+    /// large, the open handle to it is returned instead so that it can be
+    /// hashed without being opened again.
+    fn read_small(&self, zf: &ZarrFile) -> Result<Contents, FSError> {
+        let mut attempt = 1;
+        loop {
+            if self.zarr.is_cancelled() {
+                return Err(FSError::Cancelled);
+            }
+            let file = OpenFile::open(zf.path())?;
+            if file.metadata().len() > self.config.small_file_size {
+                return Ok(Contents::Large(file));
+            }
+            let (mut fp, md) = file.into_parts();
+            let before = FileStamp::from(&md);
+            // Allow for the file having grown since it was statted
+            let cap = usize::try_from(md.len()).unwrap_or(usize::MAX);
+            let mut data = Vec::with_capacity(cap.saturating_add(1));
+            if let Err(source) = fp.read_to_end(&mut data) {
+                return Err(FSError::Digest {
+                    path: zf.path().into(),
+                    source,
+                });
+            }
+            zf.progress().bytes_read(data.len() as u64);
+            let after = FileStamp::from(&fp.metadata()?);
+            if after.modified_since(&before, data.len() as u64) && zf.reread_modified(attempt)? {
+                attempt += 1;
+                continue;
+            }
+            return Ok(Contents::Small(data));
+        }
+    }
+
+    /// Hash all files in `batch` at once and send their checksums
+    fn flush(&self, batch: &mut Vec<(ZarrFile, Vec<u8>)>) {
+        if batch.is_empty() {
+            return;
+        }
+        log::trace!(
+            "[{}] Hashing batch of {} files",
+            self.thread_no,
+            batch.len()
+        );
+        let inputs = batch
+            .iter()
+            .map(|(_, data)| data.as_slice())
+            .collect::<Vec<_>>();
+        let digests = self.engine.digest_many(&inputs);
+        for ((zf, data), digest) in batch.drain(..).zip(digests) {
+            log::debug!("Computed checksum for file {}: {digest}", zf.relpath());
+            let node = FileChecksum::new(zf.relpath().clone(), digest, data.len() as u64);
+            zf.progress().file_hashed(&node);
+            self.send(Ok(node));
+        }
+    }
+
+    fn send(&self, r: Result<FileChecksum, FSError>) {
+        if r.is_err() {
+            self.failed.store(true, Ordering::Release);
+        }
+        // The receiver is only dropped after all workers have exited.
+        let _ = self.result_tx.send(r);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::checksum::{ChecksumTree, FileChecksum};
+use crate::errors::{ChecksumError, FSError};
+use crate::zarr::*;
+use std::cell::RefCell;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::{spawn_blocking, JoinSet};
+
+thread_local! {
+    /// Buffer reused by all files hashed on a given blocking-pool thread
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The result of a single task spawned by [`offload_checksum()`]
+#[derive(Debug)]
+enum Done {
+    Listed(Vec<ZarrEntry>),
+    Hashed(FileChecksum),
+    /// The traversal failed before the task could start
+    Skipped,
+}
+
+/// Asynchronously traverse & checksum a Zarr directory, listing directories
+/// in async tasks and hashing each file in a single blocking task
+///
+/// Each directory is listed in its own task, and each file is read & hashed
+/// in full by one call to [`tokio::task::spawn_blocking()`] using a buffer of
+/// `buffer_size` bytes, rather than with a separate round trip to the
+/// blocking pool for each small read.  At most `max_open` files and
+/// directories are open at once.
+///
+/// This must be called from within a Tokio runtime, which may be either
+/// single- or multi-threaded; the runtime's blocking pool is used for
+/// hashing.
+///
+/// This builds an in-memory tree of all file checksums for computing the final
+/// Zarr checksum.
+///
+/// After the first error, no further tasks are started, and the function
+/// returns once every task that was already running (including any hashing
+/// in the blocking pool) has finished.
+pub async fn offload_checksum(
+    zarr: &Zarr,
+    max_open: NonZeroUsize,
+    buffer_size: NonZeroUsize,
+) -> Result<String, ChecksumError> {
+    let permits = Arc::new(Semaphore::new(max_open.get()));
+    let mut tasks = JoinSet::new();
+    spawn_entry(
+        &mut tasks,
+        &permits,
+        ZarrEntry::Directory(zarr.root_dir()),
+        buffer_size,
+    );
+    let mut tree: Result<_, ChecksumError> = Ok(ChecksumTree::new());
+    let mut err = None;
+    // Tasks are never aborted, as aborting a task that is waiting on
+    // `spawn_blocking()` would leave the blocking closure running after we
+    // return.  Instead, after the first error, the semaphore is closed so
+    // that tasks still waiting for a permit give up, and the rest are awaited.
+    while let Some(r) = tasks.join_next().await {
+        let r = match r {
+            Ok(r) => r,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        match r {
+            Ok(Done::Listed(entries)) => {
+                if !permits.is_closed() {
+                    for entry in entries {
+                        spawn_entry(&mut tasks, &permits, entry, buffer_size);
+                    }
+                }
+            }
+            Ok(Done::Hashed(node)) => {
+                tree = tree.and_then(|mut t| {
+                    t.add_file(node)?;
+                    Ok(t)
+                });
+                if tree.is_err() {
+                    permits.close();
+                }
+            }
+            Ok(Done::Skipped) => (),
+            Err(e) => {
+                permits.close();
+                err.get_or_insert(e);
+            }
+        }
+    }
+    match err {
+        Some(e) => Err(e.into()),
+        None => tree.map(ChecksumTree::into_checksum),
+    }
+}
+
+fn spawn_entry(
+    tasks: &mut JoinSet<Result<Done, FSError>>,
+    permits: &Arc<Semaphore>,
+    entry: ZarrEntry,
+    buffer_size: NonZeroUsize,
+) {
+    let permits = Arc::clone(permits);
+    match entry {
+        ZarrEntry::Directory(zd) => {
+            tasks.spawn(async move {
+                let Ok(_permit) = permits.acquire_owned().await else {
+                    return Ok(Done::Skipped);
+                };
+                log::trace!("Listing directory {}", zd.relpath());
+                zd.async_entries().await.map(Done::Listed)
+            });
+        }
+        ZarrEntry::File(zf) => {
+            tasks.spawn(async move {
+                let Ok(permit) = permits.acquire_owned().await else {
+                    return Ok(Done::Skipped);
+                };
+                let r = spawn_blocking(move || {
+                    let _permit = permit;
+                    BUFFER.with_borrow_mut(|buf| {
+                        buf.resize(buffer_size.get(), 0);
+                        zf.into_checksum_with_buffer(buf)
+                    })
+                })
+                .await;
+                match r {
+                    Ok(r) => r.map(Done::Hashed),
+                    Err(e) => std::panic::resume_unwind(e.into_panic()),
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use super::util::list_files;
+use crate::checksum::{ChecksumTree, FileChecksum, Md5Digest};
+use crate::errors::{ChecksumError, FSError};
+use crate::util::FileStamp;
+use crate::zarr::*;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use fs_err::File;
+use md5::{Digest, Md5};
+use std::io::{ErrorKind, Read};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, available_parallelism};
+
+/// Tuning parameters for [`pipelined_checksum()`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PipelineConfig {
+    /// The number of threads reading files
+    pub readers: NonZeroUsize,
+    /// The number of threads hashing the contents of large files
+    pub hashers: NonZeroUsize,
+    /// The size of the buffers that large files are read into
+    pub buffer_size: NonZeroUsize,
+    /// The maximum number of buffers of a large file that may be read ahead
+    /// of the hashing of that file
+    pub queue_depth: NonZeroUsize,
+    /// Files no larger than this many bytes are read into a single buffer and
+    /// hashed by the thread that read them
+    pub small_file_size: u64,
+    /// Whether to advise the kernel that large files will be read
+    /// sequentially and in full (Linux only)
+    pub fadvise: bool,
+}
+
+impl Default for PipelineConfig {
+    /// One reader & one hasher per logical CPU, 1 MiB buffers, a queue depth
+    /// of 4, and files up to 64 KiB treated as small
+    fn default() -> PipelineConfig {
+        let threads = available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        PipelineConfig {
+            readers: threads,
+            hashers: threads,
+            buffer_size: NonZeroUsize::new(1 << 20).unwrap_or(NonZeroUsize::MIN),
+            queue_depth: NonZeroUsize::new(4).unwrap_or(NonZeroUsize::MIN),
+            small_file_size: 64 << 10,
+            fadvise: true,
+        }
+    }
+}
+
+/// Traverse & checksum a Zarr directory with separate pools of threads for
+/// reading and for hashing file contents
+///
+/// Directories are listed on the calling thread, and the files found are
+/// passed over a bounded channel to `config.readers` reader threads.  Files no
+/// larger than `config.small_file_size` are read in a single call and hashed
+/// by the reader itself, as handing them off would cost more than hashing
+/// them.  Larger files are read in chunks of `config.buffer_size` bytes that
+/// are passed over a bounded channel to one of `config.hashers` hashing
+/// threads, allowing the reader to stay up to `config.queue_depth` chunks
+/// ahead of the hasher; all chunks of a given file are hashed by the same
+/// thread, in order.
+///
+/// This builds an in-memory tree of all file checksums for computing the final
+/// Zarr checksum.  Hardlinked files are always read once per link, regardless
+/// of [`Zarr::dedup_hardlinks()`].
+pub fn pipelined_checksum(zarr: &Zarr, config: PipelineConfig) -> Result<String, ChecksumError> {
+    let (file_tx, file_rx) = bounded::<ZarrFile>(config.readers.get() * 2);
+    let (job_tx, job_rx) = bounded::<HashJob>(config.hashers.get());
+    let (result_tx, result_rx) = unbounded();
+    let (buf_tx, buf_rx) = unbounded();
+    let failed = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for thread_no in 0..config.hashers.get() {
+            let job_rx = job_rx.clone();
+            let buf_tx = buf_tx.clone();
+            let result_tx = result_tx.clone();
+            scope.spawn(move || hash_worker(thread_no, &job_rx, &buf_tx, &result_tx));
+        }
+        for thread_no in 0..config.readers.get() {
+            let reader = Reader {
+                zarr,
+                config: &config,
+                failed: &failed,
+                job_tx: job_tx.clone(),
+                buf_tx: buf_tx.clone(),
+                buf_rx: buf_rx.clone(),
+            };
+            let file_rx = file_rx.clone();
+            let result_tx = result_tx.clone();
+            scope.spawn(move || {
+                log::trace!("[reader {thread_no}] Starting thread");
+                for file in file_rx {
+                    if reader.failed.load(Ordering::Acquire) {
+                        break;
+                    }
+                    match reader.process(file) {
+                        Ok(Some(node)) => {
+                            let _ = result_tx.send(Ok(node));
+                        }
+                        Ok(None) => (),
+                        Err(e) => {
+                            reader.failed.store(true, Ordering::Release);
+                            let _ = result_tx.send(Err(e));
+                        }
+                    }
+                }
+                log::trace!("[reader {thread_no}] Ending thread");
+            });
+        }
+        // Drop our copies of the channel ends so that the channels close once
+        // the threads that use them are done.
+        drop(file_rx);
+        drop(job_rx);
+        drop(job_tx);
+        drop(buf_tx);
+        drop(result_tx);
+        if let Err(e) = list_files(zarr, &failed, &file_tx) {
+            failed.store(true, Ordering::Release);
+            return Err(e.into());
+        }
+        drop(file_tx);
+        // Force the receiver to receive everything (rather than breaking out
+        // early on an Err) in order to ensure that all threads run to
+        // completion
+        let mut tree: Result<_, ChecksumError> = Ok(ChecksumTree::new());
+        let mut err = None;
+        for v in result_rx {
+            match v {
+                Ok(node) => {
+                    tree = tree.and_then(|mut t| {
+                        t.add_file(node)?;
+                        Ok(t)
+                    });
+                }
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
+            }
+        }
+        match err {
+            Some(e) => Err(e.into()),
+            None => tree.map(ChecksumTree::into_checksum),
+        }
+    })
+}
+
+/// A large file being read, along with the channel over which its contents
+/// are sent to the hasher
+#[derive(Debug)]
+struct HashJob {
+    file: ZarrFile,
+    chunks: Receiver<Chunk>,
+}
+
+#[derive(Debug)]
+enum Chunk {
+    Data(Vec<u8>),
+    /// The file has been read in full, and its digest should be reported
+    End {
+        size: u64,
+    },
+    /// The file was modified while being read and will be read again as a
+    /// new job, so the digest computed so far should be discarded
+    Abort,
+}
+
+fn hash_worker(
+    thread_no: usize,
+    job_rx: &Receiver<HashJob>,
+    buf_tx: &Sender<Vec<u8>>,
+    result_tx: &Sender<Result<FileChecksum, FSError>>,
+) {
+    log::trace!("[hasher {thread_no}] Starting thread");
+    for HashJob { file, chunks } in job_rx {
+        let mut hasher = Md5::new();
+        // If the reader fails, it drops the sender without sending `End`, and
+        // the reader reports the error itself.
+        for chunk in chunks {
+            match chunk {
+                Chunk::Data(buf) => {
+                    hasher.update(&buf);
+                    // If all of the readers have exited, there's no one to
+                    // reuse the buffer, so ignore failures.
+                    let _ = buf_tx.send(buf);
+                }
+                Chunk::End { size } => {
+                    let digest = Md5Digest::from(<[u8; 16]>::from(hasher.finalize()));
+                    log::debug!("Computed checksum for file {}: {digest}", file.relpath());
+                    let node = FileChecksum::new(file.relpath().clone(), digest, size);
+                    file.progress().file_hashed(&node);
+                    let _ = result_tx.send(Ok(node));
+                    break;
+                }
+                Chunk::Abort => break,
+            }
+        }
+    }
+    log::trace!("[hasher {thread_no}] Ending thread");
+}
+
+/// The state shared by each reader thread
+struct Reader<'a> {
+    zarr: &'a Zarr,
+    config: &'a PipelineConfig,
+    failed: &'a AtomicBool,
+    job_tx: Sender<HashJob>,
+    buf_tx: Sender<Vec<u8>>,
+    buf_rx: Receiver<Vec<u8>>,
+}
+
+impl Reader<'_> {
+    /// Read the given file, subject to the Zarr's
+    /// [`ModifiedPolicy`][crate::zarr::ModifiedPolicy].  Small files are
+    /// hashed immediately and their checksums returned; large files are
+    /// handed off to a hasher, which reports the checksum, and `None` is
+    /// returned.
+    fn process(&self, zf: ZarrFile) -> Result<Option<FileChecksum>, FSError> {
+        let mut attempt = 1;
+        loop {
+            let mut fp = File::open(zf.path())?;
+            let md = fp.metadata()?;
+            let before = FileStamp::from(&md);
+            let outcome = if md.len() <= self.config.small_file_size {
+                self.read_small(&zf, &mut fp, md.len())?
+            } else {
+                self.read_large(zf.clone(), &mut fp, md.len())?
+            };
+            let after = FileStamp::from(&fp.metadata()?);
+            let modified = after.modified_since(&before, outcome.size);
+            if modified && zf.reread_modified(attempt)? {
+                if let Some(chunks) = outcome.chunks {
+                    let _ = chunks.send(Chunk::Abort);
+                }
+                attempt += 1;
+                continue;
+            }
+            return match (outcome.digest, outcome.chunks) {
+                (Some(digest), _) => {
+                    log::debug!("Computed checksum for file {}: {digest}", zf.relpath());
+                    let node = FileChecksum::new(zf.relpath().clone(), digest, outcome.size);
+                    zf.progress().file_hashed(&node);
+                    Ok(Some(node))
+                }
+                (None, Some(chunks)) => {
+                    let _ = chunks.send(Chunk::End { size: outcome.size });
+                    Ok(None)
+                }
+                (None, None) => unreachable!("read should produce a digest or a hash job"),
+            };
+        }
+    }
+
+    /// Read a small file in one go and hash it on the current thread
+    fn read_small(&self, zf: &ZarrFile, fp: &mut File, len: u64) -> Result<ReadOutcome, FSError> {
+        let mut buf = self.get_buffer();
+        // Allow for the file having grown since it was statted
+        let cap = usize::try_from(len).unwrap_or(usize::MAX).saturating_add(1);
+        buf.resize(cap.max(buf.len()), 0);
+        let mut filled = 0;
+        let mut size = 0;
+        let mut hasher = Md5::new();
+        loop {
+            if self.zarr.is_cancelled() {
+                return Err(FSError::Cancelled);
+            }
+            if filled == buf.len() {
+                hasher.update(&buf);
+                filled = 0;
+            }
+            let n = read_some(zf, fp, &mut buf[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+            size += n as u64;
+            zf.progress().bytes_read(n as u64);
+        }
+        hasher.update(&buf[..filled]);
+        let _ = self.buf_tx.send(buf);
+        Ok(ReadOutcome {
+            size,
+            digest: Some(Md5Digest::from(<[u8; 16]>::from(hasher.finalize()))),
+            chunks: None,
+        })
+    }
+
+    /// Read a large file in chunks, sending them to a hasher
+    fn read_large(&self, zf: ZarrFile, fp: &mut File, len: u64) -> Result<ReadOutcome, FSError> {
+        if self.config.fadvise {
+            advise_sequential(fp, len);
+        }
+        let (chunk_tx, chunk_rx) = bounded(self.config.queue_depth.get());
+        if self
+            .job_tx
+            .send(HashJob {
+                file: zf.clone(),
+                chunks: chunk_rx,
+            })
+            .is_err()
+        {
+            unreachable!("hashers should not exit before readers");
+        }
+        let mut size = 0;
+        loop {
+            if self.zarr.is_cancelled() {
+                return Err(FSError::Cancelled);
+            }
+            let mut buf = self.get_buffer();
+            buf.resize(self.config.buffer_size.get(), 0);
+            let mut filled = 0;
+            while filled < buf.len() {
+                let n = read_some(&zf, fp, &mut buf[filled..])?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            if filled == 0 {
+                break;
+            }
+            buf.truncate(filled);
+            size += filled as u64;
+            zf.progress().bytes_read(filled as u64);
+            let eof = filled < self.config.buffer_size.get();
+            if chunk_tx.send(Chunk::Data(buf)).is_err() {
+                unreachable!("hasher should not drop a job before it ends");
+            }
+            if eof {
+                break;
+            }
+        }
+        Ok(ReadOutcome {
+            size,
+            digest: None,
+            chunks: Some(chunk_tx),
+        })
+    }
+
+    fn get_buffer(&self) -> Vec<u8> {
+        self.buf_rx.try_recv().unwrap_or_default()
+    }
+}
+
+/// The result of reading a file once: either its digest (for small files) or
+/// the channel for sending the end of the file to its hasher (for large files)
+struct ReadOutcome {
+    size: u64,
+    digest: Option<Md5Digest>,
+    chunks: Option<Sender<Chunk>>,
+}
+
+fn read_some(zf: &ZarrFile, fp: &mut File, buf: &mut [u8]) -> Result<usize, FSError> {
+    loop {
+        match fp.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(source) => {
+                return Err(FSError::Digest {
+                    path: zf.path().into(),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+fn advise_sequential(fp: &File, len: u64) {
+    use std::os::fd::AsRawFd;
+    let fd = fp.as_raw_fd();
+    let len = libc::off_t::try_from(len).unwrap_or(0);
+    for advice in [libc::POSIX_FADV_SEQUENTIAL, libc::POSIX_FADV_WILLNEED] {
+        // SAFETY: `fd` is a valid open file descriptor for the duration of
+        // the call, and `posix_fadvise()` does not touch any of our memory.
+        let r = unsafe { libc::posix_fadvise(fd, 0, len, advice) };
+        if r != 0 {
+            log::debug!(
+                "posix_fadvise() failed for {}: {}",
+                fp.path().display(),
+                std::io::Error::from_raw_os_error(r)
+            );
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_sequential(_fp: &File, _len: u64) {}
@@ -0,0 +1,36 @@
+use crate::checksum::nodes::*;
+use crate::errors::{ChecksumError, FSError};
+use crate::zarr::*;
+use rayon::prelude::*;
+use std::num::NonZeroUsize;
+
+/// Traverse & checksum a Zarr directory tree recursively using a rayon thread
+/// pool
+///
+/// The `threads` argument determines the number of threads in the pool.
+///
+/// This works like [`recursive_checksum()`][super::recursive_checksum], except
+/// that the entries of each directory are checksummed in parallel using
+/// rayon's parallel iterators, which split the work with `join()` and let idle
+/// threads steal pending halves.  The checksum for each directory is computed
+/// as soon as the checksums for all of its entries are computed.
+pub fn rayon_checksum(zarr: &Zarr, threads: NonZeroUsize) -> Result<String, ChecksumError> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.get())
+        .thread_name(|i| format!("zarr-rayon-{i}"))
+        .build()
+        .expect("Building a rayon thread pool should not fail");
+    Ok(pool.install(|| recurse(zarr.root_dir()))?.into_checksum())
+}
+
+fn recurse(zdir: ZarrDirectory) -> Result<DirChecksum, FSError> {
+    let nodes = zdir
+        .entries()?
+        .into_par_iter()
+        .map(|entry| match entry {
+            ZarrEntry::File(f) => f.into_checksum().map(EntryChecksum::from),
+            ZarrEntry::Directory(d) => recurse(d).map(EntryChecksum::from),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(zdir.get_checksum(nodes))
+}
@@ -33,5 +33,5 @@ fn recurse(zdir: ZarrDirectory) -> Result<DirChecksum, FSError> {
             ZarrEntry::Directory(d) => ds.push(recurse(d)?),
         }
     }
-    Ok(ds.checksum())
+    Ok(zdir.collapse(&ds))
 }
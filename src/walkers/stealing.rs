@@ -0,0 +1,240 @@
+use super::jobstack::Scheduler;
+use crate::cancel::Cancellation;
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use std::iter;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// A work-stealing alternative to [`JobStack`][super::jobstack::JobStack]
+///
+/// Each worker thread owns a [`LocalQueue`] onto which it pushes the jobs it
+/// produces and from which it pops jobs in LIFO order.  When its queue is
+/// empty, a thread takes jobs from the shared queue of initial jobs or steals
+/// them from the other threads' queues.  The number of outstanding jobs is
+/// tracked with an atomic counter, and idle threads sleep on a condition
+/// variable until more jobs become available, all jobs are done, or the
+/// scheduler is shut down or cancelled.  As a result, threads that have work to do do not
+/// contend on any lock.
+#[derive(Debug)]
+pub(crate) struct StealingScheduler<T> {
+    injector: Injector<T>,
+    stealers: Vec<Stealer<T>>,
+    /// The number of jobs that have been pushed but not yet completed
+    jobs: AtomicUsize,
+    shutdown: AtomicBool,
+    /// Whether the scheduler was shut down because `cancellation` was
+    /// triggered
+    cancelled: AtomicBool,
+    cancellation: Cancellation,
+    /// The number of threads waiting (or about to wait) on `cond`
+    sleeping: AtomicUsize,
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+/// A worker thread's queue of jobs in a [`StealingScheduler`]
+#[derive(Debug)]
+pub(crate) struct LocalQueue<T> {
+    worker: Worker<T>,
+    index: usize,
+}
+
+impl<T> StealingScheduler<T> {
+    /// Create a scheduler with the given initial jobs, along with a
+    /// [`LocalQueue`] for each of `threads` worker threads
+    pub(crate) fn new<I: IntoIterator<Item = T>>(
+        items: I,
+        threads: NonZeroUsize,
+    ) -> (Self, Vec<LocalQueue<T>>) {
+        let injector = Injector::new();
+        let mut jobs = 0;
+        for item in items {
+            injector.push(item);
+            jobs += 1;
+        }
+        let workers = iter::repeat_with(Worker::new_lifo)
+            .take(threads.get())
+            .collect::<Vec<_>>();
+        let stealers = workers.iter().map(Worker::stealer).collect();
+        let queues = workers
+            .into_iter()
+            .enumerate()
+            .map(|(index, worker)| LocalQueue { worker, index })
+            .collect();
+        let scheduler = StealingScheduler {
+            injector,
+            stealers,
+            jobs: AtomicUsize::new(jobs),
+            shutdown: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            cancellation: Cancellation::default(),
+            sleeping: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+        };
+        (scheduler, queues)
+    }
+
+    /// Shut the scheduler down once `cancellation` is triggered.  Workers
+    /// waiting for jobs periodically wake up to check for this.
+    pub(crate) fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    fn pop(&self, queue: &LocalQueue<T>) -> Option<T> {
+        loop {
+            if !self.is_shutdown() && self.cancellation.is_cancelled() {
+                log::trace!("[StealingScheduler] Traversal cancelled");
+                self.cancelled.store(true, Ordering::Release);
+                self.shutdown();
+            }
+            if self.is_shutdown() {
+                log::trace!("[StealingScheduler] Shut down; returning None");
+                return None;
+            }
+            if let value @ Some(_) = queue.worker.pop().or_else(|| self.steal(queue)) {
+                return value;
+            }
+            let guard = self
+                .lock
+                .lock()
+                .expect("Mutex should not have been poisoned");
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            // Pairs with the fence in `extend()` so that either we see the
+            // newly-pushed jobs or the pusher sees that we're sleeping
+            fence(Ordering::SeqCst);
+            if self.jobs.load(Ordering::SeqCst) == 0 {
+                log::trace!("[StealingScheduler] No jobs; returning None");
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+            if self.is_shutdown() || !self.is_empty() {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            log::trace!("[StealingScheduler] No jobs available to take; waiting");
+            let guard = match self.cancellation.wait_interval() {
+                Some(interval) => {
+                    self.cond
+                        .wait_timeout(guard, interval)
+                        .expect("Mutex should not have been poisoned")
+                        .0
+                }
+                None => self
+                    .cond
+                    .wait(guard)
+                    .expect("Mutex should not have been poisoned"),
+            };
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+            drop(guard);
+        }
+    }
+
+    /// Take a batch of jobs from the initial queue or steal a job from
+    /// another thread, returning `None` if there are no jobs to take
+    fn steal(&self, queue: &LocalQueue<T>) -> Option<T> {
+        let others = self
+            .stealers
+            .iter()
+            .cycle()
+            .skip(queue.index + 1)
+            .take(self.stealers.len() - 1);
+        iter::repeat_with(|| {
+            self.injector
+                .steal_batch_and_pop(&queue.worker)
+                .or_else(|| others.clone().map(Stealer::steal).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(Steal::success)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.injector.is_empty() && self.stealers.iter().all(Stealer::is_empty)
+    }
+
+    fn job_done(&self) {
+        let jobs = self.jobs.fetch_sub(1, Ordering::SeqCst) - 1;
+        log::trace!("[StealingScheduler] Job count decremented to {jobs}");
+        if jobs == 0 {
+            self.wake_all();
+        }
+    }
+
+    fn extend<I: IntoIterator<Item = T>>(&self, queue: &LocalQueue<T>, iter: I) {
+        if self.is_shutdown() {
+            return;
+        }
+        let items = iter.into_iter().collect::<Vec<_>>();
+        if items.is_empty() {
+            return;
+        }
+        // Count the jobs before pushing them so that the count cannot drop to
+        // zero while a stolen job is completed before it is counted
+        let jobs = self.jobs.fetch_add(items.len(), Ordering::SeqCst) + items.len();
+        log::trace!("[StealingScheduler] Job count incremented to {jobs}");
+        for item in items {
+            queue.worker.push(item);
+        }
+        fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            self.wake_all();
+        }
+    }
+
+    fn wake_all(&self) {
+        let _guard = self
+            .lock
+            .lock()
+            .expect("Mutex should not have been poisoned");
+        self.cond.notify_all();
+    }
+}
+
+impl<T> Scheduler<T> for StealingScheduler<T> {
+    type Worker = LocalQueue<T>;
+
+    fn handle_many_jobs<F, I, E>(&self, queue: LocalQueue<T>, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(T) -> Result<I, E>,
+        I: IntoIterator<Item = T>,
+    {
+        let mut r = Ok(());
+        while let Some(value) = self.pop(&queue) {
+            match f(value) {
+                Ok(iter) => {
+                    self.extend(&queue, iter);
+                    self.job_done();
+                }
+                Err(e) => {
+                    self.job_done();
+                    self.shutdown();
+                    r = Err(e);
+                    break;
+                }
+            }
+        }
+        // After a shutdown, discard any jobs left in the queue now rather
+        // than when the scheduler is dropped so that any resources they hold
+        // are released.
+        while queue.worker.pop().is_some() {}
+        r
+    }
+
+    fn shutdown(&self) {
+        if !self.shutdown.swap(true, Ordering::AcqRel) {
+            log::trace!("[StealingScheduler] Shutting down scheduler");
+            while !self.injector.steal().is_empty() {}
+            self.wake_all();
+        }
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
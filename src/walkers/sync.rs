@@ -0,0 +1,221 @@
+//! Synchronization primitives for the job-based walkers.  Normally these are
+//! just re-exports from `std` and `crossbeam-utils`, but when compiled with
+//! `--cfg zarr_loom`, they are replaced by [loom]'s instrumented versions so
+//! that the loom tests can explore every interleaving of the walkers' jobs.
+//!
+//! [loom]: https://docs.rs/loom
+#[cfg(not(zarr_loom))]
+pub(crate) use crossbeam_utils::sync::WaitGroup;
+#[cfg(zarr_loom)]
+pub(crate) use loom::sync::{Arc, Condvar, Mutex};
+#[cfg(not(zarr_loom))]
+pub(crate) use std::sync::{mpsc, Arc, Condvar, Mutex};
+
+#[cfg(zarr_loom)]
+pub(crate) use self::loom_shims::{mpsc, WaitGroup};
+
+#[cfg(zarr_loom)]
+mod loom_shims {
+    use super::{Arc, Condvar, Mutex};
+    use std::fmt;
+
+    /// A loom equivalent of `crossbeam_utils::sync::WaitGroup`
+    pub(crate) struct WaitGroup {
+        inner: Arc<(Mutex<usize>, Condvar)>,
+    }
+
+    impl WaitGroup {
+        pub(crate) fn new() -> WaitGroup {
+            WaitGroup {
+                inner: Arc::new((Mutex::new(1), Condvar::new())),
+            }
+        }
+
+        /// Drop this reference and wait for all other references to be
+        /// dropped
+        pub(crate) fn wait(self) {
+            let inner = Arc::clone(&self.inner);
+            drop(self);
+            let (count, cond) = &*inner;
+            let mut count = count.lock().expect("Mutex should not have been poisoned");
+            while *count > 0 {
+                count = cond
+                    .wait(count)
+                    .expect("Mutex should not have been poisoned");
+            }
+        }
+    }
+
+    impl Clone for WaitGroup {
+        fn clone(&self) -> WaitGroup {
+            *self
+                .inner
+                .0
+                .lock()
+                .expect("Mutex should not have been poisoned") += 1;
+            WaitGroup {
+                inner: Arc::clone(&self.inner),
+            }
+        }
+    }
+
+    impl Drop for WaitGroup {
+        fn drop(&mut self) {
+            let (count, cond) = &*self.inner;
+            let mut count = count.lock().expect("Mutex should not have been poisoned");
+            *count -= 1;
+            if *count == 0 {
+                cond.notify_all();
+            }
+        }
+    }
+
+    impl fmt::Debug for WaitGroup {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("WaitGroup")
+        }
+    }
+
+    /// A channel built from loom's primitives with the same semantics as
+    /// `std::sync::mpsc`.  (loom's own channels do not notice when all senders
+    /// have been dropped, which `collapsio_mpsc` relies on.)
+    pub(crate) mod mpsc {
+        use super::super::{Arc, Condvar, Mutex};
+        use std::collections::VecDeque;
+        use std::fmt;
+        pub(crate) use std::sync::mpsc::{RecvError, SendError};
+
+        struct Channel<T> {
+            state: Mutex<State<T>>,
+            cond: Condvar,
+        }
+
+        struct State<T> {
+            queue: VecDeque<T>,
+            senders: usize,
+            receiver_alive: bool,
+        }
+
+        pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
+            let chan = Arc::new(Channel {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    senders: 1,
+                    receiver_alive: true,
+                }),
+                cond: Condvar::new(),
+            });
+            (Sender(Arc::clone(&chan)), Receiver(chan))
+        }
+
+        pub(crate) struct Sender<T>(Arc<Channel<T>>);
+
+        impl<T> Sender<T> {
+            pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+                let mut state = self
+                    .0
+                    .state
+                    .lock()
+                    .expect("Mutex should not have been poisoned");
+                if !state.receiver_alive {
+                    return Err(SendError(value));
+                }
+                state.queue.push_back(value);
+                self.0.cond.notify_all();
+                Ok(())
+            }
+        }
+
+        impl<T> Clone for Sender<T> {
+            fn clone(&self) -> Sender<T> {
+                self.0
+                    .state
+                    .lock()
+                    .expect("Mutex should not have been poisoned")
+                    .senders += 1;
+                Sender(Arc::clone(&self.0))
+            }
+        }
+
+        impl<T> Drop for Sender<T> {
+            fn drop(&mut self) {
+                let mut state = self
+                    .0
+                    .state
+                    .lock()
+                    .expect("Mutex should not have been poisoned");
+                state.senders -= 1;
+                if state.senders == 0 {
+                    self.0.cond.notify_all();
+                }
+            }
+        }
+
+        impl<T> fmt::Debug for Sender<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("Sender { .. }")
+            }
+        }
+
+        pub(crate) struct Receiver<T>(Arc<Channel<T>>);
+
+        impl<T> Receiver<T> {
+            pub(crate) fn recv(&self) -> Result<T, RecvError> {
+                let mut state = self
+                    .0
+                    .state
+                    .lock()
+                    .expect("Mutex should not have been poisoned");
+                loop {
+                    if let Some(value) = state.queue.pop_front() {
+                        return Ok(value);
+                    } else if state.senders == 0 {
+                        return Err(RecvError);
+                    }
+                    state = self
+                        .0
+                        .cond
+                        .wait(state)
+                        .expect("Mutex should not have been poisoned");
+                }
+            }
+        }
+
+        impl<T> Drop for Receiver<T> {
+            fn drop(&mut self) {
+                let mut state = self
+                    .0
+                    .state
+                    .lock()
+                    .expect("Mutex should not have been poisoned");
+                state.receiver_alive = false;
+                state.queue.clear();
+            }
+        }
+
+        impl<T> IntoIterator for Receiver<T> {
+            type Item = T;
+            type IntoIter = IntoIter<T>;
+
+            fn into_iter(self) -> IntoIter<T> {
+                IntoIter(self)
+            }
+        }
+
+        impl<T> fmt::Debug for Receiver<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("Receiver { .. }")
+            }
+        }
+
+        pub(crate) struct IntoIter<T>(Receiver<T>);
+
+        impl<T> Iterator for IntoIter<T> {
+            type Item = T;
+
+            fn next(&mut self) -> Option<T> {
+                self.0.recv().ok()
+            }
+        }
+    }
+}
@@ -0,0 +1,609 @@
+// Submitting operations to io_uring requires `unsafe`, as the kernel holds
+// raw pointers into our buffers until the operations complete.
+#![allow(unsafe_code)]
+use crate::checksum::{ChecksumTree, FileChecksum, Md5Digest};
+use crate::errors::{ChecksumError, FSError};
+use crate::util::FileStamp;
+use crate::zarr::*;
+use io_uring::{opcode, squeue, types::Fd, IoUring};
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::num::NonZeroUsize;
+use std::os::fd::RawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+/// Size of the buffers that file contents are read into
+const BUFFER_SIZE: usize = 128 * 1024;
+
+// The low bits of each submission's `user_data` identify the operation; the
+// remaining bits identify the slot.
+const OP_BITS: u32 = 2;
+const OP_OPEN: u64 = 0;
+const OP_READ: u64 = 1;
+const OP_STATX: u64 = 2;
+/// `user_data` for `close()` submissions, which are not tied to a slot
+const CLOSE_USER_DATA: u64 = u64::MAX;
+/// `user_data` for cancellation requests issued by [`Driver::abort()`]
+const CANCEL_USER_DATA: u64 = u64::MAX - 1;
+
+/// Traverse & checksum a Zarr directory using Linux's `io_uring` interface for
+/// file I/O
+///
+/// Directories are listed synchronously on the calling thread.  For up to
+/// `queue_depth` files at a time, `openat()`, `statx()`, and `read()` calls
+/// are batched into a single `io_uring` submission queue, and the data read is
+/// passed to `threads` hashing threads for computing MD5 digests.  All reads
+/// of a given file are hashed by the same thread, in order.
+///
+/// Each open file is statted before its first read and after its last read,
+/// and if its size, modification time, or status change time changed or its
+/// size does not match the number of bytes read, the Zarr's
+/// [`ModifiedPolicy`] is applied.
+///
+/// This builds an in-memory tree of all file checksums for computing the final
+/// Zarr checksum.
+pub fn uring_checksum(
+    zarr: &Zarr,
+    threads: NonZeroUsize,
+    queue_depth: NonZeroUsize,
+) -> Result<String, ChecksumError> {
+    let (result_tx, result_rx) = channel();
+    let (buf_tx, buf_rx) = channel();
+    let outcome = thread::scope(|scope| {
+        let mut hashers = Vec::with_capacity(threads.get());
+        for thread_no in 0..threads.get() {
+            let (tx, rx) = channel();
+            let buf_tx = buf_tx.clone();
+            let result_tx = result_tx.clone();
+            scope.spawn(move || hash_worker(thread_no, rx, &buf_tx, &result_tx));
+            hashers.push(tx);
+        }
+        // Dropping the driver at the end of this closure drops the hashers'
+        // senders, letting the hashing threads exit.
+        Driver::new(zarr, hashers, buf_rx, queue_depth.get())?.run()
+    });
+    drop(result_tx);
+    outcome?;
+    Ok(ChecksumTree::from_files(result_rx)?.into_checksum())
+}
+
+enum HashMsg {
+    Data {
+        id: u64,
+        buf: Vec<u8>,
+        len: usize,
+    },
+    Done {
+        id: u64,
+        file: ZarrFile,
+        size: u64,
+    },
+    /// The data sent so far for a file is to be discarded, as the file will
+    /// be read again under a new ID
+    Discard {
+        id: u64,
+    },
+}
+
+fn hash_worker(
+    thread_no: usize,
+    rx: Receiver<HashMsg>,
+    buf_tx: &Sender<Vec<u8>>,
+    result_tx: &Sender<FileChecksum>,
+) {
+    log::trace!("[{thread_no}] Starting hashing thread");
+    let mut states: HashMap<u64, Md5> = HashMap::new();
+    for msg in rx {
+        match msg {
+            HashMsg::Data { id, buf, len } => {
+                states.entry(id).or_default().update(&buf[..len]);
+                // If the driver has exited, there's no one to reuse the
+                // buffer, so ignore failures.
+                let _ = buf_tx.send(buf);
+            }
+            HashMsg::Done { id, file, size } => {
+                let digest = states.remove(&id).unwrap_or_default().finalize();
+                let digest = Md5Digest::from(<[u8; 16]>::from(digest));
+                log::debug!("Computed checksum for file {}: {digest}", file.relpath());
+                let node = FileChecksum::new(file.relpath().clone(), digest, size);
+                file.progress().file_hashed(&node);
+                let _ = result_tx.send(node);
+            }
+            HashMsg::Discard { id } => {
+                states.remove(&id);
+            }
+        }
+    }
+    log::trace!("[{thread_no}] Ending hashing thread");
+}
+
+/// A file currently being processed by the ring
+struct Slot {
+    id: u64,
+    file: ZarrFile,
+    // The kernel holds pointers to `cpath`, `statxbuf`, and `buf` while
+    // operations on the slot are pending, so they must not be dropped or
+    // moved out of until `pending` is zero.
+    cpath: CString,
+    statxbuf: Box<libc::statx>,
+    buf: Option<Vec<u8>>,
+    fd: Option<RawFd>,
+    /// The file's attributes as of before the first read
+    before: Option<FileStamp>,
+    offset: u64,
+    /// The number of times reading the file has been started
+    attempt: u32,
+    /// The operation most recently submitted for the slot
+    op: u64,
+    pending: usize,
+    failed: bool,
+}
+
+struct Driver {
+    zarr: Zarr,
+    ring: IoUring,
+    slots: Vec<Option<Slot>>,
+    dirs: Vec<ZarrDirectory>,
+    files: Vec<ZarrFile>,
+    hashers: Vec<Sender<HashMsg>>,
+    free_bufs: Vec<Vec<u8>>,
+    buf_rx: Receiver<Vec<u8>>,
+    bufs_allocated: usize,
+    max_bufs: usize,
+    next_id: u64,
+    /// The number of submitted `close()` calls that have not yet completed
+    closes_pending: usize,
+    err: Option<FSError>,
+}
+
+impl Driver {
+    fn new(
+        zarr: &Zarr,
+        hashers: Vec<Sender<HashMsg>>,
+        buf_rx: Receiver<Vec<u8>>,
+        queue_depth: usize,
+    ) -> Result<Driver, FSError> {
+        // Each slot has at most one operation in flight at once, plus there
+        // may be some outstanding `close()` calls.
+        let entries = u32::try_from((queue_depth * 2).next_power_of_two()).unwrap_or(u32::MAX);
+        let ring = IoUring::new(entries)?;
+        Ok(Driver {
+            zarr: zarr.clone(),
+            ring,
+            slots: std::iter::repeat_with(|| None).take(queue_depth).collect(),
+            dirs: vec![zarr.root_dir()],
+            files: Vec::new(),
+            hashers,
+            free_bufs: Vec::new(),
+            buf_rx,
+            bufs_allocated: 0,
+            max_bufs: queue_depth * 2,
+            next_id: 0,
+            closes_pending: 0,
+            err: None,
+        })
+    }
+
+    fn run(mut self) -> Result<(), FSError> {
+        loop {
+            if self.err.is_none() && self.zarr.is_cancelled() {
+                self.fail(FSError::Cancelled);
+            }
+            if self.err.is_none() {
+                self.fill_slots();
+            }
+            if self.slots.iter().all(Option::is_none) && self.closes_pending == 0 {
+                break;
+            }
+            if let Err(e) = self.submit_and_wait() {
+                self.abort(e);
+                break;
+            }
+            self.reap();
+        }
+        match self.err.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Submit all queued operations and wait for at least one completion,
+    /// retrying if interrupted by a signal
+    fn submit_and_wait(&self) -> io::Result<()> {
+        loop {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                // The completion queue is full, and the kernel won't accept
+                // more submissions until we reap it.
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Process all available completions
+    fn reap(&mut self) {
+        let completions = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect::<Vec<_>>();
+        for (user_data, result) in completions {
+            self.complete(user_data, result);
+        }
+    }
+
+    /// Returns `true` if any submitted operations have not yet completed
+    fn in_flight(&self) -> bool {
+        self.closes_pending > 0 || self.slots.iter().flatten().any(|slot| slot.pending > 0)
+    }
+
+    /// Handle an unrecoverable error from the ring by cancelling all pending
+    /// operations and reaping their completions, so that the kernel is done
+    /// with the slots' paths & buffers before they are freed.  If the ring
+    /// cannot even be waited on, the slots are leaked instead.
+    fn abort(&mut self, e: io::Error) {
+        self.fail(e.into());
+        for i in 0..self.slots.len() {
+            let op = match &self.slots[i] {
+                Some(slot) if slot.pending > 0 => slot.op,
+                _ => continue,
+            };
+            let cancel = opcode::AsyncCancel::new(user_data(i, op))
+                .build()
+                .user_data(CANCEL_USER_DATA);
+            if let Err(e) = self.push(&cancel) {
+                log::warn!("Failed to submit io_uring cancellation: {e}");
+            }
+        }
+        while self.in_flight() {
+            if let Err(e) = self.submit_and_wait() {
+                log::warn!(
+                    "Failed to wait for cancelled io_uring operations: {e}; leaking their buffers"
+                );
+                for slot in self.slots.iter_mut().filter_map(Option::take) {
+                    Box::leak(Box::new(slot));
+                }
+                return;
+            }
+            self.reap();
+        }
+    }
+
+    fn fail(&mut self, e: FSError) {
+        log::trace!("[uring] Error occurred: {e:?}");
+        self.err.get_or_insert(e);
+    }
+
+    /// Start opening files in all free slots
+    fn fill_slots(&mut self) {
+        for i in 0..self.slots.len() {
+            if self.slots[i].is_some() {
+                continue;
+            }
+            let Some(file) = self.next_file() else {
+                return;
+            };
+            let Ok(cpath) = CString::new(file.path().as_os_str().as_bytes()) else {
+                self.fail(FSError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("path {:?} contains a NUL byte", file.path()),
+                )));
+                return;
+            };
+            let id = self.next_id;
+            self.next_id += 1;
+            // SAFETY: `statx` is a plain C struct for which all-zero bytes is
+            // a valid value.
+            let statxbuf = Box::new(unsafe { std::mem::zeroed::<libc::statx>() });
+            let slot = self.slots[i].insert(Slot {
+                id,
+                file,
+                cpath,
+                statxbuf,
+                buf: None,
+                fd: None,
+                before: None,
+                offset: 0,
+                attempt: 1,
+                op: OP_OPEN,
+                pending: 0,
+                failed: false,
+            });
+            let open = opcode::OpenAt::new(Fd(libc::AT_FDCWD), slot.cpath.as_ptr())
+                .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+                .build()
+                .user_data(user_data(i, OP_OPEN));
+            if let Err(e) = self.push(&open) {
+                self.slots[i] = None;
+                self.fail(e.into());
+                return;
+            }
+            if let Some(slot) = self.slots[i].as_mut() {
+                slot.pending += 1;
+            }
+        }
+    }
+
+    fn next_file(&mut self) -> Option<ZarrFile> {
+        loop {
+            if let Some(f) = self.files.pop() {
+                return Some(f);
+            }
+            let zd = self.dirs.pop()?;
+            match zd.entries() {
+                Ok(entries) => {
+                    for entry in entries.into_iter().rev() {
+                        match entry {
+                            ZarrEntry::File(f) => self.files.push(f),
+                            ZarrEntry::Directory(d) => self.dirs.push(d),
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.fail(e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        loop {
+            // SAFETY: All pointers in the entries passed to this method point
+            // into heap allocations owned by a slot, which is not freed until
+            // all of its pending operations have completed.
+            let r = unsafe { self.ring.submission().push(entry) };
+            if r.is_ok() {
+                return Ok(());
+            }
+            // The submission queue is full; flush it to the kernel & retry
+            self.ring.submit()?;
+        }
+    }
+
+    fn complete(&mut self, user_data: u64, result: i32) {
+        if user_data == CANCEL_USER_DATA {
+            return;
+        }
+        if user_data == CLOSE_USER_DATA {
+            self.closes_pending -= 1;
+            if result < 0 {
+                log::warn!(
+                    "Failed to close file: {}",
+                    io::Error::from_raw_os_error(-result)
+                );
+            }
+            return;
+        }
+        let i = usize::try_from(user_data >> OP_BITS).expect("slot index should fit in usize");
+        let op = user_data & ((1 << OP_BITS) - 1);
+        let Some(slot) = self.slots[i].as_mut() else {
+            unreachable!("Completion received for empty slot");
+        };
+        slot.pending -= 1;
+        let mut error = None;
+        let mut next = Next::Read;
+        match op {
+            OP_OPEN => {
+                if result < 0 {
+                    let e = io::Error::from_raw_os_error(-result);
+                    error = Some(FSError::Io(io::Error::new(
+                        e.kind(),
+                        format!("failed to open file `{}`: {e}", slot.file.path().display()),
+                    )));
+                } else {
+                    slot.fd = Some(result);
+                    next = Next::Statx;
+                }
+            }
+            OP_STATX => {
+                if result < 0 {
+                    let e = io::Error::from_raw_os_error(-result);
+                    error = Some(FSError::Io(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "failed to query metadata of file `{}`: {e}",
+                            slot.file.path().display()
+                        ),
+                    )));
+                } else if slot.before.is_none() {
+                    slot.before = Some(FileStamp::from(&*slot.statxbuf));
+                } else {
+                    next = Next::Finish;
+                }
+            }
+            OP_READ => {
+                let buf = slot.buf.take().expect("read completed without a buffer");
+                match usize::try_from(result) {
+                    Err(_) => {
+                        error = Some(FSError::Digest {
+                            path: slot.file.path().into(),
+                            source: io::Error::from_raw_os_error(-result),
+                        });
+                        self.free_bufs.push(buf);
+                    }
+                    Ok(0) => {
+                        next = Next::Statx;
+                        self.free_bufs.push(buf);
+                    }
+                    Ok(len) => {
+                        slot.offset += len as u64;
+                        slot.file.progress().bytes_read(len as u64);
+                        let hasher = hasher_for(&self.hashers, slot.id);
+                        // The hashers only exit once we drop their senders
+                        let _ = hasher.send(HashMsg::Data {
+                            id: slot.id,
+                            buf,
+                            len,
+                        });
+                    }
+                }
+            }
+            _ => unreachable!("Unknown io_uring operation {op}"),
+        }
+        if error.is_some() {
+            slot.failed = true;
+        }
+        let pending = slot.pending;
+        if let Some(e) = error {
+            self.fail(e);
+        }
+        if pending > 0 {
+            return;
+        }
+        let slot = self.slots[i].as_ref().expect("slot should be occupied");
+        if slot.failed || self.err.is_some() {
+            let fd = slot.fd;
+            self.slots[i] = None;
+            self.close(fd);
+        } else {
+            match next {
+                Next::Read => self.start_read(i),
+                Next::Statx => self.start_statx(i),
+                Next::Finish => self.finish(i),
+            }
+        }
+    }
+
+    /// Query the attributes of the slot's open file
+    fn start_statx(&mut self, i: usize) {
+        let slot = self.slots[i].as_mut().expect("slot should be occupied");
+        let fd = slot.fd.expect("statx started before file was opened");
+        let statx = opcode::Statx::new(
+            Fd(fd),
+            c"".as_ptr(),
+            std::ptr::from_mut(&mut *slot.statxbuf).cast(),
+        )
+        .flags(libc::AT_EMPTY_PATH)
+        .mask(libc::STATX_SIZE | libc::STATX_MTIME | libc::STATX_CTIME)
+        .build()
+        .user_data(user_data(i, OP_STATX));
+        self.submit_op(i, OP_STATX, &statx);
+    }
+
+    /// Called once a file has been read to the end and statted again: send
+    /// the file to be finalized by its hasher, unless it was modified while
+    /// being read and should be read again
+    fn finish(&mut self, i: usize) {
+        let slot = self.slots[i].as_mut().expect("slot should be occupied");
+        let before = slot
+            .before
+            .expect("file should have been statted before reading");
+        let after = FileStamp::from(&*slot.statxbuf);
+        if after.modified_since(&before, slot.offset) {
+            match slot.file.reread_modified(slot.attempt) {
+                Ok(true) => {
+                    let old_id = slot.id;
+                    slot.id = self.next_id;
+                    self.next_id += 1;
+                    slot.before = None;
+                    slot.offset = 0;
+                    slot.attempt += 1;
+                    let _ = hasher_for(&self.hashers, old_id).send(HashMsg::Discard { id: old_id });
+                    self.start_statx(i);
+                    return;
+                }
+                Ok(false) => (),
+                Err(e) => {
+                    let fd = slot.fd;
+                    self.slots[i] = None;
+                    self.close(fd);
+                    self.fail(e);
+                    return;
+                }
+            }
+        }
+        let slot = self.slots[i].take().expect("slot should be occupied");
+        let _ = hasher_for(&self.hashers, slot.id).send(HashMsg::Done {
+            id: slot.id,
+            file: slot.file,
+            size: slot.offset,
+        });
+        self.close(slot.fd);
+    }
+
+    fn start_read(&mut self, i: usize) {
+        let mut buf = self.get_buffer();
+        let slot = self.slots[i].as_mut().expect("slot should be occupied");
+        let fd = slot.fd.expect("read started before file was opened");
+        let read = opcode::Read::new(
+            Fd(fd),
+            buf.as_mut_ptr(),
+            u32::try_from(buf.len()).unwrap_or(u32::MAX),
+        )
+        .offset(slot.offset)
+        .build()
+        .user_data(user_data(i, OP_READ));
+        slot.buf = Some(buf);
+        self.submit_op(i, OP_READ, &read);
+    }
+
+    /// Submit an operation for slot `i`, freeing the slot if submission fails
+    fn submit_op(&mut self, i: usize, op: u64, entry: &squeue::Entry) {
+        let slot = self.slots[i].as_mut().expect("slot should be occupied");
+        slot.op = op;
+        slot.pending += 1;
+        if let Err(e) = self.push(entry) {
+            let slot = self.slots[i].as_mut().expect("slot should be occupied");
+            slot.pending -= 1;
+            if let Some(buf) = slot.buf.take() {
+                self.free_bufs.push(buf);
+            }
+            let fd = slot.fd;
+            self.slots[i] = None;
+            self.close(fd);
+            self.fail(e.into());
+        }
+    }
+
+    fn get_buffer(&mut self) -> Vec<u8> {
+        self.free_bufs.extend(self.buf_rx.try_iter());
+        if let Some(buf) = self.free_bufs.pop() {
+            return buf;
+        }
+        if self.bufs_allocated < self.max_bufs {
+            self.bufs_allocated += 1;
+            return vec![0; BUFFER_SIZE];
+        }
+        // All buffers are waiting to be hashed, and the hashers return each
+        // one once they're done with it.
+        self.buf_rx
+            .recv()
+            .expect("hashing threads should not exit while the driver is running")
+    }
+
+    fn close(&mut self, fd: Option<RawFd>) {
+        if let Some(fd) = fd {
+            let close = opcode::Close::new(Fd(fd))
+                .build()
+                .user_data(CLOSE_USER_DATA);
+            match self.push(&close) {
+                Ok(()) => self.closes_pending += 1,
+                Err(e) => log::warn!("Failed to submit close() call: {e}"),
+            }
+        }
+    }
+}
+
+/// What to do with a slot once its current operation has completed
+enum Next {
+    Read,
+    Statx,
+    Finish,
+}
+
+fn user_data(slot: usize, op: u64) -> u64 {
+    ((slot as u64) << OP_BITS) | op
+}
+
+fn hasher_for(hashers: &[Sender<HashMsg>], id: u64) -> &Sender<HashMsg> {
+    let len = hashers.len() as u64;
+    let i = usize::try_from(id % len).expect("hasher index should fit in usize");
+    &hashers[i]
+}
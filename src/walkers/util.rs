@@ -1,4 +1,7 @@
 use crate::errors::FSError;
+use crate::zarr::{Zarr, ZarrEntry, ZarrFile};
+use crossbeam_channel::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Debug)]
 pub(super) enum Output<J, T> {
@@ -6,3 +9,108 @@ pub(super) enum Output<J, T> {
     ToSend(Result<T, FSError>),
     Nil,
 }
+
+/// List the Zarr depth-first on the current thread, sending each file over
+/// `file_tx`.  Listing stops early once `failed` is set or all receivers have
+/// been dropped.
+pub(super) fn list_files(
+    zarr: &Zarr,
+    failed: &AtomicBool,
+    file_tx: &Sender<ZarrFile>,
+) -> Result<(), FSError> {
+    let mut dirs = vec![zarr.root_dir()];
+    while let Some(dir) = dirs.pop() {
+        for entry in dir.entries()? {
+            if failed.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            if zarr.is_cancelled() {
+                return Err(FSError::Cancelled);
+            }
+            match entry {
+                ZarrEntry::Directory(zd) => dirs.push(zd),
+                ZarrEntry::File(zf) => {
+                    if file_tx.send(zf).is_err() {
+                        // All receivers have exited, which only happens
+                        // after an error
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(zarr_loom)]
+pub(super) mod loom_util {
+    use super::Output;
+    use crate::walkers::jobstack::JobStack;
+    use crate::walkers::sync::{mpsc, Arc};
+    use loom::thread;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::{tempdir, TempDir};
+
+    /// Create a Zarr small enough for loom to explore all interleavings of
+    /// its jobs yet still containing a nested directory and an empty one
+    pub(crate) fn mktree() -> TempDir {
+        let tmp_path = tempdir().unwrap();
+        let root = tmp_path.path();
+        fs::write(root.join("a"), "foo").unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join("b"), "bar").unwrap();
+        tmp_path
+    }
+
+    /// The expected checksum for [`mktree()`]
+    pub(crate) fn expected_checksum(root: &Path) -> String {
+        crate::walkers::recursive_checksum(&crate::zarr::Zarr::new(root)).unwrap()
+    }
+
+    /// Run `f` under loom.  Unless overridden with `LOOM_MAX_PREEMPTIONS`, the
+    /// number of preemptions per execution is bounded at 3, as exploring the
+    /// interleavings of whole walkers is otherwise intractable; even this bound
+    /// exposes the races that the walkers guard against.
+    pub(crate) fn model<F>(f: F)
+    where
+        F: Fn() + Sync + Send + 'static,
+    {
+        let mut builder = loom::model::Builder::new();
+        if builder.preemption_bound.is_none() {
+            builder.preemption_bound = Some(3);
+        }
+        builder.check(f);
+    }
+
+    /// Handle the jobs in `stack` on two threads the same way as the
+    /// walkers' worker threads do and return everything sent to the output
+    pub(crate) fn run_jobs<J>(
+        stack: JobStack<J>,
+        process: fn(J, usize) -> Output<J, String>,
+    ) -> Vec<String>
+    where
+        J: Send + 'static,
+    {
+        let stack = Arc::new(stack);
+        let (sender, receiver) = mpsc::channel();
+        let worker = |thread_no: usize| {
+            let stack = Arc::clone(&stack);
+            let sender = sender.clone();
+            move || {
+                stack.handle_many_jobs(|job| match process(job, thread_no) {
+                    Output::ToPush(to_push) => Ok(to_push),
+                    Output::ToSend(to_send) => sender.send(to_send.unwrap()).map(|()| Vec::new()),
+                    Output::Nil => Ok(Vec::new()),
+                })
+            }
+        };
+        let handle = thread::spawn(worker(1));
+        worker(0)().unwrap();
+        handle.join().unwrap().unwrap();
+        drop(sender);
+        receiver.into_iter().collect()
+    }
+}
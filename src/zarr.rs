@@ -1,13 +1,20 @@
 //! General operations on Zarrs and the entries within
 mod entrypath;
+mod inodes;
+use crate::cancel::{CancelToken, Cancellation};
 use crate::checksum::nodes::*;
 use crate::errors::{EntryNameError, FSError};
-use crate::util::{async_md5_file, md5_file};
+use crate::progress::{NoProgress, ProgressObserver};
+use crate::util::{async_md5_file, md5_file, md5_file_mmap, FileDigest, OpenFile, BUFFER_SIZE};
 pub use entrypath::*;
 use fs_err::{metadata, read_dir, tokio as afs, DirEntry, ReadDir};
+use inodes::InodeCache;
 use std::ffi::OsStr;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 /// Names of files & directories that are excluded from consideration when
 /// traversing a Zarr
@@ -21,32 +28,232 @@ static EXCLUDED_DOTFILES: &[&str] = &[
     ".gitmodules",
 ];
 
+/// The number of times a file will be read under [`ModifiedPolicy::Retry`]
+/// before giving up
+pub const MAX_READ_ATTEMPTS: u32 = 3;
+
+/// What to do when a file is found to have been modified while its checksum
+/// was being computed
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ModifiedPolicy {
+    /// Read the file again, up to a total of [`MAX_READ_ATTEMPTS`] times, and
+    /// fail with [`FSError::ModifiedDuringRead`] if it is still being
+    /// modified.  Bytes from all attempts are reported to the Zarr's
+    /// [`ProgressObserver`].
+    Retry,
+    /// Log a warning and use the checksum & size from the read anyway
+    Warn,
+    /// Fail with [`FSError::ModifiedDuringRead`]
+    #[default]
+    Fail,
+}
+
+/// The default value of `threshold` for [`HashBackend::Auto`]: 1 MiB
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// How the contents of files are read in order to compute their checksums.
+///
+/// This only affects synchronous traversals; asynchronous traversals and those
+/// that do their own I/O always read files into buffers.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum HashBackend {
+    /// Read each file into a buffer one bufferful at a time
+    #[default]
+    Buffered,
+    /// Map each file into memory and hash the mapping.  Empty files and files
+    /// that cannot be mapped are read into a buffer instead.
+    ///
+    /// If a file is truncated while it is being hashed, the read fails with
+    /// [`FSError::ModifiedDuringRead`].  The file is read again under
+    /// [`ModifiedPolicy::Retry`], but the partial read is never used, even
+    /// under [`ModifiedPolicy::Warn`].
+    ///
+    /// On Linux, the first mapped read installs a process-wide `SIGBUS`
+    /// handler in order to survive files being truncated mid-read.  The
+    /// handler is never uninstalled; faults that it does not recognize as
+    /// coming from a mapped read are passed on to whatever handler was
+    /// installed before it, or else to the default action.
+    Mmap,
+    /// Use [`HashBackend::Mmap`] for files of at least `threshold` bytes and
+    /// [`HashBackend::Buffered`] for smaller files
+    Auto { threshold: u64 },
+}
+
+/// The order in which multithreaded traversals that share a single stack of
+/// jobs among their threads take jobs from it
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum JobOrder {
+    /// Take the most recently added job first
+    #[default]
+    Lifo,
+    /// Take the least recently added job first
+    Fifo,
+    /// Take directories first, then files in decreasing order of size, so
+    /// that a large file is not left to be hashed by a single thread at the
+    /// end of a traversal.  File sizes are recorded when their directories
+    /// are listed, at the cost of an extra `stat()` call per file.
+    LargestFirst,
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Zarr {
     path: PathBuf,
+    settings: Settings,
+}
+
+/// Traversal settings shared by a [`Zarr`] and all entries obtained from it.
+///
+/// Settings compare equal if they configure checksumming the same way; the
+/// progress observer, cancellation conditions, and hardlink cache are
+/// per-traversal state and are ignored by comparisons & hashing.
+#[derive(Clone, Debug)]
+struct Settings {
     exclude_dotfiles: bool,
+    on_modified: ModifiedPolicy,
+    dedup_hardlinks: bool,
+    backend: HashBackend,
+    job_order: JobOrder,
+    progress: Arc<dyn ProgressObserver>,
+    cancellation: Cancellation,
+    /// Digests of hardlinked files computed so far.  This is only set for the
+    /// settings of a traversal (i.e., those returned by
+    /// [`Zarr::root_dir()`]), so that each traversal gets a fresh cache.
+    inodes: Option<Arc<InodeCache>>,
+}
+
+impl Settings {
+    /// Return the fields that are considered by comparisons & hashing
+    fn key(&self) -> (bool, ModifiedPolicy, bool, HashBackend, JobOrder) {
+        (
+            self.exclude_dotfiles,
+            self.on_modified,
+            self.dedup_hardlinks,
+            self.backend,
+            self.job_order,
+        )
+    }
+}
+
+impl PartialEq for Settings {
+    fn eq(&self, other: &Settings) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Settings {}
+
+impl Hash for Settings {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
 impl Zarr {
     pub fn new<P: AsRef<Path>>(path: P) -> Zarr {
         Zarr {
             path: path.as_ref().into(),
-            exclude_dotfiles: false,
+            settings: Settings {
+                exclude_dotfiles: false,
+                on_modified: ModifiedPolicy::default(),
+                dedup_hardlinks: false,
+                backend: HashBackend::default(),
+                job_order: JobOrder::default(),
+                progress: Arc::new(NoProgress),
+                cancellation: Cancellation::default(),
+                inodes: None,
+            },
         }
     }
 
-    pub fn exclude_dotfiles(self, flag: bool) -> Zarr {
-        Zarr {
-            exclude_dotfiles: flag,
-            ..self
-        }
+    pub fn exclude_dotfiles(mut self, flag: bool) -> Zarr {
+        self.settings.exclude_dotfiles = flag;
+        self
+    }
+
+    /// Set what to do when a file is modified while it is being read.  The
+    /// default is [`ModifiedPolicy::Fail`].
+    pub fn on_modified(mut self, policy: ModifiedPolicy) -> Zarr {
+        self.settings.on_modified = policy;
+        self
+    }
+
+    /// If `flag` is true, files with multiple hardlinks are identified by
+    /// their device & inode numbers, and the contents of each such file are
+    /// only read once per traversal, with the resulting checksum & size
+    /// reused for every path linking to it.  This costs an extra `stat()`
+    /// call per file.  It has no effect on non-Unix platforms.
+    pub fn dedup_hardlinks(mut self, flag: bool) -> Zarr {
+        self.settings.dedup_hardlinks = flag;
+        self
+    }
+
+    /// Returns `true` if [`dedup_hardlinks()`][Zarr::dedup_hardlinks] is in
+    /// effect
+    pub fn dedups_hardlinks(&self) -> bool {
+        self.settings.dedup_hardlinks
+    }
+
+    /// Set how file contents are read for hashing.  The default is
+    /// [`HashBackend::Buffered`].
+    pub fn hash_backend(mut self, backend: HashBackend) -> Zarr {
+        self.settings.backend = backend;
+        self
+    }
+
+    /// Set the order in which traversals that share a stack of jobs among
+    /// their threads take jobs from it.  This is honored by
+    /// [`fastio_checksum()`][crate::walkers::fastio_checksum] and
+    /// [`collapsio_arc_checksum()`][crate::walkers::collapsio_arc_checksum]
+    /// and the functions built on them.  The default is [`JobOrder::Lifo`].
+    pub fn job_order(mut self, order: JobOrder) -> Zarr {
+        self.settings.job_order = order;
+        self
+    }
+
+    /// Returns the [`JobOrder`] set with [`job_order()`][Zarr::job_order]
+    pub fn get_job_order(&self) -> JobOrder {
+        self.settings.job_order
+    }
+
+    /// Report progress events from traversals of the Zarr to the given
+    /// observer
+    pub fn progress<P: ProgressObserver + 'static>(mut self, observer: P) -> Zarr {
+        self.settings.progress = Arc::new(observer);
+        self
+    }
+
+    /// Abort traversals of the Zarr once the given token is cancelled
+    pub fn cancel_token(mut self, token: CancelToken) -> Zarr {
+        self.settings.cancellation.token = Some(token);
+        self
+    }
+
+    /// Abort traversals of the Zarr that are still running at the given
+    /// instant
+    pub fn deadline(mut self, deadline: Instant) -> Zarr {
+        self.settings.cancellation.deadline = Some(deadline);
+        self
+    }
+
+    /// Returns `true` if the Zarr's cancel token has been cancelled or its
+    /// deadline has passed
+    pub fn is_cancelled(&self) -> bool {
+        self.settings.cancellation.is_cancelled()
+    }
+
+    pub(crate) fn cancellation(&self) -> &Cancellation {
+        &self.settings.cancellation
     }
 
     pub fn root_dir(&self) -> ZarrDirectory {
+        let mut settings = self.settings.clone();
+        if settings.dedup_hardlinks {
+            settings.inodes = Some(Arc::default());
+        }
         ZarrDirectory {
             path: self.path.clone(),
             relpath: DirPath::Root,
-            exclude_dotfiles: self.exclude_dotfiles,
+            settings: Arc::new(settings),
         }
     }
 }
@@ -55,6 +262,10 @@ impl Zarr {
 pub struct ZarrFile {
     path: PathBuf,
     relpath: EntryPath,
+    /// The file's size as of when its parent directory was listed, if
+    /// recorded
+    size: Option<u64>,
+    settings: Arc<Settings>,
 }
 
 impl ZarrFile {
@@ -62,22 +273,210 @@ impl ZarrFile {
         &self.path
     }
 
+    /// Returns the file's size as of when its parent directory was listed.
+    /// Sizes are only recorded when the Zarr's [`JobOrder`] is
+    /// [`JobOrder::LargestFirst`] and the directory was listed synchronously.
+    pub fn listed_size(&self) -> Option<u64> {
+        self.size
+    }
+
     pub fn relpath(&self) -> &EntryPath {
         &self.relpath
     }
 
+    pub(crate) fn progress(&self) -> &dyn ProgressObserver {
+        &*self.settings.progress
+    }
+
     pub fn into_checksum(self) -> Result<FileChecksum, FSError> {
-        let size = metadata(&self.path)?.len();
-        let checksum = md5_file(self.path)?;
-        log::debug!("Computed checksum for file {}: {checksum}", &self.relpath);
-        Ok(FileChecksum::new(self.relpath, checksum, size))
+        self.into_checksum_with_buffer(&mut [0u8; BUFFER_SIZE])
+    }
+
+    /// Like [`into_checksum()`][ZarrFile::into_checksum], but read the file
+    /// into the given buffer rather than a small stack-allocated one
+    pub(crate) fn into_checksum_with_buffer(
+        self,
+        buffer: &mut [u8],
+    ) -> Result<FileChecksum, FSError> {
+        self.checksum_file(None, buffer)
+    }
+
+    /// Like [`into_checksum()`][ZarrFile::into_checksum], but start by reading
+    /// from a handle to the file that the caller has already opened
+    pub(crate) fn into_checksum_from(self, file: OpenFile) -> Result<FileChecksum, FSError> {
+        self.checksum_file(Some(file), &mut [0u8; BUFFER_SIZE])
+    }
+
+    fn checksum_file(
+        self,
+        file: Option<OpenFile>,
+        buffer: &mut [u8],
+    ) -> Result<FileChecksum, FSError> {
+        let Settings {
+            progress,
+            cancellation,
+            ..
+        } = &*self.settings;
+        cancellation.check()?;
+        let slot = match (&self.settings.inodes, &file) {
+            (Some(inodes), Some(file)) => inodes.slot(file.metadata()),
+            (Some(inodes), None) => inodes.slot(&metadata(&self.path)?),
+            (None, _) => None,
+        };
+        let FileDigest { digest, size, .. } = match slot {
+            Some(slot) => {
+                let _guard = slot.lock();
+                match slot.get() {
+                    Some(digest) => self.reuse_digest(digest),
+                    None => slot.set(self.digest(file, buffer)?).clone(),
+                }
+            }
+            None => self.digest(file, buffer)?,
+        };
+        log::debug!("Computed checksum for file {}: {digest}", &self.relpath);
+        let node = FileChecksum::new(self.relpath, digest, size);
+        progress.file_hashed(&node);
+        Ok(node)
     }
 
     pub async fn async_into_checksum(self) -> Result<FileChecksum, FSError> {
-        let size = afs::metadata(&self.path).await?.len();
-        let checksum = async_md5_file(self.path).await?;
-        log::debug!("Computed checksum for file {}: {checksum}", &self.relpath);
-        Ok(FileChecksum::new(self.relpath, checksum, size))
+        let Settings {
+            progress,
+            cancellation,
+            ..
+        } = &*self.settings;
+        cancellation.check()?;
+        let slot = match self.settings.inodes {
+            Some(ref inodes) => inodes.slot(&afs::metadata(&self.path).await?),
+            None => None,
+        };
+        let FileDigest { digest, size, .. } = match slot {
+            Some(slot) => {
+                let _guard = slot.async_lock().await;
+                match slot.get() {
+                    Some(digest) => self.reuse_digest(digest),
+                    None => slot.set(self.async_digest().await?).clone(),
+                }
+            }
+            None => self.async_digest().await?,
+        };
+        log::debug!("Computed checksum for file {}: {digest}", &self.relpath);
+        let node = FileChecksum::new(self.relpath, digest, size);
+        progress.file_hashed(&node);
+        Ok(node)
+    }
+
+    /// Read the file and compute its digest, subject to the Zarr's
+    /// [`ModifiedPolicy`].  If `file` is given, it is used for the first read
+    /// instead of opening the file anew.
+    fn digest(&self, mut file: Option<OpenFile>, buffer: &mut [u8]) -> Result<FileDigest, FSError> {
+        let Settings {
+            progress,
+            cancellation,
+            ..
+        } = &*self.settings;
+        // Take the size from the number of bytes read rather than a separate
+        // `stat()` so that the two can't disagree if the file changes.
+        let mut attempt = 1;
+        loop {
+            let file = match file.take() {
+                Some(file) => file,
+                None => OpenFile::open(&self.path)?,
+            };
+            let r = match self.settings.backend {
+                HashBackend::Buffered => md5_file(file, buffer, &**progress, cancellation),
+                HashBackend::Mmap => md5_file_mmap(file, 0, buffer, &**progress, cancellation),
+                HashBackend::Auto { threshold } => {
+                    md5_file_mmap(file, threshold, buffer, &**progress, cancellation)
+                }
+            };
+            // A truncated mapped read never produces a digest, so it can be
+            // retried but not accepted under `ModifiedPolicy::Warn`.
+            let digest = match r {
+                Err(FSError::ModifiedDuringRead { .. })
+                    if self.settings.on_modified == ModifiedPolicy::Retry
+                        && attempt < MAX_READ_ATTEMPTS =>
+                {
+                    log::warn!(
+                        "File {} was truncated while it was being read; retrying",
+                        self.path.display()
+                    );
+                    attempt += 1;
+                    continue;
+                }
+                r => r?,
+            };
+            if let Some(digest) = self.accept_digest(digest, attempt)? {
+                return Ok(digest);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Asynchronously read the file and compute its digest, subject to the
+    /// Zarr's [`ModifiedPolicy`]
+    async fn async_digest(&self) -> Result<FileDigest, FSError> {
+        let Settings {
+            progress,
+            cancellation,
+            ..
+        } = &*self.settings;
+        let mut attempt = 1;
+        loop {
+            let digest = async_md5_file(&self.path, &**progress, cancellation).await?;
+            if let Some(digest) = self.accept_digest(digest, attempt)? {
+                return Ok(digest);
+            }
+            attempt += 1;
+        }
+    }
+
+    fn reuse_digest(&self, digest: &FileDigest) -> FileDigest {
+        log::debug!(
+            "Reusing checksum for hardlinked file {}",
+            self.path.display()
+        );
+        digest.clone()
+    }
+
+    /// Apply the Zarr's [`ModifiedPolicy`] to the result of the `attempt`-th
+    /// read of the file.  Returns `Ok(None)` if the file should be read again.
+    fn accept_digest(
+        &self,
+        digest: FileDigest,
+        attempt: u32,
+    ) -> Result<Option<FileDigest>, FSError> {
+        if digest.modified && self.reread_modified(attempt)? {
+            Ok(None)
+        } else {
+            Ok(Some(digest))
+        }
+    }
+
+    /// Apply the Zarr's [`ModifiedPolicy`] to the `attempt`-th read of the
+    /// file, during which the file was modified.  Returns `Ok(true)` if the
+    /// file should be read again or `Ok(false)` if the digest should be used
+    /// anyway.
+    pub(crate) fn reread_modified(&self, attempt: u32) -> Result<bool, FSError> {
+        match self.settings.on_modified {
+            ModifiedPolicy::Retry if attempt < MAX_READ_ATTEMPTS => {
+                log::warn!(
+                    "File {} was modified while it was being read; retrying",
+                    self.path.display()
+                );
+                Ok(true)
+            }
+            ModifiedPolicy::Warn => {
+                log::warn!(
+                    "File {} was modified while it was being read; checksum may be inaccurate",
+                    self.path.display()
+                );
+                Ok(false)
+            }
+            _ => Err(FSError::ModifiedDuringRead {
+                path: self.path.clone(),
+            }),
+        }
     }
 }
 
@@ -85,7 +484,7 @@ impl ZarrFile {
 pub struct ZarrDirectory {
     path: PathBuf,
     relpath: DirPath,
-    exclude_dotfiles: bool,
+    settings: Arc<Settings>,
 }
 
 impl ZarrDirectory {
@@ -102,21 +501,24 @@ impl ZarrDirectory {
     }
 
     pub fn iter_entries(&self) -> Result<Entries, FSError> {
+        self.settings.cancellation.check()?;
         let handle = read_dir(&self.path)?;
         Ok(Entries {
-            handle,
-            baserelpath: self.relpath.clone(),
-            exclude_dotfiles: self.exclude_dotfiles,
+            handle: Some(handle),
+            dir: self.clone(),
+            yielded: 0,
         })
     }
 
     pub async fn async_entries(&self) -> Result<Vec<ZarrEntry>, FSError> {
+        self.settings.cancellation.check()?;
         let mut entries = Vec::new();
         let mut handle = afs::read_dir(&self.path).await?;
         while let Some(p) = handle.next_entry().await.transpose() {
+            self.settings.cancellation.check()?;
             let p = p?;
             let path = p.path();
-            if self.exclude_dotfiles && is_excluded_dotfile(&path) {
+            if self.settings.exclude_dotfiles && is_excluded_dotfile(&path) {
                 log::debug!("Excluding special dotfile {path:?}");
                 continue;
             }
@@ -130,19 +532,37 @@ impl ZarrDirectory {
                     .expect("DirEntry.file_name() should not be . or .. nor contain /"),
                 None => return Err(FSError::UndecodableName { path }),
             };
-            entries.push(if is_dir {
-                ZarrEntry::Directory(ZarrDirectory {
-                    path,
-                    relpath: relpath.into(),
-                    exclude_dotfiles: self.exclude_dotfiles,
-                })
-            } else {
-                ZarrEntry::File(ZarrFile { path, relpath })
-            });
+            entries.push(self.child(path, relpath, is_dir, None));
         }
+        self.settings
+            .progress
+            .dir_listed(&self.relpath, entries.len());
         Ok(entries)
     }
 
+    fn child(
+        &self,
+        path: PathBuf,
+        relpath: EntryPath,
+        is_dir: bool,
+        size: Option<u64>,
+    ) -> ZarrEntry {
+        if is_dir {
+            ZarrEntry::Directory(ZarrDirectory {
+                path,
+                relpath: relpath.into(),
+                settings: Arc::clone(&self.settings),
+            })
+        } else {
+            ZarrEntry::File(ZarrFile {
+                path,
+                relpath,
+                size,
+                settings: Arc::clone(&self.settings),
+            })
+        }
+    }
+
     pub fn dirsummer(&self) -> Dirsummer {
         let relpath = match &self.relpath {
             // TODO: Replace this kludgy workaround with something better:
@@ -170,38 +590,54 @@ impl ZarrDirectory {
     {
         let mut ds = self.dirsummer();
         ds.extend(nodes);
-        ds.checksum()
+        self.collapse(&ds)
+    }
+
+    /// Compute the checksum for the directory from a [`Dirsummer`] obtained
+    /// from [`dirsummer()`][ZarrDirectory::dirsummer] to which the checksums
+    /// for all of the directory's entries have been added, and report the
+    /// result to the Zarr's [`ProgressObserver`]
+    pub fn collapse(&self, ds: &Dirsummer) -> DirChecksum {
+        let node = ds.checksum();
+        self.settings.progress.dir_collapsed(&node);
+        node
     }
 }
 
 #[derive(Debug)]
 pub struct Entries {
-    handle: ReadDir,
-    baserelpath: DirPath,
-    exclude_dotfiles: bool,
+    // `None` once the directory has been exhausted
+    handle: Option<ReadDir>,
+    dir: ZarrDirectory,
+    yielded: usize,
 }
 
 impl Entries {
     fn process_direntry(&self, p: DirEntry) -> Result<ZarrEntry, FSError> {
         let path = p.path();
         let ftype = p.file_type()?;
-        let is_dir = ftype.is_dir() || (ftype.is_symlink() && metadata(&path)?.is_dir());
+        let record_size = self.dir.settings.job_order == JobOrder::LargestFirst;
+        let (is_dir, size) = if ftype.is_symlink() {
+            let md = metadata(&path)?;
+            (md.is_dir(), Some(md.len()))
+        } else if ftype.is_dir() {
+            (true, None)
+        } else if record_size {
+            (false, Some(p.metadata()?.len()))
+        } else {
+            (false, None)
+        };
         let relpath = match p.file_name().to_str() {
             Some(s) => self
-                .baserelpath
+                .dir
+                .relpath
                 .join1(s)
                 .expect("DirEntry.file_name() should not be . or .. nor contain /"),
             None => return Err(FSError::UndecodableName { path }),
         };
-        Ok(if is_dir {
-            ZarrEntry::Directory(ZarrDirectory {
-                path,
-                relpath: relpath.into(),
-                exclude_dotfiles: self.exclude_dotfiles,
-            })
-        } else {
-            ZarrEntry::File(ZarrFile { path, relpath })
-        })
+        Ok(self
+            .dir
+            .child(path, relpath, is_dir, size.filter(|_| record_size)))
     }
 }
 
@@ -210,13 +646,26 @@ impl Iterator for Entries {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            return Some(match self.handle.next()? {
+            let Some(r) = self.handle.as_mut()?.next() else {
+                self.handle = None;
+                self.dir
+                    .settings
+                    .progress
+                    .dir_listed(&self.dir.relpath, self.yielded);
+                return None;
+            };
+            if let Err(e) = self.dir.settings.cancellation.check() {
+                self.handle = None;
+                return Some(Err(e));
+            }
+            return Some(match r {
                 Ok(p) => {
                     let path = p.path();
-                    if self.exclude_dotfiles && is_excluded_dotfile(&path) {
+                    if self.dir.settings.exclude_dotfiles && is_excluded_dotfile(&path) {
                         log::debug!("Excluding special dotfile {path:?}");
                         continue;
                     }
+                    self.yielded += 1;
                     self.process_direntry(p)
                 }
                 Err(e) => Err(e.into()),
@@ -287,7 +736,11 @@ pub fn is_excluded_dotfile<P: AsRef<Path>>(path: P) -> bool {
 #[cfg(test)]
 mod test {
     use super::*;
+    use assert_matches::assert_matches;
+    use md5::{Digest, Md5};
     use rstest::rstest;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, SystemTime};
 
     #[test]
     fn test_excluded_dotfiles_is_sorted() {
@@ -316,4 +769,260 @@ mod test {
     fn test_is_excluded_dotfile(#[case] path: &str, #[case] b: bool) {
         assert_eq!(is_excluded_dotfile(path), b);
     }
+
+    #[test]
+    fn test_zarr_eq_ignores_traversal_state() {
+        let zarr = Zarr::new("foo").exclude_dotfiles(true);
+        let observed = zarr
+            .clone()
+            .progress(NoProgress)
+            .cancel_token(CancelToken::new())
+            .deadline(Instant::now());
+        assert_eq!(zarr, observed);
+        assert_eq!(zarr.root_dir(), observed.root_dir());
+        assert_ne!(zarr, Zarr::new("foo"));
+        assert_ne!(zarr, Zarr::new("bar").exclude_dotfiles(true));
+        assert_ne!(zarr.root_dir(), Zarr::new("foo").root_dir());
+    }
+
+    fn sample_file() -> ZarrFile {
+        sample_file_with_backend(HashBackend::default())
+    }
+
+    fn sample_file_with_backend(backend: HashBackend) -> ZarrFile {
+        let zarr = Zarr::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample.zarr"
+        ))
+        .hash_backend(backend);
+        zarr.root_dir()
+            .entries()
+            .unwrap()
+            .into_iter()
+            .find_map(|entry| match entry {
+                ZarrEntry::File(zf) if zf.relpath().to_string() == ".zgroup" => Some(zf),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case(JobOrder::Lifo, None)]
+    #[case(JobOrder::Fifo, None)]
+    #[case(JobOrder::LargestFirst, Some(24))]
+    fn test_listed_size(#[case] order: JobOrder, #[case] size: Option<u64>) {
+        let zarr = Zarr::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/sample.zarr"
+        ))
+        .job_order(order);
+        let zf = zarr
+            .root_dir()
+            .entries()
+            .unwrap()
+            .into_iter()
+            .find_map(|entry| match entry {
+                ZarrEntry::File(zf) if zf.relpath().to_string() == ".zgroup" => Some(zf),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(zf.listed_size(), size);
+    }
+
+    #[test]
+    fn test_file_into_checksum() {
+        let zf = sample_file();
+        assert_eq!(metadata(zf.path()).unwrap().len(), 24);
+        let node = zf.into_checksum().unwrap();
+        assert_eq!(
+            node,
+            FileChecksum::new(
+                EntryPath::try_from(".zgroup").unwrap(),
+                "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
+                24
+            )
+        );
+    }
+
+    #[rstest]
+    #[case(HashBackend::Buffered)]
+    #[case(HashBackend::Mmap)]
+    #[case(HashBackend::Auto { threshold: 0 })]
+    #[case(HashBackend::Auto { threshold: 1000 })]
+    fn test_file_into_checksum_backend(#[case] backend: HashBackend) {
+        let node = sample_file_with_backend(backend).into_checksum().unwrap();
+        assert_eq!(
+            node,
+            FileChecksum::new(
+                EntryPath::try_from(".zgroup").unwrap(),
+                "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
+                24
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_async_into_checksum() {
+        let node = sample_file().async_into_checksum().await.unwrap();
+        assert_eq!(
+            node,
+            FileChecksum::new(
+                EntryPath::try_from(".zgroup").unwrap(),
+                "e20297935e73dd0154104d4ea53040ab".parse().unwrap(),
+                24
+            )
+        );
+    }
+
+    /// A `ProgressObserver` that sets a file's modification time to a new
+    /// value on each of the first `times` `bytes_read` events
+    #[derive(Debug)]
+    struct Modifier {
+        path: PathBuf,
+        remaining: AtomicU32,
+    }
+
+    impl ProgressObserver for Modifier {
+        fn bytes_read(&self, _n: u64) {
+            if let Ok(n) = self
+                .remaining
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            {
+                let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(u64::from(n));
+                fs_err::File::options()
+                    .write(true)
+                    .open(&self.path)
+                    .unwrap()
+                    .file()
+                    .set_modified(mtime)
+                    .unwrap();
+            }
+        }
+    }
+
+    fn modified_file(policy: ModifiedPolicy, times: u32) -> (tempfile::TempDir, ZarrFile) {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("data");
+        fs_err::write(&path, vec![b'a'; 20000]).unwrap();
+        let zarr = Zarr::new(tmpdir.path())
+            .on_modified(policy)
+            .progress(Modifier {
+                path,
+                remaining: AtomicU32::new(times),
+            });
+        let Some(ZarrEntry::File(zf)) = zarr.root_dir().entries().unwrap().pop() else {
+            panic!("Expected a single file in the directory");
+        };
+        (tmpdir, zf)
+    }
+
+    #[test]
+    fn test_modified_fail() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Fail, 1);
+        assert_matches!(zf.into_checksum(), Err(FSError::ModifiedDuringRead { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_modified_fail_async() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Fail, 1);
+        assert_matches!(
+            zf.async_into_checksum().await,
+            Err(FSError::ModifiedDuringRead { .. })
+        );
+    }
+
+    #[test]
+    fn test_modified_warn() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Warn, 1);
+        let node = zf.into_checksum().unwrap();
+        assert!(
+            node.size() >= 20000,
+            "size should include at least the original contents"
+        );
+    }
+
+    #[test]
+    fn test_modified_retry() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Retry, 1);
+        let path = zf.path().to_owned();
+        let node = zf.into_checksum().unwrap();
+        let contents = fs_err::read(path).unwrap();
+        assert_eq!(node.size(), 20000);
+        assert_eq!(node.checksum(), hex::encode(Md5::digest(contents)));
+    }
+
+    #[tokio::test]
+    async fn test_modified_retry_async() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Retry, 1);
+        let node = zf.async_into_checksum().await.unwrap();
+        assert_eq!(node.size(), 20000);
+    }
+
+    #[test]
+    fn test_modified_retry_exhausted() {
+        let (_tmpdir, zf) = modified_file(ModifiedPolicy::Retry, u32::MAX);
+        assert_matches!(zf.into_checksum(), Err(FSError::ModifiedDuringRead { .. }));
+    }
+
+    /// A `ProgressObserver` that truncates a file to half its size on the
+    /// first `bytes_read` event
+    #[derive(Debug)]
+    struct Truncator(PathBuf);
+
+    impl ProgressObserver for Truncator {
+        fn bytes_read(&self, _n: u64) {
+            let fp = fs_err::File::options().write(true).open(&self.0).unwrap();
+            let len = fp.metadata().unwrap().len();
+            if len == 4 << 20 {
+                fp.set_len(len / 2).unwrap();
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(HashBackend::Buffered)]
+    #[case(HashBackend::Mmap)]
+    fn test_truncated_fail(#[case] backend: HashBackend) {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("data");
+        fs_err::write(&path, vec![b'a'; 4 << 20]).unwrap();
+        let zarr = Zarr::new(tmpdir.path())
+            .hash_backend(backend)
+            .progress(Truncator(path));
+        let Some(ZarrEntry::File(zf)) = zarr.root_dir().entries().unwrap().pop() else {
+            panic!("Expected a single file in the directory");
+        };
+        assert_matches!(zf.into_checksum(), Err(FSError::ModifiedDuringRead { .. }));
+    }
+
+    #[test]
+    fn test_truncated_mmap_warn() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("data");
+        fs_err::write(&path, vec![b'a'; 4 << 20]).unwrap();
+        let zarr = Zarr::new(tmpdir.path())
+            .hash_backend(HashBackend::Mmap)
+            .on_modified(ModifiedPolicy::Warn)
+            .progress(Truncator(path));
+        let Some(ZarrEntry::File(zf)) = zarr.root_dir().entries().unwrap().pop() else {
+            panic!("Expected a single file in the directory");
+        };
+        assert_matches!(zf.into_checksum(), Err(FSError::ModifiedDuringRead { .. }));
+    }
+
+    #[test]
+    fn test_truncated_mmap_retry() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("data");
+        fs_err::write(&path, vec![b'a'; 4 << 20]).unwrap();
+        let zarr = Zarr::new(tmpdir.path())
+            .hash_backend(HashBackend::Mmap)
+            .on_modified(ModifiedPolicy::Retry)
+            .progress(Truncator(path));
+        let Some(ZarrEntry::File(zf)) = zarr.root_dir().entries().unwrap().pop() else {
+            panic!("Expected a single file in the directory");
+        };
+        let node = zf.into_checksum().unwrap();
+        assert_eq!(node.size(), 2 << 20);
+    }
 }
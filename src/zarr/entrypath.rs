@@ -1,18 +1,44 @@
 use crate::errors::{EntryNameError, EntryPathError};
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::path::{Component, Path};
+use std::sync::Arc;
 
 /// A normalized, nonempty, forward-slash-separated UTF-8 encoded relative path
-#[derive(Clone, Eq, Hash, PartialEq)]
-pub struct EntryPath(Vec<String>);
+///
+/// Internally, a path is stored as its final component plus a shared
+/// reference to its parent path, so that the paths of all entries in a
+/// directory share a single copy of the directory's components, and cloning a
+/// path is cheap.
+#[derive(Clone)]
+pub struct EntryPath(Arc<Segment>);
+
+struct Segment {
+    parent: Option<EntryPath>,
+    name: Box<str>,
+    /// The number of components in the path
+    depth: usize,
+}
 
 impl EntryPath {
+    fn new(parent: Option<EntryPath>, name: &str) -> EntryPath {
+        let depth = parent.as_ref().map_or(0, |p| p.0.depth) + 1;
+        EntryPath(Arc::new(Segment {
+            parent,
+            name: name.into(),
+            depth,
+        }))
+    }
+
     /// Return the basename of the path
     pub fn file_name(&self) -> &str {
-        let Some(name) = self.0.last() else {
-            unreachable!("Invariant violated: EntryPath is empty");
-        };
-        name
+        &self.0.name
+    }
+
+    /// Return the path's parent path, if it has more than one component
+    pub fn parent(&self) -> Option<&EntryPath> {
+        self.0.parent.as_ref()
     }
 
     /// Return an iterator over the parent paths of the path, starting at the
@@ -29,27 +55,93 @@ impl EntryPath {
     /// assert_eq!(parents.next(), None);
     /// ```
     pub fn parents(&self) -> Parents<'_> {
-        Parents {
-            parts: &self.0,
-            i: 0,
+        let mut ancestors = Vec::with_capacity(self.0.depth.saturating_sub(1));
+        let mut p = self.parent();
+        while let Some(ep) = p {
+            ancestors.push(ep);
+            p = ep.parent();
         }
+        Parents { ancestors }
     }
 
     pub fn join1(&self, s: &str) -> Result<EntryPath, EntryNameError> {
         if is_path_name(s) {
-            let mut parts = self.0.clone();
-            parts.push(String::from(s));
-            Ok(EntryPath(parts))
+            Ok(EntryPath::new(Some(self.clone()), s))
         } else {
             Err(EntryNameError(String::from(s)))
         }
     }
+
+    /// Return an iterator over the components of the path, from first to
+    /// last
+    fn components(&self) -> impl Iterator<Item = &str> {
+        let mut parts = Vec::with_capacity(self.0.depth);
+        let mut p = Some(self);
+        while let Some(ep) = p {
+            parts.push(ep.file_name());
+            p = ep.parent();
+        }
+        parts.into_iter().rev()
+    }
+}
+
+impl PartialEq for EntryPath {
+    fn eq(&self, other: &EntryPath) -> bool {
+        let mut a = Some(self);
+        let mut b = Some(other);
+        loop {
+            match (a, b) {
+                (None, None) => return true,
+                (Some(x), Some(y)) => {
+                    if Arc::ptr_eq(&x.0, &y.0) {
+                        return true;
+                    }
+                    if x.0.depth != y.0.depth || x.0.name != y.0.name {
+                        return false;
+                    }
+                    a = x.parent();
+                    b = y.parent();
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl Eq for EntryPath {}
+
+/// Paths are ordered component by component, which means that the entries of
+/// a directory sort together immediately after the directory itself.  Note that
+/// this differs from comparing the paths as strings when a file name contains
+/// a character that sorts before `/`, e.g., `foo/bar` sorts before `foo.txt`.
+impl Ord for EntryPath {
+    fn cmp(&self, other: &EntryPath) -> Ordering {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return Ordering::Equal;
+        }
+        self.components().cmp(other.components())
+    }
+}
+
+impl PartialOrd for EntryPath {
+    fn partial_cmp(&self, other: &EntryPath) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for EntryPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.0.depth);
+        for part in self.components() {
+            part.hash(state);
+        }
+    }
 }
 
 impl fmt::Debug for EntryPath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("\"")?;
-        for (i, part) in self.0.iter().enumerate() {
+        for (i, part) in self.components().enumerate() {
             if i > 0 {
                 f.write_str("/")?;
             }
@@ -62,7 +154,7 @@ impl fmt::Debug for EntryPath {
 
 impl fmt::Display for EntryPath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, part) in self.0.iter().enumerate() {
+        for (i, part) in self.components().enumerate() {
             if i > 0 {
                 f.write_str("/")?;
             }
@@ -76,21 +168,18 @@ impl TryFrom<&Path> for EntryPath {
     type Error = EntryPathError;
 
     fn try_from(path: &Path) -> Result<EntryPath, EntryPathError> {
-        let mut output = Vec::new();
+        let mut output: Option<EntryPath> = None;
         for c in path.components() {
             match c {
                 Component::Normal(part) => match part.to_str() {
-                    Some(s) => output.push(String::from(s)),
+                    Some(s) => output = Some(EntryPath::new(output, s)),
                     None => return Err(EntryPathError(path.into())),
                 },
                 Component::CurDir => (),
                 _ => return Err(EntryPathError(path.into())),
             }
         }
-        if output.is_empty() {
-            return Err(EntryPathError(path.into()));
-        }
-        Ok(EntryPath(output))
+        output.ok_or_else(|| EntryPathError(path.into()))
     }
 }
 
@@ -109,18 +198,16 @@ impl TryFrom<&str> for EntryPath {
 /// This struct is returned by [`EntryPath::parents()`].
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Parents<'a> {
-    parts: &'a Vec<String>,
-    i: usize,
+    // Stored from nearest to furthest, so that popping yields the shortest
+    // path first
+    ancestors: Vec<&'a EntryPath>,
 }
 
 impl Iterator for Parents<'_> {
     type Item = EntryPath;
 
     fn next(&mut self) -> Option<EntryPath> {
-        (self.i + 1 < self.parts.len()).then(|| {
-            self.i += 1;
-            EntryPath(self.parts[0..self.i].to_vec())
-        })
+        self.ancestors.pop().cloned()
     }
 }
 
@@ -143,6 +230,40 @@ mod tests {
         assert_eq!(parents.next(), None);
     }
 
+    #[test]
+    fn test_join1_shares_parent() {
+        let parent = EntryPath::try_from("foo/bar").unwrap();
+        let child = parent.join1("baz").unwrap();
+        assert_eq!(child.to_string(), "foo/bar/baz");
+        assert!(Arc::ptr_eq(&child.parent().unwrap().0, &parent.0));
+        assert_eq!(child, EntryPath::try_from("foo/bar/baz").unwrap());
+    }
+
+    #[rstest]
+    #[case("foo", "bar")]
+    #[case("foo/bar", "foo/baz")]
+    #[case("foo/bar", "foo")]
+    #[case("foo/bar", "bar")]
+    #[case("foo/bar", "quux/bar")]
+    fn test_ne(#[case] a: &str, #[case] b: &str) {
+        assert_ne!(
+            EntryPath::try_from(a).unwrap(),
+            EntryPath::try_from(b).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("foo", "foo/bar")]
+    #[case("foo/bar", "foo.txt")]
+    #[case("foo/bar", "foo/baz")]
+    #[case("foo/bar/baz", "foo/quux")]
+    #[case("abc", "abd")]
+    fn test_ord(#[case] a: &str, #[case] b: &str) {
+        let a = EntryPath::try_from(a).unwrap();
+        let b = EntryPath::try_from(b).unwrap();
+        assert!(a < b, "{a:?} should sort before {b:?}");
+    }
+
     #[test]
     fn test_parents_len_1() {
         let path = EntryPath::try_from("foo").unwrap();
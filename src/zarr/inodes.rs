@@ -0,0 +1,69 @@
+use crate::util::FileDigest;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// The digest of a single inode.  The first reader to acquire the slot's lock
+/// computes the digest and stores it; subsequent holders reuse it.
+///
+/// Synchronous and asynchronous readers wait on separate locks, as a
+/// [`std::sync::Mutex`] must not be held across an `.await`, while tokio's
+/// `blocking_lock()` panics when called from within a runtime, which the
+/// synchronous walkers may be.  The digest itself is stored in a [`OnceLock`],
+/// so it is never replaced once set, even if a synchronous and an asynchronous
+/// reader both compute it.
+#[derive(Debug, Default)]
+pub(super) struct InodeSlot {
+    digest: OnceLock<FileDigest>,
+    sync_lock: Mutex<()>,
+    async_lock: tokio::sync::Mutex<()>,
+}
+
+impl InodeSlot {
+    /// Acquire the slot's lock for a synchronous reader
+    pub(super) fn lock(&self) -> MutexGuard<'_, ()> {
+        self.sync_lock
+            .lock()
+            .expect("Mutex should not have been poisoned")
+    }
+
+    /// Acquire the slot's lock for an asynchronous reader
+    pub(super) async fn async_lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.async_lock.lock().await
+    }
+
+    /// Return the digest, if it has been computed
+    pub(super) fn get(&self) -> Option<&FileDigest> {
+        self.digest.get()
+    }
+
+    /// Store the computed digest, returning the digest that ends up stored
+    pub(super) fn set(&self, digest: FileDigest) -> &FileDigest {
+        self.digest.get_or_init(|| digest)
+    }
+}
+
+/// Cache of file digests keyed by device & inode number, shared by all
+/// entries of a single traversal so that hardlinked files are only read once
+#[derive(Debug, Default)]
+pub(super) struct InodeCache(Mutex<HashMap<(u64, u64), Arc<InodeSlot>>>);
+
+impl InodeCache {
+    /// Return the slot for the inode described by `md`, or `None` if the
+    /// inode only has one link (or if the platform does not expose inode
+    /// numbers), in which case the file should be read directly.
+    #[cfg(unix)]
+    pub(super) fn slot(&self, md: &Metadata) -> Option<Arc<InodeSlot>> {
+        use std::os::unix::fs::MetadataExt;
+        if md.nlink() < 2 {
+            return None;
+        }
+        let mut map = self.0.lock().expect("Mutex should not have been poisoned");
+        Some(Arc::clone(map.entry((md.dev(), md.ino())).or_default()))
+    }
+
+    #[cfg(not(unix))]
+    pub(super) fn slot(&self, _md: &Metadata) -> Option<Arc<InodeSlot>> {
+        None
+    }
+}
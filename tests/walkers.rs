@@ -7,10 +7,18 @@ use fs_extra::dir;
 use rstest::rstest;
 use rstest_reuse::{self, apply, template};
 use std::fs;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::available_parallelism;
+use std::time::{Duration, Instant};
 use tempfile::{tempdir, NamedTempFile, TempDir};
-use zarr_checksum_gallery::zarr::Zarr;
+use zarr_checksum_gallery::cancel::CancelToken;
+use zarr_checksum_gallery::checksum::{compile_checksum, Checksum, DirChecksum, FileChecksum};
+use zarr_checksum_gallery::generate::{generate, ShapeSpec};
+use zarr_checksum_gallery::progress::ProgressObserver;
+use zarr_checksum_gallery::zarr::{DirPath, HashBackend, JobOrder, ModifiedPolicy, Zarr};
 use zarr_checksum_gallery::*;
 
 cfg_if! {
@@ -413,6 +421,14 @@ fn test_recursive_checksum(#[case] case: Option<TestCase>) {
     }
 }
 
+#[apply(test_cases)]
+fn test_recursive_mmap_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = recursive_checksum(&case.zarr().hash_backend(HashBackend::Mmap));
+        case.check(r);
+    }
+}
+
 #[apply(test_cases)]
 fn test_breadth_first_checksum(#[case] case: Option<TestCase>) {
     if let Some(case) = case {
@@ -429,6 +445,125 @@ fn test_fastio_checksum(#[case] case: Option<TestCase>) {
     }
 }
 
+#[apply(test_cases)]
+fn test_fastio_bounded_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = fastio_bounded_checksum(
+            &case.zarr(),
+            available_parallelism().unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_fastio_checksum_job_order(
+    #[case] case: Option<TestCase>,
+    #[values(JobOrder::Fifo, JobOrder::LargestFirst)] order: JobOrder,
+) {
+    if let Some(case) = case {
+        let r = fastio_checksum(
+            &case.zarr().job_order(order),
+            available_parallelism().unwrap(),
+        );
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_fastio_bounded_checksum_job_order(
+    #[case] case: Option<TestCase>,
+    #[values(JobOrder::Fifo, JobOrder::LargestFirst)] order: JobOrder,
+) {
+    if let Some(case) = case {
+        let r = fastio_bounded_checksum(
+            &case.zarr().job_order(order),
+            available_parallelism().unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        case.check(r);
+    }
+}
+
+/// A [`ProgressObserver`] that records the order in which files are hashed
+#[derive(Clone, Debug, Default)]
+struct HashOrder(Arc<Mutex<Vec<String>>>);
+
+impl ProgressObserver for HashOrder {
+    fn file_hashed(&self, file: &FileChecksum) {
+        self.0.lock().unwrap().push(file.relpath().to_string());
+    }
+}
+
+/// `fastio_bounded_checksum()` always takes jobs in LIFO order so that it
+/// never opens more directories than it needs to
+#[test]
+fn test_fastio_bounded_ignores_job_order() {
+    let hash_order = |order| {
+        let recorder = HashOrder::default();
+        let zarr = Zarr::new(SAMPLE_ZARR_PATH)
+            .job_order(order)
+            .progress(recorder.clone());
+        let r = fastio_bounded_checksum(
+            &zarr,
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        assert_eq!(r.unwrap(), SAMPLE_CHECKSUM);
+        let files = recorder.0.lock().unwrap().clone();
+        files
+    };
+    let lifo = hash_order(JobOrder::Lifo);
+    assert_eq!(hash_order(JobOrder::Fifo), lifo);
+    assert_eq!(hash_order(JobOrder::LargestFirst), lifo);
+}
+
+#[apply(test_cases)]
+fn test_fastio_stealing_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = fastio_stealing_checksum(&case.zarr(), available_parallelism().unwrap());
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_rayon_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = rayon_checksum(&case.zarr(), available_parallelism().unwrap());
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_pipelined_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        // Treat every file as large and read it in several chunks
+        let config = PipelineConfig {
+            buffer_size: NonZeroUsize::new(16).unwrap(),
+            queue_depth: NonZeroUsize::new(2).unwrap(),
+            small_file_size: 0,
+            ..PipelineConfig::default()
+        };
+        let r = pipelined_checksum(&case.zarr(), config);
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_multibuffer_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        // Use batches smaller than the number of files so that more than one
+        // batch is hashed
+        let config = MultiBufferConfig {
+            batch_size: NonZeroUsize::new(2).unwrap(),
+            ..MultiBufferConfig::default()
+        };
+        let r = multibuffer_checksum(&case.zarr(), config);
+        case.check(r);
+    }
+}
+
 #[apply(test_cases)]
 fn test_depth_first_checksum(#[case] case: Option<TestCase>) {
     if let Some(case) = case {
@@ -446,6 +581,75 @@ async fn test_fastasync_checksum(#[case] case: Option<TestCase>) {
     }
 }
 
+#[apply(test_cases)]
+#[tokio::test]
+async fn test_offload_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = offload_checksum(
+            &case.zarr(),
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(64).unwrap(),
+        )
+        .await;
+        case.check(r);
+    }
+}
+
+/// A [`ProgressObserver`] that records whether any file data is read after
+/// `done` is set
+#[derive(Clone, Debug, Default)]
+struct ReadAfterDone {
+    done: Arc<AtomicBool>,
+    late: Arc<AtomicBool>,
+}
+
+impl ProgressObserver for ReadAfterDone {
+    fn bytes_read(&self, _n: u64) {
+        if self.done.load(Ordering::SeqCst) {
+            self.late.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// When `offload_checksum()` fails, it does not return until the files
+/// already being hashed in the blocking pool are done
+#[cfg(unix)]
+#[tokio::test]
+async fn test_offload_waits_for_hashing_after_error() {
+    let tmp_path = tempdir().unwrap();
+    let root = tmp_path.path();
+    for i in 0..4 {
+        fs::write(root.join(format!("big{i}")), vec![0u8; 16 << 20]).unwrap();
+    }
+    fs::create_dir(root.join("sub")).unwrap();
+    if fs::write(
+        root.join("sub").join(OsStr::from_bytes(b"f\xF6\xF6")),
+        "bad\n",
+    )
+    .is_err()
+    {
+        // The filesystem does not allow non-UTF-8 names; see `bad_filename()`
+        return;
+    }
+    let observer = ReadAfterDone::default();
+    let r = offload_checksum(
+        &Zarr::new(root).progress(observer.clone()),
+        NonZeroUsize::new(8).unwrap(),
+        NonZeroUsize::new(4096).unwrap(),
+    )
+    .await;
+    observer.done.store(true, Ordering::SeqCst);
+    assert_matches!(
+        r,
+        Err(ChecksumError::FSError(FSError::UndecodableName { .. }))
+    );
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(
+        !observer.late.load(Ordering::SeqCst),
+        "files were still being read after offload_checksum() returned"
+    );
+}
+
 #[apply(test_cases)]
 fn test_collapsio_arc_checksum(#[case] case: Option<TestCase>) {
     if let Some(case) = case {
@@ -454,6 +658,28 @@ fn test_collapsio_arc_checksum(#[case] case: Option<TestCase>) {
     }
 }
 
+#[apply(test_cases)]
+fn test_collapsio_arc_checksum_job_order(
+    #[case] case: Option<TestCase>,
+    #[values(JobOrder::Fifo, JobOrder::LargestFirst)] order: JobOrder,
+) {
+    if let Some(case) = case {
+        let r = collapsio_arc_checksum(
+            &case.zarr().job_order(order),
+            available_parallelism().unwrap(),
+        );
+        case.check(r);
+    }
+}
+
+#[apply(test_cases)]
+fn test_collapsio_arc_stealing_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = collapsio_arc_stealing_checksum(&case.zarr(), available_parallelism().unwrap());
+        case.check(r);
+    }
+}
+
 #[apply(test_cases)]
 fn test_collapsio_mpsc_checksum(#[case] case: Option<TestCase>) {
     if let Some(case) = case {
@@ -461,3 +687,625 @@ fn test_collapsio_mpsc_checksum(#[case] case: Option<TestCase>) {
         case.check(r);
     }
 }
+
+#[cfg(target_os = "linux")]
+#[apply(test_cases)]
+fn test_uring_checksum(#[case] case: Option<TestCase>) {
+    if let Some(case) = case {
+        let r = uring_checksum(
+            &case.zarr(),
+            available_parallelism().unwrap(),
+            NonZeroUsize::new(4).unwrap(),
+        );
+        case.check(r);
+    }
+}
+
+/// A `ProgressObserver` that appends to a file the first time that bytes are
+/// reported
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct AppendOnce {
+    path: PathBuf,
+    done: AtomicBool,
+}
+
+#[cfg(target_os = "linux")]
+impl ProgressObserver for AppendOnce {
+    fn bytes_read(&self, _n: u64) {
+        use std::io::Write;
+        if !self.done.swap(true, Ordering::AcqRel) {
+            let mut fp = fs::OpenOptions::new()
+                .append(true)
+                .open(&self.path)
+                .unwrap();
+            fp.write_all(b"more data").unwrap();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+#[case(ModifiedPolicy::Retry)]
+#[case(ModifiedPolicy::Warn)]
+#[case(ModifiedPolicy::Fail)]
+fn test_uring_modified(#[case] policy: ModifiedPolicy) {
+    let tmp_path = tempdir().unwrap();
+    let path = tmp_path.path().join("data");
+    fs::write(&path, vec![b'a'; 1 << 20]).unwrap();
+    let zarr = Zarr::new(tmp_path.path())
+        .on_modified(policy)
+        .progress(AppendOnce {
+            path,
+            done: AtomicBool::new(false),
+        });
+    let r = uring_checksum(
+        &zarr,
+        NonZeroUsize::new(1).unwrap(),
+        NonZeroUsize::new(4).unwrap(),
+    );
+    match policy {
+        ModifiedPolicy::Retry => {
+            let expected = recursive_checksum(&Zarr::new(tmp_path.path())).unwrap();
+            assert_eq!(r.unwrap(), expected);
+        }
+        ModifiedPolicy::Warn => assert!(r.is_ok()),
+        ModifiedPolicy::Fail => assert_matches!(
+            r,
+            Err(ChecksumError::FSError(FSError::ModifiedDuringRead { .. }))
+        ),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct EventCounter {
+    dirs_listed: Arc<AtomicU64>,
+    files_hashed: Arc<AtomicU64>,
+    bytes_read: Arc<AtomicU64>,
+    dirs_collapsed: Arc<AtomicU64>,
+}
+
+impl EventCounter {
+    fn counts(&self) -> (u64, u64, u64, u64) {
+        (
+            self.dirs_listed.load(Ordering::SeqCst),
+            self.files_hashed.load(Ordering::SeqCst),
+            self.bytes_read.load(Ordering::SeqCst),
+            self.dirs_collapsed.load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl ProgressObserver for EventCounter {
+    fn dir_listed(&self, _dir: &DirPath, _entries: usize) {
+        self.dirs_listed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn file_hashed(&self, _file: &FileChecksum) {
+        self.files_hashed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn bytes_read(&self, n: u64) {
+        self.bytes_read.fetch_add(n, Ordering::SeqCst);
+    }
+
+    fn dir_collapsed(&self, _dir: &DirChecksum) {
+        self.dirs_collapsed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A checksumming implementation to test against the sample Zarr
+struct Walker {
+    run: fn(&Zarr) -> Result<String, ChecksumError>,
+    /// Whether the walker reports `dir_collapsed` events
+    collapses: bool,
+    /// Whether the walker honors `Zarr::dedup_hardlinks()`
+    dedups: bool,
+}
+
+#[template]
+#[rstest]
+#[case::recursive(Walker { run: recursive_checksum, collapses: true, dedups: true })]
+#[case::breadth_first(Walker { run: breadth_first_checksum, collapses: false, dedups: true })]
+#[case::depth_first(Walker { run: depth_first_checksum, collapses: true, dedups: true })]
+#[case::fastio(Walker {
+    run: |z| fastio_checksum(z, available_parallelism().unwrap()),
+    collapses: false,
+    dedups: true,
+})]
+#[case::fastio_bounded(Walker {
+    run: |z| fastio_bounded_checksum(z, available_parallelism().unwrap(), NonZeroUsize::new(2).unwrap()),
+    collapses: true,
+    dedups: true,
+})]
+#[case::fastio_stealing(Walker {
+    run: |z| fastio_stealing_checksum(z, available_parallelism().unwrap()),
+    collapses: false,
+    dedups: true,
+})]
+#[case::collapsio_arc(Walker {
+    run: |z| collapsio_arc_checksum(z, available_parallelism().unwrap()),
+    collapses: true,
+    dedups: true,
+})]
+#[case::collapsio_arc_stealing(Walker {
+    run: |z| collapsio_arc_stealing_checksum(z, available_parallelism().unwrap()),
+    collapses: true,
+    dedups: true,
+})]
+#[case::collapsio_mpsc(Walker {
+    run: |z| collapsio_mpsc_checksum(z, available_parallelism().unwrap()),
+    collapses: true,
+    dedups: true,
+})]
+#[case::pipelined(Walker {
+    run: |z| pipelined_checksum(z, PipelineConfig::default()),
+    collapses: false,
+    dedups: false,
+})]
+#[case::pipelined_large(Walker {
+    run: |z| {
+        let config = PipelineConfig {
+            buffer_size: NonZeroUsize::new(64).unwrap(),
+            small_file_size: 0,
+            ..PipelineConfig::default()
+        };
+        pipelined_checksum(z, config)
+    },
+    collapses: false,
+    dedups: false,
+})]
+#[case::multibuffer(Walker {
+    run: |z| multibuffer_checksum(z, MultiBufferConfig::default()),
+    collapses: false,
+    dedups: false,
+})]
+#[case::multibuffer_large(Walker {
+    run: |z| {
+        let config = MultiBufferConfig {
+            small_file_size: 0,
+            ..MultiBufferConfig::default()
+        };
+        multibuffer_checksum(z, config)
+    },
+    collapses: false,
+    dedups: true,
+})]
+#[case::rayon(Walker {
+    run: |z| rayon_checksum(z, available_parallelism().unwrap()),
+    collapses: true,
+    dedups: true,
+})]
+#[case::fastasync(Walker {
+    run: |z| {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(fastasync_checksum(z, available_parallelism().unwrap()))
+    },
+    collapses: false,
+    dedups: true,
+})]
+#[case::offload(Walker {
+    run: |z| {
+        tokio::runtime::Runtime::new().unwrap().block_on(offload_checksum(
+            z,
+            NonZeroUsize::new(4).unwrap(),
+            NonZeroUsize::new(1 << 16).unwrap(),
+        ))
+    },
+    collapses: false,
+    dedups: true,
+})]
+#[cfg_attr(target_os = "linux", case::uring(Walker {
+    run: |z| uring_checksum(z, available_parallelism().unwrap(), NonZeroUsize::new(4).unwrap()),
+    collapses: false,
+    dedups: false,
+}))]
+fn all_walkers(#[case] walker: Walker) {}
+
+#[apply(all_walkers)]
+fn test_progress_events(#[case] walker: Walker) {
+    let counter = EventCounter::default();
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH).progress(counter.clone());
+    assert_eq!((walker.run)(&zarr).unwrap(), SAMPLE_CHECKSUM);
+    let collapsed = if walker.collapses { 3 } else { 0 };
+    assert_eq!(counter.counts(), (3, 5, 1516, collapsed));
+}
+
+#[apply(all_walkers)]
+fn test_cancelled(#[case] walker: Walker) {
+    let token = CancelToken::new();
+    token.cancel();
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH).cancel_token(token);
+    assert_matches!((walker.run)(&zarr), Err(ChecksumError::Cancelled));
+}
+
+#[apply(all_walkers)]
+fn test_deadline_passed(#[case] walker: Walker) {
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH).deadline(Instant::now());
+    assert_matches!((walker.run)(&zarr), Err(ChecksumError::Cancelled));
+}
+
+/// A `ProgressObserver` that cancels the traversal after the first file is
+/// checksummed
+#[derive(Clone, Debug)]
+struct CancelAfterFirstFile(CancelToken);
+
+impl ProgressObserver for CancelAfterFirstFile {
+    fn file_hashed(&self, _file: &FileChecksum) {
+        self.0.cancel();
+    }
+}
+
+#[apply(all_walkers)]
+fn test_cancelled_midway(#[case] walker: Walker) {
+    let token = CancelToken::new();
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH)
+        .progress(CancelAfterFirstFile(token.clone()))
+        .cancel_token(token);
+    // With multiple workers, the remaining files may all have been
+    // checksummed before the cancellation is noticed.
+    match (walker.run)(&zarr) {
+        Ok(s) => assert_eq!(s, SAMPLE_CHECKSUM),
+        Err(e) => assert_matches!(e, ChecksumError::Cancelled),
+    }
+}
+
+/// Create a directory tree containing three hardlinks to the same file
+#[cfg(unix)]
+fn hardlinked_tree() -> TempDir {
+    let tmp_path = tempdir().unwrap();
+    let root = tmp_path.path();
+    fs::create_dir(root.join("sub")).unwrap();
+    fs::write(root.join("a"), vec![b'a'; 1000]).unwrap();
+    fs::hard_link(root.join("a"), root.join("b")).unwrap();
+    fs::hard_link(root.join("a"), root.join("sub").join("c")).unwrap();
+    fs::write(root.join("sub").join("d"), vec![b'd'; 500]).unwrap();
+    tmp_path
+}
+
+#[cfg(unix)]
+#[apply(all_walkers)]
+fn test_dedup_hardlinks(#[case] walker: Walker) {
+    let tmp_path = hardlinked_tree();
+    let root = tmp_path.path();
+    let expected = (walker.run)(&Zarr::new(root)).unwrap();
+    assert_eq!(expected, "f28f0d83d910d7650605afe9498ecb13-4--3500");
+    let counter = EventCounter::default();
+    let zarr = Zarr::new(root)
+        .dedup_hardlinks(true)
+        .progress(counter.clone());
+    assert_eq!((walker.run)(&zarr).unwrap(), expected);
+    let (_, files_hashed, bytes_read, _) = counter.counts();
+    assert_eq!(files_hashed, 4);
+    assert_eq!(bytes_read, if walker.dedups { 1500 } else { 3500 });
+}
+
+/// The synchronous walkers may be called from within an async runtime, even
+/// when deduplicating hardlinks
+#[cfg(unix)]
+#[tokio::test]
+async fn test_dedup_hardlinks_in_runtime() {
+    let tmp_path = hardlinked_tree();
+    let zarr = Zarr::new(tmp_path.path()).dedup_hardlinks(true);
+    let expected = "f28f0d83d910d7650605afe9498ecb13-4--3500";
+    assert_eq!(recursive_checksum(&zarr).unwrap(), expected);
+    assert_eq!(depth_first_checksum(&zarr).unwrap(), expected);
+    assert_eq!(breadth_first_checksum(&zarr).unwrap(), expected);
+}
+
+#[cfg(unix)]
+#[apply(all_walkers)]
+fn test_generated_tree(#[case] walker: Walker) {
+    let tmp_path = tempdir().unwrap();
+    let root = tmp_path.path().join("zarr");
+    let spec =
+        "depth=3,fanout=3,chunks=0-12,sizes=log-uniform:0-200K,empty-dirs=4,dotfiles=6,symlinks=6"
+            .parse::<ShapeSpec>()
+            .unwrap();
+    generate(&root, &spec, 1).unwrap();
+    assert_eq!(
+        (walker.run)(&Zarr::new(&root)).unwrap(),
+        "6dd5f7ce60aee5de62da211567eb2021-207--3653912"
+    );
+    assert_eq!(
+        (walker.run)(&Zarr::new(&root).exclude_dotfiles(true)).unwrap(),
+        "b8222abe2d3af791bb7472da68c03491-199--3653663"
+    );
+}
+
+/// Generated trees with many symlinks to arrays can be traversed without
+/// running into a symlink cycle
+#[cfg(unix)]
+#[apply(all_walkers)]
+fn test_generated_tree_symlinks(#[case] walker: Walker) {
+    let spec = "depth=2,fanout=2,chunks=0-3,sizes=fixed:10,symlinks=12"
+        .parse::<ShapeSpec>()
+        .unwrap();
+    for seed in 0..8 {
+        let tmp_path = tempdir().unwrap();
+        let root = tmp_path.path().join("zarr");
+        generate(&root, &spec, seed).unwrap();
+        let zarr = Zarr::new(&root);
+        assert_eq!(
+            (walker.run)(&zarr).unwrap(),
+            recursive_checksum(&zarr).unwrap(),
+            "seed {seed}"
+        );
+    }
+}
+
+/// Property tests checking every walker against a reference implementation
+/// on random trees.  Unix-only, as the file names include characters that
+/// Windows does not allow.
+#[cfg(unix)]
+mod random_trees {
+    use super::*;
+    use md5::{Digest, Md5};
+    use proptest::prelude::{
+        any, prop, prop_assert_eq, prop_oneof, proptest, ProptestConfig, Strategy,
+    };
+    use std::collections::{BTreeMap, HashSet};
+    use std::fmt::Write;
+    use zarr_checksum_gallery::checksum::{ChecksumTree, SortedChecksumBuilder};
+    use zarr_checksum_gallery::zarr::EntryPath;
+
+    /// An entry in a randomly-generated directory tree for the property tests.
+    /// File contents are derived from `size` and `seed` rather than stored so
+    /// that shrunk failure cases stay readable.
+    #[derive(Clone, Debug)]
+    enum Node {
+        File { size: usize, seed: u8 },
+        Directory(BTreeMap<String, Node>),
+    }
+
+    /// Characters that the checksum JSON must escape, plus a few that are
+    /// merely awkward in paths
+    static ESCAPE_CHARS: &[char] = &[
+        '"', '\\', '\u{8}', '\u{c}', '\n', '\r', '\t', '\u{1}', '\u{1f}', '\u{7f}', ' ', '.', '-',
+        '~',
+    ];
+
+    /// Non-ASCII characters, including one outside the Basic Multilingual Plane
+    /// (which is escaped as a UTF-16 surrogate pair)
+    static UNICODE_CHARS: &[char] = &['é', 'ß', 'Ж', '中', '\u{2028}', '\u{feff}', '\u{1F410}'];
+
+    fn entry_name() -> impl Strategy<Value = String> {
+        let chars = prop_oneof![
+            4 => prop::char::range('a', 'z'),
+            1 => prop::sample::select(ESCAPE_CHARS),
+            1 => prop::sample::select(UNICODE_CHARS),
+            1 => any::<char>().prop_filter("not allowed in file names", |&c| c != '/' && c != '\0'),
+        ];
+        prop::collection::vec(chars, 1..8)
+            .prop_map(String::from_iter)
+            .prop_filter("reserved name", |s| s != "." && s != "..")
+    }
+
+    fn file_size() -> impl Strategy<Value = usize> {
+        // Mostly small files, with the occasional one large enough to be read in
+        // multiple blocks and to skip the small-file batching in `multibuffer`
+        prop_oneof![
+            6 => 0usize..256,
+            3 => 256usize..8192,
+            1 => 60_000usize..200_000,
+        ]
+    }
+
+    /// Drop entries whose names differ from an earlier entry's only in case so
+    /// that trees can also be written to case-insensitive filesystems
+    fn fold_case_collisions(entries: BTreeMap<String, Node>) -> BTreeMap<String, Node> {
+        let mut seen = HashSet::new();
+        entries
+            .into_iter()
+            .filter(|(name, _)| seen.insert(name.to_lowercase()))
+            .collect()
+    }
+
+    /// Strategy for the entries of the root directory of a random tree
+    fn random_tree() -> impl Strategy<Value = BTreeMap<String, Node>> {
+        let file = (file_size(), any::<u8>()).prop_map(|(size, seed)| Node::File { size, seed });
+        let node = file.prop_recursive(4, 48, 6, |inner| {
+            prop::collection::btree_map(entry_name(), inner, 0..6)
+                .prop_map(|entries| Node::Directory(fold_case_collisions(entries)))
+        });
+        prop::collection::btree_map(entry_name(), node, 0..8).prop_map(fold_case_collisions)
+    }
+
+    fn file_content(size: usize, seed: u8) -> Vec<u8> {
+        std::iter::successors(Some(seed), |b| Some(b.wrapping_mul(167).wrapping_add(13)))
+            .take(size)
+            .collect()
+    }
+
+    fn write_tree(dir: &Path, entries: &BTreeMap<String, Node>) {
+        for (name, node) in entries {
+            let path = dir.join(name);
+            match node {
+                Node::File { size, seed } => fs::write(&path, file_content(*size, *seed)).unwrap(),
+                Node::Directory(sub) => {
+                    fs::create_dir(&path).unwrap();
+                    write_tree(&path, sub);
+                }
+            }
+        }
+    }
+
+    /// Collect `FileChecksum`s for every file in a tree.  As `entries` is
+    /// iterated in name order, the files are produced in sorted order.
+    fn tree_file_checksums(
+        entries: &BTreeMap<String, Node>,
+        parent: Option<&EntryPath>,
+        out: &mut Vec<FileChecksum>,
+    ) {
+        for (name, node) in entries {
+            let relpath = match parent {
+                Some(p) => p.join1(name).unwrap(),
+                None => EntryPath::try_from(name.as_str()).unwrap(),
+            };
+            match node {
+                Node::File { size, seed } => {
+                    let digest = <[u8; 16]>::from(Md5::digest(file_content(*size, *seed)));
+                    let size = u64::try_from(*size).unwrap();
+                    out.push(FileChecksum::new(relpath, digest.into(), size));
+                }
+                Node::Directory(sub) => tree_file_checksums(sub, Some(&relpath), out),
+            }
+        }
+    }
+
+    /// A deliberately naïve implementation of the Zarr checksum algorithm that
+    /// works directly on the in-memory tree and builds each directory's JSON by
+    /// hand.  Returns the directory's checksum along with its file count and
+    /// total size.
+    fn reference_checksum(entries: &BTreeMap<String, Node>) -> (String, u64, u64) {
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        let mut file_count = 0;
+        let mut total_size = 0;
+        for (name, node) in entries {
+            match node {
+                Node::File { size, seed } => {
+                    let digest = hex::encode(Md5::digest(file_content(*size, *seed)));
+                    let size = u64::try_from(*size).unwrap();
+                    files.push(format!(
+                        r#"{{"digest":"{digest}","name":{},"size":{size}}}"#,
+                        python_json_str(name)
+                    ));
+                    file_count += 1;
+                    total_size += size;
+                }
+                Node::Directory(sub) => {
+                    let (checksum, count, size) = reference_checksum(sub);
+                    if count > 0 {
+                        directories.push(format!(
+                            r#"{{"digest":"{checksum}","name":{},"size":{size}}}"#,
+                            python_json_str(name)
+                        ));
+                        file_count += count;
+                        total_size += size;
+                    }
+                }
+            }
+        }
+        let json = format!(
+            r#"{{"directories":[{}],"files":[{}]}}"#,
+            directories.join(","),
+            files.join(",")
+        );
+        let digest = hex::encode(Md5::digest(json));
+        (
+            format!("{digest}-{file_count}--{total_size}"),
+            file_count,
+            total_size,
+        )
+    }
+
+    /// Serialize a string the way Python's `json.dumps()` does with its default
+    /// `ensure_ascii=True`
+    fn python_json_str(s: &str) -> String {
+        let mut out = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\u{8}' => out.push_str("\\b"),
+                '\u{c}' => out.push_str("\\f"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                ' '..='~' => out.push(c),
+                _ => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        write!(out, "\\u{unit:04x}").unwrap();
+                    }
+                }
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    #[apply(all_walkers)]
+    fn test_random_tree(#[case] walker: Walker) {
+        proptest!(ProptestConfig::with_cases(32), |(tree in random_tree())| {
+            let tmp_path = tempdir().unwrap();
+            write_tree(tmp_path.path(), &tree);
+            let (expected, _, _) = reference_checksum(&tree);
+            prop_assert_eq!((walker.run)(&Zarr::new(tmp_path.path())).unwrap(), expected);
+        });
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(256))]
+
+        #[test]
+        fn test_random_tree_from_files(tree in random_tree()) {
+            let (expected, _, _) = reference_checksum(&tree);
+            let mut files = Vec::new();
+            tree_file_checksums(&tree, None, &mut files);
+            prop_assert_eq!(
+                SortedChecksumBuilder::from_files(files.iter().cloned()).unwrap().into_checksum(),
+                &*expected
+            );
+            // `ChecksumTree` must not depend on the order in which files are added
+            files.reverse();
+            prop_assert_eq!(ChecksumTree::from_files(files).unwrap().into_checksum(), expected);
+        }
+    }
+}
+
+#[test]
+fn test_fastio_file_checksums() {
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH);
+    let files = fastio_file_checksums(
+        &zarr,
+        available_parallelism().unwrap(),
+        NonZeroUsize::new(1).unwrap(),
+    )
+    .collect::<Result<Vec<_>, _>>()
+    .unwrap();
+    assert_eq!(files.len(), 5);
+    assert_eq!(compile_checksum(files).unwrap(), SAMPLE_CHECKSUM);
+}
+
+#[test]
+fn test_fastio_file_checksums_early_drop() {
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH);
+    let mut iter = fastio_file_checksums(
+        &zarr,
+        available_parallelism().unwrap(),
+        NonZeroUsize::new(1).unwrap(),
+    );
+    assert_matches!(iter.next(), Some(Ok(_)));
+    // Dropping must not deadlock on workers blocked on the full buffer
+    drop(iter);
+}
+
+#[tokio::test]
+async fn test_fastasync_file_checksums() {
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH);
+    let mut stream = fastasync_file_checksums(
+        &zarr,
+        available_parallelism().unwrap(),
+        NonZeroUsize::new(1).unwrap(),
+    );
+    let mut files = Vec::new();
+    while let Some(r) = stream.recv().await {
+        files.push(r.unwrap());
+    }
+    assert_eq!(files.len(), 5);
+    assert_eq!(compile_checksum(files).unwrap(), SAMPLE_CHECKSUM);
+}
+
+#[tokio::test]
+async fn test_fastasync_file_checksums_early_drop() {
+    let zarr = Zarr::new(SAMPLE_ZARR_PATH);
+    let mut stream = fastasync_file_checksums(
+        &zarr,
+        available_parallelism().unwrap(),
+        NonZeroUsize::new(1).unwrap(),
+    );
+    assert_matches!(stream.recv().await, Some(Ok(_)));
+    drop(stream);
+}
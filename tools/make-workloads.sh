@@ -0,0 +1,37 @@
+#!/bin/bash
+# Generate a standard set of synthetic Zarrs for use with the other scripts in
+# this directory
+set -e
+
+cmd=target/release/zarr-checksum-gallery
+outdir="${1:?Usage: $0 <outdir> [seed]}"
+seed="${2:-0}"
+
+cargo build -r
+
+mkdir -p "$outdir"
+
+# Many small chunks spread over a wide tree
+"$cmd" generate --seed "$seed" \
+    --shape depth=2,fanout=10,chunks=1000,sizes=uniform:1K-16K \
+    "$outdir/small-files"
+
+# A few large chunks
+"$cmd" generate --seed "$seed" \
+    --shape depth=1,fanout=4,chunks=4,sizes=fixed:64M \
+    "$outdir/large-files"
+
+# A deep, narrow tree
+"$cmd" generate --seed "$seed" \
+    --shape depth=8,fanout=2,chunks=8,sizes=uniform:0-4K \
+    "$outdir/deep"
+
+# Mostly small chunks with a long tail of large ones
+"$cmd" generate --seed "$seed" \
+    --shape depth=2,fanout=8,chunks=50-500,sizes=log-uniform:1-256M \
+    "$outdir/skewed"
+
+# Everything that needs special handling
+"$cmd" generate --seed "$seed" \
+    --shape depth=3,fanout=4,chunks=0-32,sizes=log-uniform:0-1M,empty-dirs=20,dotfiles=20,symlinks=20 \
+    "$outdir/mixed"
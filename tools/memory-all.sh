@@ -0,0 +1,42 @@
+#!/bin/bash
+# Report the peak resident set size of each implementation when run on the
+# given Zarr.  Peak RSS is obtained from getrusage(2) via Python and is in KiB
+# on Linux (bytes on macOS).
+set -e
+
+cmd=target/release/zarr-checksum-gallery
+zarr="${1:?Usage: $0 <zarr>}"
+
+cargo build -r
+
+peak_rss() {
+    python3 -c '
+import resource, subprocess, sys
+subprocess.run(sys.argv[1:], check=True, stdout=subprocess.DEVNULL)
+print(resource.getrusage(resource.RUSAGE_CHILDREN).ru_maxrss)
+' "$@"
+}
+
+measure() {
+    name="$1"
+    shift
+    printf '%-24s %12s\n' "$name" "$(peak_rss "$@")"
+}
+
+measure breadth-first $cmd breadth-first "$zarr"
+measure collapsio-arc $cmd collapsio-arc ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure collapsio-arc-stealing $cmd collapsio-arc --work-stealing ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure collapsio-mpsc $cmd collapsio-mpsc ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure depth-first $cmd depth-first "$zarr"
+measure fastasync $cmd fastasync ${ZARR_ASYNC_THREADS:+--threads $ZARR_ASYNC_THREADS} ${ZARR_WORKERS:+--workers $ZARR_WORKERS} "$zarr"
+measure fastio $cmd fastio ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure fastio-stealing $cmd fastio --work-stealing ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure fastio-bounded $cmd fastio ${ZARR_THREADS:+--threads $ZARR_THREADS} --max-in-flight "${ZARR_MAX_IN_FLIGHT:-1024}" "$zarr"
+measure multibuffer $cmd multibuffer ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure offload $cmd offload ${ZARR_ASYNC_THREADS:+--threads $ZARR_ASYNC_THREADS} "$zarr"
+measure pipelined $cmd pipelined ${ZARR_THREADS:+--readers $ZARR_THREADS --hashers $ZARR_THREADS} "$zarr"
+measure rayon $cmd rayon ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+measure recursive $cmd recursive "$zarr"
+if [ "$(uname -s)" = Linux ]
+then measure uring $cmd uring ${ZARR_THREADS:+--threads $ZARR_THREADS} "$zarr"
+fi
@@ -1,17 +1,12 @@
 #!/bin/bash
+# Benchmark all implementations on a given Zarr.  Any further arguments are
+# passed to the `bench` subcommand, e.g., `--cache both` or `--json`.
 set -e
 
 cmd=target/release/zarr-checksum-gallery
-zarr="${1:?Usage: $0 <zarr>}"
+zarr="${1:?Usage: $0 <zarr> [<bench options>]}"
+shift
 
 cargo build -r
 
-hyperfine \
-    -w3 \
-    -n breadth-first "$cmd breadth-first $zarr" \
-    -n collapsio-arc "$cmd collapsio-arc ${ZARR_THREADS:+--threads $ZARR_THREADS} $zarr" \
-    -n collapsio-mpsc "$cmd collapsio-mpsc ${ZARR_THREADS:+--threads $ZARR_THREADS} $zarr" \
-    -n depth-first "$cmd depth-first $zarr" \
-    -n fastasync "$cmd fastasync ${ZARR_ASYNC_THREADS:+--threads $ZARR_ASYNC_THREADS} ${ZARR_WORKERS:+--workers $ZARR_WORKERS} $zarr" \
-    -n fastio "$cmd fastio ${ZARR_THREADS:+--threads $ZARR_THREADS} $zarr" \
-    -n recursive "$cmd recursive $zarr"
+"$cmd" bench ${ZARR_THREADS:+--threads $ZARR_THREADS} "$@" "$zarr"
@@ -0,0 +1,19 @@
+#!/bin/bash
+# Compare the hashing backends on a given Zarr using the recursive
+# implementation, which does no I/O of its own.  `buffered` is the default
+# backend, which reads each file through `md5_file()`.  Any further arguments
+# are passed to the `bench` subcommand, e.g., `--runs 10`.
+set -e
+
+cmd=target/release/zarr-checksum-gallery
+zarr="${1:?Usage: $0 <zarr> [<bench options>]}"
+shift
+threshold="${ZARR_MMAP_THRESHOLD:-1048576}"
+
+cargo build -r
+
+for backend in buffered mmap auto
+do
+    "$cmd" --hash-backend "$backend" --mmap-threshold "$threshold" \
+        bench -i recursive "$@" "$zarr"
+done
@@ -1,10 +1,38 @@
 #!/bin/bash
-commit1="${1:?Usage $0 <commit1> <commit2> <zarr> [<implementation>]}"
-commit2="${2:?Usage $0 <commit1> <commit2> <zarr> [<implementation>]}"
-zarrpath="${3:?Usage: $0 <commit1> <commit2> <zarr> [<implementation>]}"
+# Compare the performance of an implementation at two commits, using the
+# `bench` subcommand of the current checkout to run builds of both
+set -e
+
+usage="Usage: $0 <commit1> <commit2> <zarr> [<implementation>]"
+commit1="${1:?$usage}"
+commit2="${2:?$usage}"
+zarrpath="${3:?$usage}"
 implementation="${4:-fastio}"
-hyperfine \
-    -L commit "$commit1","$commit2" \
-    -s 'git checkout {commit} && cargo build -r' \
-    -w3 \
-    -n '{commit}' "target/release/zarr-checksum-gallery $implementation $zarrpath"
+
+cargo build -r
+tmpdir="$(mktemp -d)"
+trap 'rm -rf "$tmpdir"' EXIT
+cp target/release/zarr-checksum-gallery "$tmpdir/harness"
+
+start="$(git rev-parse --abbrev-ref HEAD)"
+if [ "$start" = HEAD ]
+then start="$(git rev-parse HEAD)"
+fi
+i=0
+for commit in "$commit1" "$commit2"
+do
+    i=$((i + 1))
+    git checkout -q "$commit"
+    cargo build -r
+    cp target/release/zarr-checksum-gallery "$tmpdir/build$i"
+done
+git checkout -q "$start"
+
+i=0
+for commit in "$commit1" "$commit2"
+do
+    i=$((i + 1))
+    echo "== $commit =="
+    "$tmpdir/harness" bench --program "$tmpdir/build$i" -i "$implementation" -w3 "$zarrpath"
+    echo
+done
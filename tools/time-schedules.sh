@@ -0,0 +1,17 @@
+#!/bin/bash
+# Compare the job orders on a given Zarr using the implementations that take
+# jobs from a shared job stack.  Any further arguments are passed to the
+# `bench` subcommand, e.g., `--threads 4 --runs 10`.
+set -e
+
+cmd=target/release/zarr-checksum-gallery
+zarr="${1:?Usage: $0 <zarr> [<bench options>]}"
+shift
+
+cargo build -r
+
+for order in lifo fifo largest-first
+do
+    echo "=== --job-order $order ==="
+    "$cmd" --job-order "$order" bench -i fastio -i collapsio-arc "$@" "$zarr"
+done